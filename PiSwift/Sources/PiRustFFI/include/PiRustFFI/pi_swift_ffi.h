@@ -8,7 +8,9 @@ extern "C" {
 #endif
 
 // Returns 0 on success and writes a newly-allocated UTF-8 string to `out_response`.
-// Returns non-zero on failure and writes a newly-allocated UTF-8 string to `out_error`.
+// Returns non-zero on failure and writes a newly-allocated UTF-8 JSON error object to `out_error`:
+// `{"kind":"provider","message":"...","status":429,"code":"...","error_type":"...",
+//   "retryable":true,"retry_after_ms":1000,"sources":["..."]}` (optional fields may be absent).
 //
 // All `const char*` inputs may be null unless otherwise documented.
 // `prompt` must be non-null and non-empty.
@@ -24,7 +26,7 @@ int32_t pi_run_prompt(
 );
 
 // Returns 0 on success and writes a newly-allocated UTF-8 JSON string to `out_transcript_json`.
-// Returns non-zero on failure and writes a newly-allocated UTF-8 string to `out_error`.
+// Returns non-zero on failure and writes a JSON error object to `out_error` (see `pi_run_prompt`).
 //
 // All `const char*` inputs may be null unless otherwise documented.
 // `prompt` must be non-null and non-empty.
@@ -39,6 +41,68 @@ int32_t pi_run_prompt_transcript_json(
     char **out_error
 );
 
+// Opaque handle used to cancel an in-flight run from another thread.
+typedef struct PiRunHandle PiRunHandle;
+
+// Creates a run handle. Free with `pi_run_handle_free` after the run using it has returned.
+PiRunHandle *pi_run_handle_new(void);
+
+// Cancels the run using `handle` (drops the provider request, kills running tools).
+// Thread-safe; may be called more than once. `handle` may be null. Called while no run is in
+// progress, it cancels the next run. Each run resets the handle when it returns, so a handle
+// can be reused after a cancelled run.
+void pi_run_handle_cancel(const PiRunHandle *handle);
+
+// Frees a run handle. `handle` may be null.
+void pi_run_handle_free(PiRunHandle *handle);
+
+// Queues a steering message for the run using `handle`: sent before the next provider call; tool calls
+// not yet started in the current step are skipped. Thread-safe. Returns 0 on success.
+int32_t pi_run_handle_steer(const PiRunHandle *handle, const char *message);
+
+// Queues a follow-up message, run as a new user turn after the current one ends. Thread-safe.
+// Returns 0 on success.
+int32_t pi_run_handle_follow_up(const PiRunHandle *handle, const char *message);
+
+// Limits the next run using `handle`; 0 leaves that budget unlimited. A run over budget fails with
+// "<kind> budget exceeded" after a `budget_exceeded` event; every run ends with a `run_end` event
+// reporting tokens, cost and time used.
+void pi_run_handle_set_budget(const PiRunHandle *handle, uint64_t max_tokens, double max_cost_usd, double max_seconds);
+
+// Returns the summary of the last run using `handle` that did not fail outright, as JSON
+// (`{"stop_reason":{"reason":"completed"},"steps":2,"tool_calls":[...],"usage":{...},"cost":{...},"budget":{...}}`),
+// or null if there is none. Free a non-null result with `pi_string_free`.
+char *pi_run_handle_summary_json(const PiRunHandle *handle);
+
+// Decides whether a tool call (JSON `{"id":...,"name":...,"arguments":{...}}`) may run.
+// Return 0 to deny, 1 to approve, 2 to approve this tool for the rest of the run.
+typedef int32_t (*pi_approval_callback)(const char *tool_call_json, void *user_data);
+
+// Sets the approver for tool calls that `<cwd>/.pi/permissions.json` marks as "ask"
+// (without one they are denied). Called from a background thread; may block. `approve` may be null.
+void pi_run_handle_set_approver(const PiRunHandle *handle, pi_approval_callback approve, void *user_data);
+
+// Receives one agent event serialized as JSON (e.g. `{"type":"text_delta","delta":"Hi"}`).
+// `event_json` is only valid for the duration of the call.
+typedef void (*pi_event_callback)(const char *event_json, void *user_data);
+
+// Like `pi_run_prompt`, but invokes `on_event` for every agent event while the run is in progress.
+// `handle` may be null; if set, the run can be cancelled with `pi_run_handle_cancel` (error kind "aborted").
+// `on_event` may be null; it is called from a background thread and `user_data` is passed through unchanged.
+int32_t pi_run_prompt_stream(
+    const char *api_key,
+    const char *base_url,
+    const char *model,
+    const char *system_prompt,
+    const char *cwd,
+    const char *prompt,
+    const PiRunHandle *handle,
+    pi_event_callback on_event,
+    void *user_data,
+    char **out_response,
+    char **out_error
+);
+
 // Frees a string allocated by this library (returned via `pi_run_prompt*`).
 void pi_string_free(char *s);
 
@@ -1,13 +1,52 @@
 import Foundation
 import PiRustFFI
 
-public struct PiSwiftError: Error, CustomStringConvertible, Sendable, Equatable {
+public struct PiSwiftError: Error, CustomStringConvertible, Sendable, Equatable, Decodable {
     public let message: String
+    /// Error category, e.g. "provider", "aborted", "budget_exceeded".
+    public var kind: String?
+    /// HTTP status of a failed provider request.
+    public var status: Int?
+    /// Provider error code and type, e.g. "rate_limit_exceeded" / "insufficient_quota".
+    public var code: String?
+    public var errorType: String?
+    /// Whether sending the same request again may succeed.
+    public var retryable: Bool = false
+    public var retryAfterMs: Int?
+    /// Underlying causes, outermost first.
+    public var sources: [String] = []
 
     public init(_ message: String) {
         self.message = message
     }
 
+    /// Decodes the JSON error object written to `out_error`; falls back to treating it as text.
+    init(ffiError json: String) {
+        let decoder = JSONDecoder()
+        decoder.keyDecodingStrategy = .convertFromSnakeCase
+        if let decoded = try? decoder.decode(PiSwiftError.self, from: Data(json.utf8)) {
+            self = decoded
+        } else {
+            self.init(json)
+        }
+    }
+
+    private enum CodingKeys: String, CodingKey {
+        case message, kind, status, code, errorType, retryable, retryAfterMs, sources
+    }
+
+    public init(from decoder: Decoder) throws {
+        let c = try decoder.container(keyedBy: CodingKeys.self)
+        message = try c.decode(String.self, forKey: .message)
+        kind = try c.decodeIfPresent(String.self, forKey: .kind)
+        status = try c.decodeIfPresent(Int.self, forKey: .status)
+        code = try c.decodeIfPresent(String.self, forKey: .code)
+        errorType = try c.decodeIfPresent(String.self, forKey: .errorType)
+        retryable = try c.decodeIfPresent(Bool.self, forKey: .retryable) ?? false
+        retryAfterMs = try c.decodeIfPresent(Int.self, forKey: .retryAfterMs)
+        sources = try c.decodeIfPresent([String].self, forKey: .sources) ?? []
+    }
+
     public var description: String { message }
 }
 
@@ -46,6 +85,28 @@ public final class PiSwiftClient: Sendable {
         }.value
     }
 
+    /// Runs the prompt and calls `onEvent` with each agent event (JSON) as it happens.
+    ///
+    /// Cancelling the calling task aborts the run (the Rust side drops the request and kills tools).
+    public func run(
+        prompt: String,
+        onEvent: @escaping @Sendable (String) -> Void
+    ) async throws -> String {
+        let handle = RunHandle()
+        return try await withTaskCancellationHandler {
+            try await Task.detached(priority: .userInitiated) { [config] in
+                try Self.runStreamingBlocking(
+                    config: config,
+                    prompt: prompt,
+                    handle: handle,
+                    onEvent: onEvent
+                )
+            }.value
+        } onCancel: {
+            handle.cancel()
+        }
+    }
+
     public func runTranscriptJSON(prompt: String) async throws -> String {
         try await Task.detached(priority: .userInitiated) { [config] in
             try Self.runTranscriptJSONBlocking(config: config, prompt: prompt)
@@ -101,7 +162,7 @@ public final class PiSwiftClient: Sendable {
         }
 
         if let outError {
-            throw PiSwiftError(String(cString: outError))
+            throw PiSwiftError(ffiError: String(cString: outError))
         }
 
         throw PiSwiftError("PiSwift: unknown error (code \(rc))")
@@ -146,7 +207,93 @@ public final class PiSwiftClient: Sendable {
         }
 
         if let outError {
-            throw PiSwiftError(String(cString: outError))
+            throw PiSwiftError(ffiError: String(cString: outError))
+        }
+
+        throw PiSwiftError("PiSwift: unknown error (code \(rc))")
+    }
+
+    private final class RunHandle: @unchecked Sendable {
+        let raw: OpaquePointer
+
+        init() {
+            raw = pi_run_handle_new()
+        }
+
+        func cancel() {
+            pi_run_handle_cancel(raw)
+        }
+
+        deinit {
+            pi_run_handle_free(raw)
+        }
+    }
+
+    private final class EventHandlerBox {
+        let handler: @Sendable (String) -> Void
+
+        init(_ handler: @escaping @Sendable (String) -> Void) {
+            self.handler = handler
+        }
+    }
+
+    private static func runStreamingBlocking(
+        config: PiSwiftConfig,
+        prompt: String,
+        handle: RunHandle,
+        onEvent: @escaping @Sendable (String) -> Void
+    ) throws -> String {
+        var outResponse: UnsafeMutablePointer<CChar>?
+        var outError: UnsafeMutablePointer<CChar>?
+
+        let box = Unmanaged.passRetained(EventHandlerBox(onEvent))
+
+        defer {
+            box.release()
+            if let outResponse { pi_string_free(outResponse) }
+            if let outError { pi_string_free(outError) }
+        }
+
+        let cwdPath = config.cwd?.path
+
+        let rc = withOptionalCString(config.apiKey) { apiKey in
+            withOptionalCString(config.baseURL) { baseURL in
+                config.model.withCString { model in
+                    withOptionalCString(config.systemPrompt) { systemPrompt in
+                        withOptionalCString(cwdPath) { cwd in
+                            prompt.withCString { promptCString in
+                                pi_run_prompt_stream(
+                                    apiKey,
+                                    baseURL,
+                                    model,
+                                    systemPrompt,
+                                    cwd,
+                                    promptCString,
+                                    handle.raw,
+                                    { eventJSON, userData in
+                                        guard let eventJSON, let userData else { return }
+                                        let box = Unmanaged<EventHandlerBox>
+                                            .fromOpaque(userData)
+                                            .takeUnretainedValue()
+                                        box.handler(String(cString: eventJSON))
+                                    },
+                                    box.toOpaque(),
+                                    &outResponse,
+                                    &outError
+                                )
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if rc == 0, let outResponse {
+            return String(cString: outResponse)
+        }
+
+        if let outError {
+            throw PiSwiftError(ffiError: String(cString: outError))
         }
 
         throw PiSwiftError("PiSwift: unknown error (code \(rc))")
@@ -3,11 +3,15 @@
 //! Filesystem-backed tools + session persistence adapter.
 
 use async_trait::async_trait;
-use pi_contracts::{NonEmptyString, PiError, SessionId, ToolSpec};
-use pi_core::{SessionStore, Tool, ToolContext, ToolResult, Transcript};
+use base64::Engine as _;
+use pi_contracts::{ChatMessage, ContentPart, NonEmptyString, PiError, ProviderFixture, SessionEntry, SessionId, ToolSpec};
+use pi_core::{BpeEncoding, BpeTokenizer, FixtureStore, PermissionPolicy, SessionStore, Tool, Tokenizers, ToolContext, ToolResult, Transcript};
 use serde::Deserialize;
 use serde_json::Value as Json;
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tokio::fs;
 
 fn schema_object(props: Json, required: &[&str]) -> Json {
@@ -52,16 +56,22 @@ impl Tool for ReadTool {
         let a: ReadArgs = serde_json::from_value(args)?;
         let p = ctx.cwd.join(a.path);
         let txt = fs::read_to_string(&p).await.map_err(PiError::from)?;
-        let out = match (a.start_line, a.end_line) {
-            (None, None) => txt,
+        let total = txt.lines().count();
+        let (out, start, end) = match (a.start_line, a.end_line) {
+            (None, None) => (txt, 1, total),
             (s, e) => {
                 let lines: Vec<&str> = txt.lines().collect();
                 let start = s.unwrap_or(1).saturating_sub(1);
                 let end = e.unwrap_or(lines.len()).min(lines.len());
-                lines.get(start..end).unwrap_or(&[]).join("\n")
+                (lines.get(start..end).unwrap_or(&[]).join("\n"), start + 1, end)
             }
         };
-        Ok(ToolResult::text(out))
+        Ok(ToolResult::text(out).with_details(serde_json::json!({
+            "path": p.display().to_string(),
+            "start_line": start,
+            "end_line": end,
+            "total_lines": total,
+        })))
     }
 }
 
@@ -96,8 +106,16 @@ impl Tool for WriteTool {
         if let Some(parent) = p.parent() {
             fs::create_dir_all(parent).await?;
         }
+        let bytes = a.content.len();
         fs::write(&p, a.content).await?;
-        Ok(ToolResult::text(format!("wrote {}", p.display())))
+        Ok(ToolResult::text(format!("wrote {}", p.display())).with_details(serde_json::json!({
+            "path": p.display().to_string(),
+            "bytes": bytes,
+        })))
+    }
+
+    fn parallel_safe(&self) -> bool {
+        false
     }
 }
 
@@ -145,8 +163,10 @@ impl Tool for EditTool {
 
     async fn execute(&self, args: Json, ctx: ToolContext) -> Result<ToolResult, PiError> {
         let a: EditArgs = serde_json::from_value(args)?;
-        let p = ctx.cwd.join(a.path);
-        let mut txt = fs::read_to_string(&p).await?;
+        let p = ctx.cwd.join(&a.path);
+        let before = fs::read_to_string(&p).await?;
+        let mut txt = before.clone();
+        let edits = a.edits.len();
         for (i, e) in a.edits.into_iter().enumerate() {
             let n = txt.matches(&e.find).count();
             if n != 1 {
@@ -154,12 +174,65 @@ impl Tool for EditTool {
             }
             txt = txt.replacen(&e.find, &e.replace, 1);
         }
+        let diff = unified_diff(&a.path, &before, &txt);
         fs::write(&p, txt).await?;
-        Ok(ToolResult::text(format!("edited {}", p.display())))
+        Ok(ToolResult::text(format!("edited {}", p.display())).with_details(serde_json::json!({
+            "path": p.display().to_string(),
+            "edits": edits,
+            "diff": diff,
+        })))
+    }
+
+    fn parallel_safe(&self) -> bool {
+        false
     }
 }
 
-/// Session store: directory of JSON transcripts.
+/// Lines of context around a diff hunk.
+const DIFF_CONTEXT: usize = 3;
+
+/// A unified diff of `before` -> `after` as one hunk spanning the changed lines (edits are usually
+/// local, so this stays readable without a full LCS diff). Empty when nothing changed.
+fn unified_diff(path: &str, before: &str, after: &str) -> String {
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    if prefix == old.len() && prefix == new.len() {
+        return String::new();
+    }
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let start = prefix.saturating_sub(DIFF_CONTEXT);
+    let trailing = suffix.min(DIFF_CONTEXT);
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+
+    let mut out = format!(
+        "--- a/{path}\n+++ b/{path}\n@@ -{},{} +{},{} @@\n",
+        start + 1,
+        old_end + trailing - start,
+        start + 1,
+        new_end + trailing - start
+    );
+    for line in &old[start..prefix] {
+        out.push_str(&format!(" {line}\n"));
+    }
+    for line in &old[prefix..old_end] {
+        out.push_str(&format!("-{line}\n"));
+    }
+    for line in &new[prefix..new_end] {
+        out.push_str(&format!("+{line}\n"));
+    }
+    for line in &old[old_end..old_end + trailing] {
+        out.push_str(&format!(" {line}\n"));
+    }
+    out
+}
+
+/// Session store: directory of JSON transcripts (arrays of [`SessionEntry`]).
 #[derive(Clone)]
 pub struct JsonDirSessionStore {
     dir: PathBuf,
@@ -175,12 +248,23 @@ impl JsonDirSessionStore {
     }
 }
 
+/// A saved transcript; sessions written before entries existed are plain message arrays.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredTranscript {
+    Entries(Transcript),
+    Messages(Vec<ChatMessage>),
+}
+
 #[async_trait]
 impl SessionStore for JsonDirSessionStore {
     async fn load(&self, id: SessionId) -> Result<Option<Transcript>, PiError> {
         let p = self.path(id);
         match fs::read_to_string(&p).await {
-            Ok(s) => Ok(Some(serde_json::from_str::<Transcript>(&s)?)),
+            Ok(s) => Ok(Some(match serde_json::from_str::<StoredTranscript>(&s)? {
+                StoredTranscript::Entries(entries) => entries,
+                StoredTranscript::Messages(messages) => SessionEntry::chain(messages),
+            })),
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
             Err(e) => Err(PiError::from(e)),
         }
@@ -194,6 +278,119 @@ impl SessionStore for JsonDirSessionStore {
     }
 }
 
+/// Stores recorded provider exchanges as `<dir>/0000.json`, `0001.json`, ... (files are
+/// overwritten by index; [`clear`](Self::clear) before recording so no stale ones are left).
+pub struct JsonDirFixtureStore {
+    dir: PathBuf,
+}
+
+impl JsonDirFixtureStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Deletes every `*.json` fixture in the directory; a missing directory is already clear.
+    pub async fn clear(&self) -> Result<(), PiError> {
+        for p in self.paths().await? {
+            fs::remove_file(p).await?;
+        }
+        Ok(())
+    }
+
+    /// `*.json` files in the directory, in name order.
+    async fn paths(&self) -> Result<Vec<PathBuf>, PiError> {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(PiError::from(e)),
+        };
+        let mut paths = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let p = entry.path();
+            if p.extension().is_some_and(|ext| ext == "json") {
+                paths.push(p);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+#[async_trait]
+impl FixtureStore for JsonDirFixtureStore {
+    /// Loads every `*.json` file in name order; a missing directory has no fixtures.
+    async fn load(&self) -> Result<Vec<ProviderFixture>, PiError> {
+        let paths = self.paths().await?;
+        let mut fixtures = Vec::with_capacity(paths.len());
+        for p in paths {
+            let s = fs::read_to_string(&p).await?;
+            fixtures.push(serde_json::from_str(&s).map_err(|e| {
+                PiError::Invalid(format!("invalid fixture {}: {e}", p.display()))
+            })?);
+        }
+        Ok(fixtures)
+    }
+
+    async fn save(&self, index: usize, fixture: &ProviderFixture) -> Result<(), PiError> {
+        fs::create_dir_all(&self.dir).await?;
+        let p = self.dir.join(format!("{index:04}.json"));
+        fs::write(p, serde_json::to_string_pretty(fixture)?).await?;
+        Ok(())
+    }
+}
+
+/// Loads a JSON [`PermissionPolicy`] (e.g. `.pi/permissions.json`); `None` if the file is missing.
+pub async fn load_permission_policy(path: &Path) -> Result<Option<PermissionPolicy>, PiError> {
+    match fs::read_to_string(path).await {
+        Ok(s) => Ok(Some(serde_json::from_str(&s)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(PiError::from(e)),
+    }
+}
+
+/// Loads every `<encoding>.tiktoken` vocab found in `dir` (e.g. `.pi/tokenizers`). Missing files
+/// are skipped; models without a vocab fall back to heuristic counting.
+pub async fn load_tokenizers(dir: &Path) -> Result<Tokenizers, PiError> {
+    let mut tokenizers = Tokenizers::new();
+    for encoding in BpeEncoding::ALL {
+        let p = dir.join(format!("{}.tiktoken", encoding.name()));
+        match fs::read_to_string(&p).await {
+            Ok(vocab) => tokenizers.insert(BpeTokenizer::from_tiktoken(encoding, &vocab)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(PiError::from(e)),
+        }
+    }
+    Ok(tokenizers)
+}
+
+/// Mime type of a file that can be attached to a message, by extension.
+pub fn attachment_mime(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "wav" => "audio/wav",
+        "mp3" => "audio/mpeg",
+        _ => return None,
+    })
+}
+
+/// Reads an image or audio file as a message attachment.
+pub async fn load_attachment(path: &Path) -> Result<ContentPart, PiError> {
+    let mime_type = attachment_mime(path)
+        .ok_or_else(|| PiError::Invalid(format!("unsupported attachment: {}", path.display())))?
+        .to_string();
+    let bytes = fs::read(path).await?;
+    let data = base64::engine::general_purpose::STANDARD.encode(bytes);
+    Ok(if mime_type.starts_with("audio/") {
+        ContentPart::Audio { mime_type, data }
+    } else {
+        ContentPart::Image { mime_type, data }
+    })
+}
+
 /// Convenience: builds the default coding-tools set.
 pub fn coding_tools() -> Vec<Arc<dyn Tool>> {
     vec![
@@ -218,7 +415,7 @@ mod tests {
         let err = tool
             .execute(
                 serde_json::json!({"path":"a.txt","edits":[{"find":"x","replace":"y"}]}),
-                ToolContext { cwd: dir.path().to_path_buf() },
+                ToolContext::new(dir.path()),
             )
             .await
             .unwrap_err();
@@ -228,4 +425,123 @@ mod tests {
             _ => panic!("expected tool error"),
         }
     }
+
+    #[tokio::test]
+    async fn edit_reports_a_diff() {
+        let dir = tempdir().unwrap();
+        let lines: Vec<String> = (1..=10).map(|i| format!("line {i}")).collect();
+        fs::write(dir.path().join("a.txt"), lines.join("\n")).await.unwrap();
+
+        let out = EditTool
+            .execute(
+                serde_json::json!({"path":"a.txt","edits":[{"find":"line 6","replace":"six\nand a half"}]}),
+                ToolContext::new(dir.path()),
+            )
+            .await
+            .unwrap();
+        let details = out.details.unwrap();
+        assert_eq!(details["edits"], 1);
+        assert_eq!(
+            details["diff"],
+            "--- a/a.txt\n+++ b/a.txt\n@@ -3,7 +3,8 @@\n line 3\n line 4\n line 5\n-line 6\n+six\n+and a half\n line 7\n line 8\n line 9\n"
+        );
+        assert_eq!(unified_diff("a.txt", "same", "same"), "");
+    }
+
+    #[tokio::test]
+    async fn permission_policy_is_optional() {
+        let dir = tempdir().unwrap();
+        let p = dir.path().join("permissions.json");
+        assert!(load_permission_policy(&p).await.unwrap().is_none());
+
+        fs::write(&p, r#"{"default":"ask","rules":[{"tool":"read","action":"allow"}]}"#)
+            .await
+            .unwrap();
+        let policy = load_permission_policy(&p).await.unwrap().unwrap();
+        assert_eq!(policy.rules.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn fixtures_load_in_recording_order() {
+        use pi_contracts::{ChatMessage, ChatRequest, ChatResponse, RequestParams};
+
+        let dir = tempdir().unwrap();
+        let store = JsonDirFixtureStore::new(dir.path().join("fixtures"));
+        assert!(store.load().await.unwrap().is_empty());
+
+        let fixture = |text: &str| ProviderFixture {
+            request: ChatRequest {
+                model: NonEmptyString::new("m").unwrap(),
+                messages: vec![ChatMessage::user(text)],
+                tools: vec![],
+                temperature: None,
+                max_tokens: None,
+                reasoning_effort: None,
+                tool_choice: None,
+                parallel_tool_calls: None,
+                response_format: None,
+                params: RequestParams::default(),
+            },
+            response: ChatResponse {
+                assistant: ChatMessage::assistant(text, vec![]),
+                usage: None,
+                cost: None,
+                finish_reason: None,
+            },
+            stream: None,
+        };
+        for i in [10, 2] {
+            store.save(i, &fixture(&i.to_string())).await.unwrap();
+        }
+        assert_eq!(store.load().await.unwrap(), [fixture("2"), fixture("10")]);
+
+        store.clear().await.unwrap();
+        store.save(0, &fixture("0")).await.unwrap();
+        assert_eq!(store.load().await.unwrap(), [fixture("0")]);
+    }
+
+    #[tokio::test]
+    async fn attachments_are_typed_by_extension() {
+        let dir = tempdir().unwrap();
+        let p = dir.path().join("shot.PNG");
+        fs::write(&p, b"\x89PNG").await.unwrap();
+        assert_eq!(
+            load_attachment(&p).await.unwrap(),
+            ContentPart::Image {
+                mime_type: "image/png".into(),
+                data: "iVBORw==".into()
+            }
+        );
+        assert!(matches!(
+            load_attachment(&dir.path().join("notes.txt")).await,
+            Err(PiError::Invalid(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sessions_round_trip_entries_and_upgrade_message_arrays() {
+        let dir = tempdir().unwrap();
+        let store = JsonDirSessionStore::new(dir.path());
+        let id = SessionId::new();
+        assert!(store.load(id.clone()).await.unwrap().is_none());
+
+        let mut tr = SessionEntry::chain([
+            ChatMessage::user("hi"),
+            ChatMessage::assistant("hello", vec![]),
+        ]);
+        tr[1].model = Some(NonEmptyString::new("gpt-test").unwrap());
+        tr[1].duration_ms = Some(12);
+        store.save(id.clone(), &tr).await.unwrap();
+        assert_eq!(store.load(id.clone()).await.unwrap().unwrap(), tr);
+
+        // Sessions saved as plain messages load as a fresh entry chain.
+        let messages = vec![ChatMessage::user("old")];
+        fs::write(store.path(id.clone()), serde_json::to_string(&messages).unwrap())
+            .await
+            .unwrap();
+        let loaded = store.load(id).await.unwrap().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].message, messages[0]);
+        assert!(loaded[0].model.is_none());
+    }
 }
@@ -16,23 +16,28 @@ use futures::{
     SinkExt, StreamExt,
 };
 use pi_contracts::{
-    ChatMessage, ChatRequest, ChatResponse, ChatStreamEvent, NonEmptyString, PiError, TokenUsage,
-    ToolCall, ToolSpec,
+    ChatMessage, ChatRequest, ChatResponse, ChatStreamEvent, ContentPart, FinishReason,
+    NonEmptyString, PiError, ProviderId, ReasoningEffort, ResponseFormat, Thinking, TokenUsage,
+    ToolCall, ToolChoice, ToolSpec,
+};
+use pi_core::{
+    CancellationToken, ChatProvider, ChatProviderStream, ChatStream, ModelCatalog, Sleeper,
 };
-use pi_core::{ChatProvider, ChatProviderStream, ChatStream};
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as Json;
 use std::{collections::BTreeMap, time::Duration};
 use tokio::task::JoinHandle;
-use tracing::debug;
+use tracing::{debug, warn};
 
 #[derive(Clone)]
 pub struct OpenAiChatProvider {
     client: reqwest::Client,
+    id: ProviderId,
     base_url: String,
     api_key: String,
     timeout: Duration,
+    models: ModelCatalog,
 }
 
 impl OpenAiChatProvider {
@@ -44,38 +49,150 @@ impl OpenAiChatProvider {
         Ok(Self::new(base_url, api_key))
     }
 
+    /// The provider id is `openai` for `api.openai.com` and the host (with any port) for other
+    /// OpenAI-compatible servers; see [`with_id`](Self::with_id).
     pub fn new(base_url: impl Into<String>, api_key: impl Into<String>) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
         Self {
             client: reqwest::Client::builder().build().expect("reqwest client"),
-            base_url: base_url.into().trim_end_matches('/').to_string(),
+            id: provider_id(&base_url),
+            base_url,
             api_key: api_key.into(),
             timeout: Duration::from_secs(120),
+            models: ModelCatalog::builtin(),
         }
     }
 
+    /// Names the provider behind the base URL (e.g. `ollama`), for transcripts and catalog lookups.
+    pub fn with_id(mut self, id: ProviderId) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Where model capabilities (see [`pi_contracts::Model::reasoning`]) are looked up; the
+    /// built-in catalog by default. Models it doesn't list are treated as non-reasoning.
+    pub fn with_models(mut self, models: ModelCatalog) -> Self {
+        self.models = models;
+        self
+    }
+
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.timeout = timeout;
         self
     }
 
+    /// The request body for `req`, fitted to the model's catalog entry.
+    fn body(&self, req: ChatRequest, stream: bool) -> Result<OpenAiChatRequest, PiError> {
+        let body = if stream {
+            OpenAiChatRequest::stream(req)?
+        } else {
+            OpenAiChatRequest::non_stream(req)?
+        };
+        let reasoning = self
+            .models
+            .find(self.id.as_str(), &body.model)
+            .is_some_and(|m| m.reasoning);
+        Ok(if reasoning {
+            body.for_reasoning_model()
+        } else {
+            body.for_plain_model()
+        })
+    }
+
     fn headers(&self) -> Result<HeaderMap, PiError> {
         let mut h = HeaderMap::new();
         h.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         let v = format!("Bearer {}", self.api_key);
         h.insert(
             AUTHORIZATION,
-            HeaderValue::from_str(&v).map_err(|e| PiError::Http(e.to_string()))?,
+            HeaderValue::from_str(&v).map_err(|e| PiError::Http(Box::new(e)))?,
         );
         Ok(h)
     }
 }
 
+/// Turns a non-success response into [`PiError::HttpStatus`], keeping the server's retry hint
+/// and the error details from an OpenAI-style `{"error": {...}}` body.
+async fn status_error(resp: reqwest::Response) -> PiError {
+    let status = resp.status();
+    let retry_after_ms = retry_after_ms(resp.headers());
+    let txt = resp.text().await.unwrap_or_default();
+    let detail = serde_json::from_str::<OpenAiErrorBody>(&txt)
+        .map(|b| b.error)
+        .unwrap_or_default();
+    let code = detail.code.and_then(|c| match c {
+        Json::String(s) => Some(s),
+        Json::Null => None,
+        other => Some(other.to_string()),
+    });
+    PiError::HttpStatus {
+        status: status.as_u16(),
+        message: format!("openai {}: {}", status, detail.message.unwrap_or(txt)),
+        code,
+        error_type: detail.kind,
+        retry_after_ms,
+    }
+}
+
+#[derive(Deserialize)]
+struct OpenAiErrorBody {
+    error: OpenAiErrorDetail,
+}
+
+#[derive(Default, Deserialize)]
+struct OpenAiErrorDetail {
+    message: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    /// Usually a string, occasionally a number.
+    code: Option<Json>,
+}
+
+/// `retry-after-ms` (sent by OpenAI) or `retry-after` in seconds; HTTP dates are ignored.
+fn retry_after_ms(headers: &HeaderMap) -> Option<u64> {
+    fn number(headers: &HeaderMap, name: &str) -> Option<f64> {
+        let v: f64 = headers.get(name)?.to_str().ok()?.trim().parse().ok()?;
+        (v.is_finite() && v >= 0.0).then_some(v)
+    }
+    number(headers, "retry-after-ms")
+        .or_else(|| number(headers, "retry-after").map(|secs| secs * 1000.0))
+        .map(|ms| ms as u64)
+}
+
+/// Tokio timer for [`pi_core::RetryProvider`] and [`pi_core::Agent::with_sleeper`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioSleeper;
+
+#[async_trait]
+impl Sleeper for TokioSleeper {
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+/// `openai` for the OpenAI API, otherwise the base URL's host and port.
+fn provider_id(base_url: &str) -> ProviderId {
+    let host = reqwest::Url::parse(base_url).ok().and_then(|u| {
+        let host = u.host_str()?;
+        Some(match u.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        })
+    });
+    match host {
+        Some(host) if host != "api.openai.com" => {
+            NonEmptyString::new(host).expect("URL hosts are non-empty")
+        }
+        _ => NonEmptyString::new("openai").expect("non-empty"),
+    }
+}
+
 #[async_trait]
 impl ChatProvider for OpenAiChatProvider {
     async fn chat(&self, req: ChatRequest) -> Result<ChatResponse, PiError> {
         let url = format!("{}/v1/chat/completions", self.base_url);
 
-        let body = OpenAiChatRequest::non_stream(req)?;
+        let body = self.body(req, false)?;
         debug!("openai request model={}", body.model);
 
         let resp = self
@@ -86,20 +203,19 @@ impl ChatProvider for OpenAiChatProvider {
             .json(&body)
             .send()
             .await
-            .map_err(|e| PiError::Http(e.to_string()))?;
+            .map_err(|e| PiError::Http(Box::new(e)))?;
 
         if !resp.status().is_success() {
-            let status = resp.status();
-            let txt = resp.text().await.unwrap_or_default();
-            return Err(PiError::Provider(format!("openai {}: {}", status, txt)));
+            return Err(status_error(resp).await);
         }
 
-        let out: OpenAiChatResponse = resp
-            .json()
-            .await
-            .map_err(|e| PiError::Http(e.to_string()))?;
+        let out: OpenAiChatResponse = resp.json().await.map_err(|e| PiError::Http(Box::new(e)))?;
         out.try_into()
     }
+
+    fn id(&self) -> Option<ProviderId> {
+        Some(self.id.clone())
+    }
 }
 
 #[async_trait]
@@ -107,7 +223,7 @@ impl ChatProviderStream for OpenAiChatProvider {
     async fn chat_stream(&self, req: ChatRequest) -> Result<ChatStream, PiError> {
         let url = format!("{}/v1/chat/completions", self.base_url);
 
-        let body = OpenAiChatRequest::stream(req)?;
+        let body = self.body(req, true)?;
         debug!("openai stream request model={}", body.model);
 
         let resp = self
@@ -118,17 +234,18 @@ impl ChatProviderStream for OpenAiChatProvider {
             .json(&body)
             .send()
             .await
-            .map_err(|e| PiError::Http(e.to_string()))?;
+            .map_err(|e| PiError::Http(Box::new(e)))?;
 
         if !resp.status().is_success() {
-            let status = resp.status();
-            let txt = resp.text().await.unwrap_or_default();
-            return Err(PiError::Provider(format!("openai {}: {}", status, txt)));
+            return Err(status_error(resp).await);
         }
 
         let (mut tx, rx) = mpsc::channel::<ChatStreamEvent>(128);
         let (res_tx, res_rx) = oneshot::channel::<Result<ChatResponse, PiError>>();
 
+        let cancel = CancellationToken::new();
+        let task_cancel = cancel.clone();
+
         let handle: JoinHandle<()> = tokio::spawn(async move {
             let mut asm = StreamAssembler::default();
 
@@ -136,7 +253,22 @@ impl ChatProviderStream for OpenAiChatProvider {
             let mut bytes = resp.bytes_stream();
 
             let mut done = false;
-            while let Some(chunk) = bytes.next().await {
+            loop {
+                let chunk = tokio::select! {
+                    c = bytes.next() => match c {
+                        Some(c) => c,
+                        None => break,
+                    },
+                    _ = task_cancel.cancelled() => {
+                        // Returning drops `bytes`, which closes the HTTP connection.
+                        let _ = tx.try_send(ChatStreamEvent::Error {
+                            reason: pi_contracts::StreamErrorReason::Aborted,
+                            message: "aborted".into(),
+                        });
+                        let _ = res_tx.send(Err(PiError::Aborted));
+                        return;
+                    }
+                };
                 let bytes = match chunk {
                     Ok(b) => b,
                     Err(e) => {
@@ -146,7 +278,7 @@ impl ChatProviderStream for OpenAiChatProvider {
                                 message: e.to_string(),
                             })
                             .await;
-                        let _ = res_tx.send(Err(PiError::Http(e.to_string())));
+                        let _ = res_tx.send(Err(PiError::Http(Box::new(e))));
                         return;
                     }
                 };
@@ -217,7 +349,11 @@ impl ChatProviderStream for OpenAiChatProvider {
                 }
             }
 
-            let _ = tx.send(ChatStreamEvent::Done).await;
+            let _ = tx
+                .send(ChatStreamEvent::Done {
+                    finish_reason: asm.finish_reason,
+                })
+                .await;
             let _ = res_tx.send(asm.finish());
         });
 
@@ -229,7 +365,7 @@ impl ChatProviderStream for OpenAiChatProvider {
                 .map_err(|_| PiError::Provider("stream dropped".into()))?
         });
 
-        Ok(ChatStream::new(rx, result))
+        Ok(ChatStream::new(rx, result).with_cancel(cancel))
     }
 }
 
@@ -241,16 +377,67 @@ struct OpenAiChatRequest {
     temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     max_tokens: Option<u32>,
+    /// Replaces `max_tokens` for reasoning models, where it also covers reasoning tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_completion_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<ReasoningEffort>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     tools: Vec<OpenAiTool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    tool_choice: Option<String>,
+    tool_choice: Option<Json>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parallel_tool_calls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<Json>,
     #[serde(skip_serializing_if = "Option::is_none")]
     stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     stream_options: Option<OpenAiStreamOptions>,
+    /// [`RequestParams::extra`](pi_contracts::RequestParams::extra); never one of
+    /// [`TYPED_FIELDS`].
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Json>,
 }
 
+/// The body keys [`OpenAiChatRequest`] sets itself. `extra` may not use them: the key would be
+/// sent twice, and which value wins would be up to the server.
+const TYPED_FIELDS: &[&str] = &[
+    "model",
+    "messages",
+    "temperature",
+    "max_tokens",
+    "max_completion_tokens",
+    "top_p",
+    "stop",
+    "seed",
+    "presence_penalty",
+    "frequency_penalty",
+    "user",
+    "metadata",
+    "reasoning_effort",
+    "tools",
+    "tool_choice",
+    "parallel_tool_calls",
+    "response_format",
+    "stream",
+    "stream_options",
+];
+
 #[derive(Debug, Serialize)]
 struct OpenAiStreamOptions {
     include_usage: bool,
@@ -259,18 +446,94 @@ struct OpenAiStreamOptions {
 impl OpenAiChatRequest {
     fn base(req: ChatRequest) -> Result<Self, PiError> {
         let tools: Vec<OpenAiTool> = req.tools.into_iter().map(OpenAiTool::from).collect();
+        // OpenAI rejects both fields on requests without tools.
+        let has_tools = !tools.is_empty();
+        let tool_choice = match req.tool_choice.unwrap_or(ToolChoice::Auto) {
+            ToolChoice::Auto => "auto".into(),
+            ToolChoice::None => "none".into(),
+            ToolChoice::Required => "required".into(),
+            ToolChoice::Tool { name } => {
+                serde_json::json!({"type": "function", "function": {"name": name}})
+            }
+        };
+        let response_format = req.response_format.map(|f| match f {
+            ResponseFormat::Text => serde_json::json!({"type": "text"}),
+            ResponseFormat::JsonObject => serde_json::json!({"type": "json_object"}),
+            ResponseFormat::JsonSchema {
+                name,
+                schema,
+                strict,
+            } => serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {"name": name, "schema": schema, "strict": strict},
+            }),
+        });
+        let params = req.params;
+        if let Some(key) = params
+            .extra
+            .keys()
+            .find(|k| TYPED_FIELDS.contains(&k.as_str()))
+        {
+            return Err(PiError::Invalid(format!(
+                "`{key}` is a typed request field; set it through its own parameter, not `extra`"
+            )));
+        }
         Ok(Self {
             model: req.model.into_string(),
-            messages: req.messages.into_iter().map(OpenAiMessage::from).collect(),
+            messages: openai_messages(req.messages),
             temperature: req.temperature,
             max_tokens: req.max_tokens,
-            tool_choice: (!tools.is_empty()).then_some("auto".into()),
+            max_completion_tokens: None,
+            top_p: params.top_p,
+            stop: params.stop,
+            seed: params.seed,
+            presence_penalty: params.presence_penalty,
+            frequency_penalty: params.frequency_penalty,
+            user: params.user,
+            metadata: params.metadata,
+            reasoning_effort: req.reasoning_effort,
+            tool_choice: has_tools.then_some(tool_choice),
+            parallel_tool_calls: req.parallel_tool_calls.filter(|_| has_tools),
+            response_format,
             tools,
             stream: None,
             stream_options: None,
+            extra: params.extra,
         })
     }
 
+    /// Fits the request to a reasoning model: `max_tokens` is sent as `max_completion_tokens`,
+    /// and the sampling parameters these models reject are dropped with a warning. `extra` is
+    /// passed through untouched.
+    fn for_reasoning_model(mut self) -> Self {
+        self.max_completion_tokens = self.max_tokens.take();
+        let dropped = [
+            ("temperature", self.temperature.take().is_some()),
+            ("top_p", self.top_p.take().is_some()),
+            ("presence_penalty", self.presence_penalty.take().is_some()),
+            ("frequency_penalty", self.frequency_penalty.take().is_some()),
+        ];
+        for (name, _) in dropped.iter().filter(|(_, was_set)| *was_set) {
+            warn!(
+                "model {} does not support `{name}`; dropping it",
+                self.model
+            );
+        }
+        self
+    }
+
+    /// Fits the request to a model without reasoning: `reasoning_effort` is dropped with a
+    /// warning, since OpenAI rejects it there.
+    fn for_plain_model(mut self) -> Self {
+        if self.reasoning_effort.take().is_some() {
+            warn!(
+                "model {} does not support `reasoning_effort`; dropping it",
+                self.model
+            );
+        }
+        self
+    }
+
     fn non_stream(req: ChatRequest) -> Result<Self, PiError> {
         Self::base(req)
     }
@@ -316,46 +579,183 @@ impl From<ToolSpec> for OpenAiTool {
 struct OpenAiMessage {
     role: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    content: Option<String>,
+    content: Option<OpenAiContent>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tool_calls: Option<Vec<OpenAiToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tool_call_id: Option<String>,
 }
 
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+enum OpenAiContent {
+    Text(String),
+    Parts(Vec<OpenAiContentPart>),
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAiContentPart {
+    Text { text: String },
+    ImageUrl { image_url: OpenAiImageUrl },
+    InputAudio { input_audio: OpenAiInputAudio },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct OpenAiImageUrl {
+    url: String,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct OpenAiInputAudio {
+    data: String,
+    /// `wav` or `mp3`.
+    format: String,
+}
+
+impl From<ContentPart> for OpenAiContentPart {
+    fn from(p: ContentPart) -> Self {
+        match p {
+            ContentPart::Text { text } => Self::Text { text },
+            ContentPart::Image { mime_type, data } => Self::ImageUrl {
+                image_url: OpenAiImageUrl {
+                    url: format!("data:{mime_type};base64,{data}"),
+                },
+            },
+            ContentPart::Audio { mime_type, data } => Self::InputAudio {
+                input_audio: OpenAiInputAudio {
+                    data,
+                    format: match mime_type.as_str() {
+                        "audio/mpeg" | "audio/mp3" => "mp3".into(),
+                        "audio/wav" | "audio/x-wav" | "audio/wave" => "wav".into(),
+                        other => other.trim_start_matches("audio/").into(),
+                    },
+                },
+            },
+        }
+    }
+}
+
+/// Maps a conversation. Tool messages only take text, so images and audio returned by a batch of
+/// tool calls are sent in one user message right after the batch's tool messages.
+fn openai_messages(messages: Vec<ChatMessage>) -> Vec<OpenAiMessage> {
+    let mut out = Vec::with_capacity(messages.len());
+    let mut images: Vec<OpenAiContentPart> = vec![];
+    for m in messages {
+        if !matches!(m, ChatMessage::Tool { .. }) && !images.is_empty() {
+            out.push(tool_images_message(std::mem::take(&mut images)));
+        }
+        if let ChatMessage::Tool {
+            tool_call_id,
+            parts,
+            ..
+        } = &m
+        {
+            let shown: Vec<_> = parts
+                .iter()
+                .filter(|p| !matches!(p, ContentPart::Text { .. }))
+                .cloned()
+                .map(OpenAiContentPart::from)
+                .collect();
+            if !shown.is_empty() {
+                images.push(OpenAiContentPart::Text {
+                    text: format!("Attachments from tool call {tool_call_id}:"),
+                });
+                images.extend(shown);
+            }
+        }
+        out.push(OpenAiMessage::from(m));
+    }
+    if !images.is_empty() {
+        out.push(tool_images_message(images));
+    }
+    out
+}
+
+fn tool_images_message(parts: Vec<OpenAiContentPart>) -> OpenAiMessage {
+    OpenAiMessage {
+        role: "user".into(),
+        content: Some(OpenAiContent::Parts(parts)),
+        tool_calls: None,
+        tool_call_id: None,
+    }
+}
+
 impl From<ChatMessage> for OpenAiMessage {
     fn from(m: ChatMessage) -> Self {
         match m {
             ChatMessage::System { content } => Self {
                 role: "system".into(),
-                content: Some(content),
+                content: Some(OpenAiContent::Text(content)),
                 tool_calls: None,
                 tool_call_id: None,
             },
-            ChatMessage::User { content } => Self {
+            ChatMessage::User { content, parts } if parts.is_empty() => Self {
                 role: "user".into(),
-                content: Some(content),
+                content: Some(OpenAiContent::Text(content)),
                 tool_calls: None,
                 tool_call_id: None,
             },
+            ChatMessage::User { content, parts } => {
+                let text = (!content.is_empty()).then_some(ContentPart::Text { text: content });
+                Self {
+                    role: "user".into(),
+                    content: Some(OpenAiContent::Parts(
+                        text.into_iter()
+                            .chain(parts)
+                            .map(OpenAiContentPart::from)
+                            .collect(),
+                    )),
+                    tool_calls: None,
+                    tool_call_id: None,
+                }
+            }
+            // Chat completions takes no reasoning back; the model re-derives it.
             ChatMessage::Assistant {
                 content,
                 tool_calls,
+                ..
             } => Self {
                 role: "assistant".into(),
-                content: (!content.is_empty()).then_some(content),
+                content: (!content.is_empty()).then_some(OpenAiContent::Text(content)),
                 tool_calls: (!tool_calls.is_empty())
                     .then_some(tool_calls.into_iter().map(OpenAiToolCall::from).collect()),
                 tool_call_id: None,
             },
+            // Chat completions has no error flag on tool results; say so in the content instead.
+            // Images are sent separately (see `openai_messages`) and `details` not at all.
             ChatMessage::Tool {
                 tool_call_id,
                 content,
-            } => Self {
-                role: "tool".into(),
-                content: Some(content),
+                is_error,
+                parts,
+                ..
+            } => {
+                let mut text = if is_error {
+                    format!("Error: {content}")
+                } else {
+                    content
+                };
+                for part in parts {
+                    if let ContentPart::Text { text: t } = part {
+                        text.push_str("\n\n");
+                        text.push_str(&t);
+                    }
+                }
+                Self {
+                    role: "tool".into(),
+                    content: Some(OpenAiContent::Text(text)),
+                    tool_calls: None,
+                    tool_call_id: Some(tool_call_id.into_string()),
+                }
+            }
+            ChatMessage::Compaction { summary, .. } => Self {
+                role: "user".into(),
+                content: Some(OpenAiContent::Text(format!(
+                    "Summary of the earlier conversation (older messages were compacted):\n\n{summary}"
+                ))),
                 tool_calls: None,
-                tool_call_id: Some(tool_call_id.into_string()),
+                tool_call_id: None,
             },
         }
     }
@@ -398,6 +798,8 @@ struct OpenAiChatResponse {
 #[derive(Debug, Deserialize)]
 struct OpenAiChoice {
     message: OpenAiMessageOut,
+    #[serde(default)]
+    finish_reason: Option<FinishReason>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -405,6 +807,10 @@ struct OpenAiMessageOut {
     role: String,
     #[serde(default)]
     content: Option<String>,
+    /// Not sent by OpenAI itself; compatible servers (DeepSeek, OpenRouter, vLLM) return reasoning
+    /// text here.
+    #[serde(default, alias = "reasoning")]
+    reasoning_content: Option<String>,
     #[serde(default)]
     tool_calls: Option<Vec<OpenAiToolCall>>,
 }
@@ -414,18 +820,48 @@ struct OpenAiUsage {
     prompt_tokens: u64,
     completion_tokens: u64,
     total_tokens: u64,
+    #[serde(default)]
+    completion_tokens_details: Option<OpenAiCompletionTokensDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiCompletionTokensDetails {
+    #[serde(default)]
+    reasoning_tokens: u64,
+}
+
+impl From<OpenAiUsage> for TokenUsage {
+    fn from(u: OpenAiUsage) -> Self {
+        Self {
+            reasoning_tokens: u
+                .completion_tokens_details
+                .map_or(0, |d| d.reasoning_tokens),
+            ..TokenUsage::new(u.prompt_tokens, u.completion_tokens, u.total_tokens)
+        }
+    }
+}
+
+fn assistant_message(content: String, tool_calls: Vec<ToolCall>, reasoning: String) -> ChatMessage {
+    ChatMessage::Assistant {
+        content,
+        tool_calls,
+        thinking: (!reasoning.is_empty()).then_some(Thinking {
+            text: reasoning,
+            signature: None,
+        }),
+    }
 }
 
 impl TryFrom<OpenAiChatResponse> for ChatResponse {
     type Error = PiError;
 
     fn try_from(r: OpenAiChatResponse) -> Result<Self, Self::Error> {
-        let m = r
+        let choice = r
             .choices
             .into_iter()
             .next()
-            .ok_or_else(|| PiError::Provider("openai: empty choices".into()))?
-            .message;
+            .ok_or_else(|| PiError::Provider("openai: empty choices".into()))?;
+        let m = choice.message;
 
         if m.role != "assistant" {
             return Err(PiError::Provider(format!(
@@ -454,15 +890,14 @@ impl TryFrom<OpenAiChatResponse> for ChatResponse {
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(ChatResponse {
-            assistant: ChatMessage::assistant(m.content.unwrap_or_default(), tool_calls),
-            usage: r.usage.map(|u| TokenUsage {
-                prompt_tokens: u.prompt_tokens,
-                completion_tokens: u.completion_tokens,
-                total_tokens: u.total_tokens,
-                cache_read_tokens: 0,
-                cache_write_tokens: 0,
-            }),
+            assistant: assistant_message(
+                m.content.unwrap_or_default(),
+                tool_calls,
+                m.reasoning_content.unwrap_or_default(),
+            ),
+            usage: r.usage.map(TokenUsage::from),
             cost: None,
+            finish_reason: choice.finish_reason,
         })
     }
 }
@@ -481,12 +916,16 @@ struct OpenAiStreamChunk {
 struct OpenAiStreamChoice {
     #[serde(default)]
     delta: OpenAiStreamDelta,
+    #[serde(default)]
+    finish_reason: Option<FinishReason>,
 }
 
 #[derive(Debug, Deserialize, Default)]
 struct OpenAiStreamDelta {
     #[serde(default)]
     content: Option<String>,
+    #[serde(default, alias = "reasoning")]
+    reasoning_content: Option<String>,
     #[serde(default)]
     tool_calls: Option<Vec<OpenAiToolCallDelta>>,
 }
@@ -520,6 +959,8 @@ struct ToolAcc {
 #[derive(Debug, Default)]
 struct StreamAssembler {
     content: String,
+    thinking: String,
+    finish_reason: Option<FinishReason>,
     tools: BTreeMap<usize, ToolAcc>,
     usage: Option<TokenUsage>,
 }
@@ -529,13 +970,7 @@ impl StreamAssembler {
         let mut out = Vec::new();
 
         if let Some(u) = chunk.usage {
-            self.usage = Some(TokenUsage {
-                prompt_tokens: u.prompt_tokens,
-                completion_tokens: u.completion_tokens,
-                total_tokens: u.total_tokens,
-                cache_read_tokens: 0,
-                cache_write_tokens: 0,
-            });
+            self.usage = Some(TokenUsage::from(u));
             out.push(ChatStreamEvent::Usage {
                 usage: self.usage.clone().unwrap(),
             });
@@ -546,6 +981,17 @@ impl StreamAssembler {
             None => return Ok(out),
         };
 
+        if choice.finish_reason.is_some() {
+            self.finish_reason = choice.finish_reason;
+        }
+
+        if let Some(s) = choice.delta.reasoning_content {
+            if !s.is_empty() {
+                self.thinking.push_str(&s);
+                out.push(ChatStreamEvent::ThinkingDelta { delta: s });
+            }
+        }
+
         if let Some(s) = choice.delta.content {
             if !s.is_empty() {
                 self.content.push_str(&s);
@@ -617,9 +1063,10 @@ impl StreamAssembler {
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(ChatResponse {
-            assistant: ChatMessage::assistant(self.content, tool_calls),
+            assistant: assistant_message(self.content, tool_calls, self.thinking),
             usage: self.usage,
             cost: None,
+            finish_reason: self.finish_reason,
         })
     }
 }
@@ -665,11 +1112,39 @@ fn next_sse_data(buf: &mut String) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pi_contracts::RequestParams;
+
+    #[test]
+    fn provider_id_comes_from_the_base_url() {
+        let id = |url: &str| OpenAiChatProvider::new(url, "k").id().unwrap().to_string();
+        assert_eq!(id("https://api.openai.com/"), "openai");
+        assert_eq!(id("http://localhost:11434"), "localhost:11434");
+        assert_eq!(id("https://openrouter.ai/api"), "openrouter.ai");
+        let ollama = OpenAiChatProvider::new("http://localhost:11434", "k")
+            .with_id(NonEmptyString::new("ollama").unwrap());
+        assert_eq!(ollama.id().unwrap().as_str(), "ollama");
+    }
+
+    #[test]
+    fn retry_after_prefers_milliseconds_and_ignores_dates() {
+        let mut h = HeaderMap::new();
+        h.insert("retry-after", HeaderValue::from_static("3"));
+        assert_eq!(retry_after_ms(&h), Some(3_000));
+        h.insert("retry-after-ms", HeaderValue::from_static("1500.5"));
+        assert_eq!(retry_after_ms(&h), Some(1_500));
+
+        let mut h = HeaderMap::new();
+        h.insert(
+            "retry-after",
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after_ms(&h), None);
+    }
 
     #[tokio::test]
     async fn parses_tool_calls_non_stream() {
         let json = serde_json::json!({
-          "choices":[{"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"echo","arguments":"{\"text\":\"hi\"}"}}]}}],
+          "choices":[{"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"echo","arguments":"{\"text\":\"hi\"}"}}]},"finish_reason":"tool_calls"}],
           "usage":{"prompt_tokens":1,"completion_tokens":2,"total_tokens":3}
         });
 
@@ -684,6 +1159,7 @@ mod tests {
         }
         assert_eq!(resp.usage.unwrap().total_tokens, 3);
         assert!(resp.cost.is_none());
+        assert_eq!(resp.finish_reason, Some(FinishReason::ToolCalls));
     }
 
     #[test]
@@ -753,6 +1229,284 @@ mod tests {
         assert_eq!(resp.usage.unwrap().total_tokens, 3);
     }
 
+    #[test]
+    fn reasoning_is_streamed_as_thinking() {
+        let mut asm = StreamAssembler::default();
+        let chunks = [
+            serde_json::json!({"choices":[{"delta":{"reasoning_content":"2+2 "}}]}),
+            serde_json::json!({"choices":[{"delta":{"reasoning":"is 4"}}]}),
+            serde_json::json!({"choices":[{"delta":{"content":"4"}, "finish_reason":"length"}]}),
+            serde_json::json!({"choices":[], "usage":{"prompt_tokens":5,"completion_tokens":9,"total_tokens":14,
+                "completion_tokens_details":{"reasoning_tokens":8}}}),
+        ];
+        let events: Vec<_> = chunks
+            .into_iter()
+            .flat_map(|c| asm.apply(serde_json::from_value(c).unwrap()).unwrap())
+            .collect();
+        assert_eq!(
+            events[..2],
+            [
+                ChatStreamEvent::ThinkingDelta {
+                    delta: "2+2 ".into()
+                },
+                ChatStreamEvent::ThinkingDelta {
+                    delta: "is 4".into()
+                }
+            ]
+        );
+
+        let resp = asm.finish().unwrap();
+        let ChatMessage::Assistant {
+            content, thinking, ..
+        } = &resp.assistant
+        else {
+            panic!("expected assistant message");
+        };
+        assert_eq!(content, "4");
+        assert_eq!(thinking.as_ref().unwrap().text, "2+2 is 4");
+        assert_eq!(resp.usage.unwrap().reasoning_tokens, 8);
+        assert_eq!(resp.finish_reason, Some(FinishReason::Length));
+
+        // Reasoning is not sent back.
+        let json = serde_json::to_value(OpenAiMessage::from(resp.assistant)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"role": "assistant", "content": "4"})
+        );
+    }
+
+    #[test]
+    fn tool_choice_is_only_sent_with_tools() {
+        let req = |tools: Vec<ToolSpec>| ChatRequest {
+            model: NonEmptyString::new("gpt-test").unwrap(),
+            messages: vec![ChatMessage::user("hi")],
+            tools,
+            temperature: None,
+            max_tokens: None,
+            reasoning_effort: None,
+            tool_choice: Some(ToolChoice::Tool {
+                name: NonEmptyString::new("read").unwrap(),
+            }),
+            parallel_tool_calls: Some(false),
+            response_format: None,
+            params: RequestParams::default(),
+        };
+        let read = ToolSpec {
+            name: NonEmptyString::new("read").unwrap(),
+            description: "read".into(),
+            parameters: serde_json::json!({"type": "object"}),
+        };
+
+        let body = serde_json::to_value(OpenAiChatRequest::base(req(vec![read])).unwrap()).unwrap();
+        assert_eq!(
+            body["tool_choice"],
+            serde_json::json!({"type": "function", "function": {"name": "read"}})
+        );
+        assert_eq!(body["parallel_tool_calls"], false);
+
+        let body = serde_json::to_value(OpenAiChatRequest::base(req(vec![])).unwrap()).unwrap();
+        assert!(body.get("tool_choice").is_none());
+        assert!(body.get("parallel_tool_calls").is_none());
+    }
+
+    #[test]
+    fn json_schema_response_format_is_nested_under_json_schema() {
+        let schema =
+            serde_json::json!({"type": "object", "properties": {"n": {"type": "integer"}}});
+        let req = ChatRequest {
+            model: NonEmptyString::new("gpt-test").unwrap(),
+            messages: vec![ChatMessage::user("count")],
+            tools: vec![],
+            temperature: None,
+            max_tokens: None,
+            reasoning_effort: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            response_format: Some(ResponseFormat::JsonSchema {
+                name: "Count".into(),
+                schema: schema.clone(),
+                strict: true,
+            }),
+            params: RequestParams::default(),
+        };
+
+        let body = serde_json::to_value(OpenAiChatRequest::base(req).unwrap()).unwrap();
+        assert_eq!(
+            body["response_format"],
+            serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {"name": "Count", "schema": schema, "strict": true},
+            })
+        );
+    }
+
+    #[test]
+    fn request_params_are_sent_minus_what_reasoning_models_reject() {
+        let req = |model: &str| ChatRequest {
+            model: NonEmptyString::new(model).unwrap(),
+            messages: vec![ChatMessage::user("hi")],
+            tools: vec![],
+            temperature: Some(0.2),
+            max_tokens: Some(100),
+            reasoning_effort: Some(ReasoningEffort::High),
+            tool_choice: None,
+            parallel_tool_calls: None,
+            response_format: None,
+            params: RequestParams {
+                top_p: Some(0.9),
+                stop: vec!["END".into()],
+                seed: Some(7),
+                frequency_penalty: Some(0.5),
+                user: Some("u-1".into()),
+                extra: serde_json::json!({"service_tier": "flex"})
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+                ..RequestParams::default()
+            },
+        };
+
+        let body = serde_json::to_value(OpenAiChatRequest::base(req("gpt-4o")).unwrap()).unwrap();
+        assert_eq!(body["top_p"], 0.9f32 as f64);
+        assert_eq!(body["stop"], serde_json::json!(["END"]));
+        assert_eq!(body["frequency_penalty"], 0.5);
+        assert_eq!(body["service_tier"], "flex");
+        assert!(body.get("presence_penalty").is_none());
+
+        // Reasoning is looked up in the catalog, not guessed from the id.
+        let provider = OpenAiChatProvider::new("https://api.openai.com", "key");
+        let body =
+            serde_json::to_value(provider.body(req("gpt-5-chat-latest"), false).unwrap()).unwrap();
+        assert_eq!(body["top_p"], 0.9f32 as f64);
+        assert_eq!(body["max_tokens"], 100);
+        assert!(body.get("reasoning_effort").is_none());
+        let body = serde_json::to_value(provider.body(req("gpt-4o"), false).unwrap()).unwrap();
+        assert!(body.get("reasoning_effort").is_none());
+
+        let body =
+            serde_json::to_value(provider.body(req("gpt-5.1-codex"), false).unwrap()).unwrap();
+        for dropped in ["temperature", "top_p", "frequency_penalty", "max_tokens"] {
+            assert!(body.get(dropped).is_none(), "{dropped} was sent");
+        }
+        assert_eq!(body["max_completion_tokens"], 100);
+        assert_eq!(body["reasoning_effort"], "high");
+        assert_eq!(body["seed"], 7);
+        assert_eq!(body["user"], "u-1");
+        assert_eq!(body["service_tier"], "flex");
+    }
+
+    #[test]
+    fn extra_cannot_override_typed_fields() {
+        let req = |extra: Json| ChatRequest {
+            model: NonEmptyString::new("gpt-test").unwrap(),
+            messages: vec![ChatMessage::user("hi")],
+            tools: vec![],
+            temperature: None,
+            max_tokens: None,
+            reasoning_effort: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            response_format: None,
+            params: RequestParams {
+                stop: vec!["END".into()],
+                user: Some("u-1".into()),
+                metadata: BTreeMap::from([("k".into(), "v".into())]),
+                extra: extra.as_object().unwrap().clone(),
+                ..RequestParams::default()
+            },
+        };
+        for key in ["temperature", "model", "stream", "tools"] {
+            let err = OpenAiChatRequest::stream(req(serde_json::json!({ key: 1 }))).unwrap_err();
+            assert!(matches!(err, PiError::Invalid(_)), "{key}: {err}");
+        }
+
+        // The keys of a built body are all listed.
+        let body =
+            OpenAiChatRequest::stream(req(serde_json::json!({"service_tier": "flex"}))).unwrap();
+        let body = serde_json::to_value(body).unwrap();
+        for key in body.as_object().unwrap().keys() {
+            assert!(
+                key == "service_tier" || TYPED_FIELDS.contains(&key.as_str()),
+                "{key} is missing from TYPED_FIELDS"
+            );
+        }
+    }
+
+    #[test]
+    fn error_tool_results_are_marked_in_content() {
+        let id = NonEmptyString::new("call_1").unwrap();
+        let m = OpenAiMessage::from(ChatMessage::tool_error(id, "unknown tool: nope"));
+        assert_eq!(m.role, "tool");
+        assert_eq!(
+            m.content,
+            Some(OpenAiContent::Text("Error: unknown tool: nope".into()))
+        );
+        assert_eq!(m.tool_call_id.as_deref(), Some("call_1"));
+    }
+
+    #[test]
+    fn tool_images_follow_the_tool_messages() {
+        let tool = |id: &str, parts| ChatMessage::Tool {
+            tool_call_id: NonEmptyString::new(id).unwrap(),
+            content: "ok".into(),
+            is_error: false,
+            details: Some(serde_json::json!({"diff": "not for the model"})),
+            parts,
+        };
+        let png = ContentPart::Image {
+            mime_type: "image/png".into(),
+            data: "AAAA".into(),
+        };
+        let messages = openai_messages(vec![
+            tool("call_1", vec![png]),
+            tool(
+                "call_2",
+                vec![ContentPart::Text {
+                    text: "more".into(),
+                }],
+            ),
+            ChatMessage::assistant("seen", vec![]),
+        ]);
+        let json = serde_json::to_value(&messages).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"role": "tool", "content": "ok", "tool_call_id": "call_1"},
+                {"role": "tool", "content": "ok\n\nmore", "tool_call_id": "call_2"},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "Attachments from tool call call_1:"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}}
+                ]},
+                {"role": "assistant", "content": "seen"}
+            ])
+        );
+    }
+
+    #[test]
+    fn user_attachments_become_content_parts() {
+        let m = OpenAiMessage::from(ChatMessage::user_with_parts(
+            "what is this?",
+            vec![
+                ContentPart::Image {
+                    mime_type: "image/jpeg".into(),
+                    data: "/9j/".into(),
+                },
+                ContentPart::Audio {
+                    mime_type: "audio/mpeg".into(),
+                    data: "SUQz".into(),
+                },
+            ],
+        ));
+        assert_eq!(
+            serde_json::to_value(&m).unwrap(),
+            serde_json::json!({"role": "user", "content": [
+                {"type": "text", "text": "what is this?"},
+                {"type": "image_url", "image_url": {"url": "data:image/jpeg;base64,/9j/"}},
+                {"type": "input_audio", "input_audio": {"data": "SUQz", "format": "mp3"}}
+            ]})
+        );
+    }
+
     #[test]
     fn next_sse_data_splits_events() {
         let mut b = "data: 1\n\nnoise\ndata: 2\r\n\r\n".to_string();
@@ -49,22 +49,40 @@ impl Tool for BashTool {
         let mut cmd = Command::new("sh");
         cmd.arg("-lc").arg(a.command);
         cmd.current_dir(ctx.cwd);
+        // Dropping the output future (timeout or abort) kills the child.
         cmd.kill_on_drop(true);
         let fut = cmd.output();
-        let out = match a.timeout_ms {
-            Some(ms) => timeout(Duration::from_millis(ms), fut)
-                .await
-                .map_err(|_| PiError::Timeout("bash timed out".into()))??,
-            None => fut.await?,
+        let run = async {
+            match a.timeout_ms {
+                Some(ms) => timeout(Duration::from_millis(ms), fut)
+                    .await
+                    .map_err(|_| PiError::Timeout("bash timed out".into()))?
+                    .map_err(PiError::from),
+                None => fut.await.map_err(PiError::from),
+            }
         };
+        let out = ctx
+            .cancel
+            .run_until_cancelled(run)
+            .await
+            .ok_or(PiError::Aborted)??;
 
         let stdout = String::from_utf8_lossy(&out.stdout).to_string();
         let stderr = String::from_utf8_lossy(&out.stderr).to_string();
         let code = out.status.code().unwrap_or(-1);
 
-        Ok(ToolResult::text(format!(
-            "exit_code: {code}\nstdout:\n{stdout}\nstderr:\n{stderr}"
-        )))
+        let text = format!("exit_code: {code}\nstdout:\n{stdout}\nstderr:\n{stderr}");
+        // A failing command is still a result worth reading, so it's flagged rather than `Err`.
+        let result = if out.status.success() {
+            ToolResult::text(text)
+        } else {
+            ToolResult::error(text)
+        };
+        Ok(result.with_details(serde_json::json!({
+            "exit_code": code,
+            "stdout_bytes": out.stdout.len(),
+            "stderr_bytes": out.stderr.len(),
+        })))
     }
 }
 
@@ -72,3 +90,39 @@ impl Tool for BashTool {
 pub fn bash_tool() -> std::sync::Arc<dyn Tool> {
     std::sync::Arc::new(BashTool)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn failing_commands_are_flagged() {
+        let ctx = ToolContext::new(std::env::temp_dir());
+        let out = BashTool
+            .execute(serde_json::json!({"command": "echo oops >&2; exit 3"}), ctx)
+            .await
+            .unwrap();
+        assert!(out.is_error);
+        assert_eq!(out.details.unwrap()["exit_code"], 3);
+        assert!(out.content.contains("stderr:\noops"));
+    }
+
+    #[tokio::test]
+    async fn abort_kills_running_command() {
+        let ctx = ToolContext::new(std::env::temp_dir());
+        let cancel = ctx.cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            cancel.cancel();
+        });
+
+        let started = Instant::now();
+        let err = BashTool
+            .execute(serde_json::json!({"command": "sleep 30"}), ctx)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, PiError::Aborted));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
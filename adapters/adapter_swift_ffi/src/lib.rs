@@ -3,17 +3,25 @@
 //! This crate provides a small C ABI surface intended to be called from Swift.
 //! The API is intentionally string-based to keep the boundary stable.
 
+use async_trait::async_trait;
 use once_cell::sync::Lazy;
-use pi_adapter_fs::coding_tools;
-use pi_adapter_openai::OpenAiChatProvider;
+use pi_adapter_fs::{coding_tools, load_permission_policy, load_tokenizers};
+use pi_adapter_openai::{OpenAiChatProvider, TokioSleeper};
 use pi_adapter_shell::bash_tool;
-use pi_contracts::{ChatMessage, NonEmptyString, PiError};
-use pi_core::{Agent, AgentConfig, ToolContext, ToolSet, Transcript};
+use pi_contracts::{
+    AgentEvent, ApiKind, ChatMessage, NonEmptyString, PiError, QueuedMessageKind, RunSummary,
+    ToolCall,
+};
+use pi_core::{
+    Agent, AgentConfig, AgentEvents, ApprovalDecision, CancellationToken, CompactionConfig, MessageQueue,
+    ModelCatalog, RetryPolicy, RetryProvider, RunBudget, ToolApprover, ToolContext, ToolSet, Transcript,
+};
 use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
 use std::path::PathBuf;
 use std::ptr;
-use std::sync::Once;
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
 
 static RT: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
     tokio::runtime::Builder::new_multi_thread()
@@ -81,43 +89,148 @@ fn clear_out(out: *mut *mut c_char) {
     }
 }
 
+/// Receives one `AgentEvent` serialized as JSON. The string is only valid for the duration of the call.
+pub type PiEventCallback = extern "C" fn(event_json: *const c_char, user_data: *mut c_void);
+
+#[derive(Clone, Copy)]
+struct EventCallback {
+    f: PiEventCallback,
+    user_data: *mut c_void,
+}
+
+// SAFETY: the caller of `pi_run_prompt_stream` guarantees that `user_data` may be used from any
+// thread for the duration of the call.
+unsafe impl Send for EventCallback {}
+unsafe impl Sync for EventCallback {}
+
+impl EventCallback {
+    fn call(&self, ev: &AgentEvent) {
+        let Ok(json) = serde_json::to_string(ev) else {
+            return;
+        };
+        let json = CString::new(json.replace('\0', "\u{FFFD}")).expect("replaced NULs above");
+        (self.f)(json.as_ptr(), self.user_data);
+    }
+}
+
+/// Decides whether a tool call (JSON) may run: `0` deny, `1` approve, `2` approve for the rest of
+/// the run. Any other value denies.
+pub type PiApprovalCallback =
+    extern "C" fn(tool_call_json: *const c_char, user_data: *mut c_void) -> i32;
+
+#[derive(Clone, Copy)]
+struct ApprovalCallback {
+    f: PiApprovalCallback,
+    user_data: *mut c_void,
+}
+
+// SAFETY: the caller of `pi_run_handle_set_approver` guarantees that `user_data` may be used from
+// any thread while the handle is alive.
+unsafe impl Send for ApprovalCallback {}
+unsafe impl Sync for ApprovalCallback {}
+
+impl ApprovalCallback {
+    fn call(&self, tool_call_json: &CStr) -> i32 {
+        (self.f)(tool_call_json.as_ptr(), self.user_data)
+    }
+}
+
+#[async_trait]
+impl ToolApprover for ApprovalCallback {
+    async fn approve(&self, call: &ToolCall) -> Result<ApprovalDecision, PiError> {
+        let json = serde_json::to_string(call)?;
+        let json = CString::new(json.replace('\0', "\u{FFFD}")).expect("replaced NULs above");
+        let cb = *self;
+        // The host typically blocks on UI here; keep it off the async workers.
+        let code = tokio::task::spawn_blocking(move || cb.call(&json))
+            .await
+            .map_err(|e| PiError::Tool(format!("approval callback failed: {e}")))?;
+        Ok(match code {
+            1 => ApprovalDecision::Approve,
+            2 => ApprovalDecision::ApproveAlways,
+            _ => ApprovalDecision::Deny,
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn run_prompt_inner(
     api_key: String,
     base_url: String,
     model: String,
     system_prompt: Option<String>,
-    cwd: PathBuf,
+    ctx: ToolContext,
     prompt: String,
-) -> Result<Transcript, PiError> {
+    on_event: Option<EventCallback>,
+    approver: Option<ApprovalCallback>,
+    queue: MessageQueue,
+    budget: RunBudget,
+) -> Result<(Transcript, Result<RunSummary, PiError>, RunSummary), PiError> {
+    let known = ModelCatalog::builtin().find("openai", &model);
+    let context_window = known.as_ref().map_or(0, |m| m.context_window);
+    let pricing = known.map(|m| m.cost).unwrap_or_default();
+    budget.check_pricing(&model, &pricing)?;
+    let tokens = load_tokenizers(&ctx.cwd.join(".pi").join("tokenizers"))
+        .await?
+        .counter(ApiKind::OpenAiCompletions, &model);
     let model = NonEmptyString::new(model)?;
-    let provider = OpenAiChatProvider::new(base_url, api_key);
+    let events = AgentEvents::new();
+    let provider = RetryProvider::new(
+        OpenAiChatProvider::new(base_url, api_key),
+        RetryPolicy::default(),
+        Arc::new(TokioSleeper),
+    )
+    .with_events(events.clone());
 
     let mut tools = coding_tools();
     tools.push(bash_tool());
 
-    let agent = Agent::new(
+    // Same project config as the CLI; `ask` rules are denied unless the host set an approver.
+    let permissions = load_permission_policy(&ctx.cwd.join(".pi").join("permissions.json"))
+        .await?
+        .unwrap_or_default();
+
+    let mut agent = Agent::new(
         provider,
-        ToolSet::new(tools),
+        ToolSet::new(tools)?,
         AgentConfig {
-            model,
             system_prompt,
-            max_steps: 32,
-            temperature: None,
-            max_tokens: None,
+            permissions,
+            compaction: CompactionConfig::for_window(context_window),
+            budget,
+            pricing,
+            ..AgentConfig::minimal(model)
         },
-    );
+    )
+    .with_token_counter(tokens)
+    .with_sleeper(Arc::new(TokioSleeper))
+    .with_message_queue(queue)
+    .with_events(events);
+    if let Some(approver) = approver {
+        agent = agent.with_approver(Arc::new(approver));
+    }
 
     let mut tr: Transcript = vec![];
-    agent
-        .run_to_end(&mut tr, &prompt, ToolContext { cwd })
-        .await?;
-    Ok(tr)
+    let (r, summary) = match on_event {
+        Some(cb) => {
+            agent.events().on_event(move |ev| cb.call(ev));
+            agent.run_stream_tracked(&mut tr, prompt.into(), ctx).await
+        }
+        None => agent.run_tracked(&mut tr, prompt.into(), ctx).await,
+    };
+    Ok((tr, r, summary))
+}
+
+/// The transcript of a run that completed; failed runs and runs that stopped early become their
+/// error.
+fn completed((tr, r, _): (Transcript, Result<RunSummary, PiError>, RunSummary)) -> Result<Transcript, PiError> {
+    r?.stop_error().map_or(Ok(tr), Err)
 }
 
 fn last_assistant_content(tr: &Transcript) -> Result<String, PiError> {
     tr.iter()
         .rev()
-        .find_map(|m| match m {
+        .find_map(|e| match &e.message {
             ChatMessage::Assistant { content, .. } => Some(content.clone()),
             _ => None,
         })
@@ -144,10 +257,176 @@ fn resolve_cwd(cwd_opt: Option<String>) -> Result<PathBuf, PiError> {
     })
 }
 
+/// `out_error` payload: [`PiError::info`] as JSON.
+fn error_json(e: &PiError) -> String {
+    serde_json::to_string(&e.info()).unwrap_or_else(|_| e.to_string())
+}
+
 fn resolve_model(model_opt: Option<String>) -> String {
     nonempty_opt(model_opt).unwrap_or_else(|| "gpt-4o-mini".into())
 }
 
+/// Opaque handle for controlling an in-flight run from another thread.
+pub struct PiRunHandle {
+    /// Cancels the current (or next) run; replaced with a fresh token when a run returns.
+    cancel: Mutex<CancellationToken>,
+    approver: Mutex<Option<ApprovalCallback>>,
+    queue: MessageQueue,
+    budget: Mutex<RunBudget>,
+    summary: Mutex<Option<RunSummary>>,
+}
+
+/// Creates a run handle. Free it with `pi_run_handle_free` once the run has returned.
+#[no_mangle]
+pub extern "C" fn pi_run_handle_new() -> *mut PiRunHandle {
+    Box::into_raw(Box::new(PiRunHandle {
+        cancel: Mutex::new(CancellationToken::new()),
+        approver: Mutex::new(None),
+        queue: MessageQueue::new(),
+        budget: Mutex::new(RunBudget::unlimited()),
+        summary: Mutex::new(None),
+    }))
+}
+
+/// Sets the callback asked about tool calls that the project's `.pi/permissions.json` marks as
+/// `ask`. Without one, such calls are denied. Pass a null `approve` to clear it.
+///
+/// # Safety
+/// - `handle` must be null or a live pointer returned by `pi_run_handle_new`.
+/// - `approve` is invoked from a background thread; `user_data` must be safe to use from that
+///   thread until the handle is freed.
+#[no_mangle]
+pub unsafe extern "C" fn pi_run_handle_set_approver(
+    handle: *const PiRunHandle,
+    approve: Option<PiApprovalCallback>,
+    user_data: *mut c_void,
+) {
+    // SAFETY: caller promises `handle` is null or live.
+    if let Some(h) = unsafe { handle.as_ref() } {
+        *h.approver.lock().unwrap() = approve.map(|f| ApprovalCallback { f, user_data });
+    }
+}
+
+/// Limits the next run using this handle. A value of 0 (or less) leaves that budget unlimited.
+/// A run that exceeds a budget fails with "<kind> budget exceeded" after emitting a
+/// `budget_exceeded` event; its summary is still available via `pi_run_handle_summary_json`.
+///
+/// # Safety
+/// - `handle` must be null or a live pointer returned by `pi_run_handle_new`.
+#[no_mangle]
+pub unsafe extern "C" fn pi_run_handle_set_budget(
+    handle: *const PiRunHandle,
+    max_tokens: u64,
+    max_cost_usd: f64,
+    max_seconds: f64,
+) {
+    // SAFETY: caller promises `handle` is null or live.
+    if let Some(h) = unsafe { handle.as_ref() } {
+        *h.budget.lock().unwrap() = RunBudget {
+            max_tokens: (max_tokens > 0).then_some(max_tokens),
+            max_cost_usd: (max_cost_usd > 0.0).then_some(max_cost_usd),
+            max_duration: Duration::try_from_secs_f64(max_seconds).ok().filter(|d| !d.is_zero()),
+        };
+    }
+}
+
+/// Returns the [`RunSummary`] of the last run using this handle (stop reason, steps, tool calls
+/// with durations, usage and cost) as JSON, including runs that failed, or null if there is none
+/// (no run yet, or the last one failed before the agent started, e.g. on a bad argument).
+///
+/// # Safety
+/// - `handle` must be null or a live pointer returned by `pi_run_handle_new`.
+/// - A non-null result must be freed via `pi_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn pi_run_handle_summary_json(handle: *const PiRunHandle) -> *mut c_char {
+    // SAFETY: caller promises `handle` is null or live.
+    let Some(h) = (unsafe { handle.as_ref() }) else {
+        return ptr::null_mut();
+    };
+    match h.summary.lock().unwrap().as_ref().map(serde_json::to_string) {
+        Some(Ok(json)) => to_c_string(json),
+        _ => ptr::null_mut(),
+    }
+}
+
+fn queue_message(
+    handle: *const PiRunHandle,
+    message: *const c_char,
+    kind: QueuedMessageKind,
+) -> i32 {
+    // SAFETY: caller promises `handle` is null or live.
+    let Some(h) = (unsafe { handle.as_ref() }) else {
+        return 1;
+    };
+    match cstr_req(message, "message") {
+        Ok(m) => {
+            h.queue.push(kind, m);
+            0
+        }
+        Err(_) => 1,
+    }
+}
+
+/// Queues a steering message for the run using this handle: it is sent before the next provider
+/// call and tool calls not yet started in the current step are skipped. Thread-safe.
+///
+/// Returns 0 on success, non-zero if `handle` is null or `message` is null/empty/not UTF-8.
+///
+/// # Safety
+/// - `handle` must be null or a live pointer returned by `pi_run_handle_new`.
+/// - `message` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn pi_run_handle_steer(
+    handle: *const PiRunHandle,
+    message: *const c_char,
+) -> i32 {
+    queue_message(handle, message, QueuedMessageKind::Steering)
+}
+
+/// Queues a follow-up message: it runs as a new user turn once the current one ends, before
+/// `pi_run_prompt_stream` returns. Thread-safe.
+///
+/// Returns 0 on success, non-zero if `handle` is null or `message` is null/empty/not UTF-8.
+///
+/// # Safety
+/// - `handle` must be null or a live pointer returned by `pi_run_handle_new`.
+/// - `message` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn pi_run_handle_follow_up(
+    handle: *const PiRunHandle,
+    message: *const c_char,
+) -> i32 {
+    queue_message(handle, message, QueuedMessageKind::FollowUp)
+}
+
+/// Cancels the run using this handle: the provider request is dropped and running tools are killed.
+/// Safe to call from any thread and more than once. Called while no run is in progress, it cancels
+/// the next run; each run that returns leaves the handle ready for another.
+///
+/// # Safety
+/// - `handle` must be null or a live pointer returned by `pi_run_handle_new`.
+#[no_mangle]
+pub unsafe extern "C" fn pi_run_handle_cancel(handle: *const PiRunHandle) {
+    // SAFETY: caller promises `handle` is null or live.
+    if let Some(h) = unsafe { handle.as_ref() } {
+        h.cancel.lock().unwrap().cancel();
+    }
+}
+
+/// Frees a run handle.
+///
+/// # Safety
+/// - `handle` must be null or a pointer returned by `pi_run_handle_new` that is not used afterwards
+///   (in particular, no run using it may still be in progress).
+#[no_mangle]
+pub unsafe extern "C" fn pi_run_handle_free(handle: *mut PiRunHandle) {
+    if handle.is_null() {
+        return;
+    }
+    // SAFETY: allocated by `Box::into_raw` in `pi_run_handle_new`.
+    drop(Box::from_raw(handle));
+}
+
 /// Frees a string allocated by this library.
 ///
 /// # Safety
@@ -164,7 +443,7 @@ pub unsafe extern "C" fn pi_string_free(s: *mut c_char) {
 
 /// Runs the agent to completion and returns the final assistant content as a UTF-8 string.
 ///
-/// Returns 0 on success. On failure returns non-zero and writes an error message to `out_error`.
+/// Returns 0 on success. On failure returns non-zero and writes a JSON error object to `out_error`.
 ///
 /// # Safety
 /// - All `*const c_char` inputs must be either null or valid pointers to NUL-terminated UTF-8 strings.
@@ -196,7 +475,7 @@ pub unsafe extern "C" fn pi_run_prompt(
         let cwd = resolve_cwd(cstr_opt(cwd)?)?;
         let prompt = cstr_req(prompt, "prompt")?;
 
-        let tr = RT.block_on(run_prompt_inner(api_key, base_url, model, system_prompt, cwd, prompt))?;
+        let tr = completed(RT.block_on(run_prompt_inner(api_key, base_url, model, system_prompt, ToolContext::new(cwd), prompt, None, None, MessageQueue::new(), RunBudget::unlimited()))?)?;
         let s = last_assistant_content(&tr)?;
         Ok(to_c_string(s))
     }));
@@ -215,11 +494,11 @@ pub unsafe extern "C" fn pi_run_prompt(
             0
         }
         Ok(Err(e)) => {
-            write_out(out_error, e.to_string());
+            write_out(out_error, error_json(&e));
             1
         }
         Err(_) => {
-            write_out(out_error, "panic across FFI boundary");
+            write_out(out_error, error_json(&PiError::Adapter("panic across FFI boundary".into())));
             2
         }
     }
@@ -227,7 +506,7 @@ pub unsafe extern "C" fn pi_run_prompt(
 
 /// Runs the agent to completion and returns the full transcript as JSON.
 ///
-/// Returns 0 on success. On failure returns non-zero and writes an error message to `out_error`.
+/// Returns 0 on success. On failure returns non-zero and writes a JSON error object to `out_error`.
 ///
 /// # Safety
 /// - All `*const c_char` inputs must be either null or valid pointers to NUL-terminated UTF-8 strings.
@@ -259,7 +538,7 @@ pub unsafe extern "C" fn pi_run_prompt_transcript_json(
         let cwd = resolve_cwd(cstr_opt(cwd)?)?;
         let prompt = cstr_req(prompt, "prompt")?;
 
-        let tr = RT.block_on(run_prompt_inner(api_key, base_url, model, system_prompt, cwd, prompt))?;
+        let tr = completed(RT.block_on(run_prompt_inner(api_key, base_url, model, system_prompt, ToolContext::new(cwd), prompt, None, None, MessageQueue::new(), RunBudget::unlimited()))?)?;
         let json = serde_json::to_string(&tr)?;
         Ok(to_c_string(json))
     }));
@@ -278,11 +557,96 @@ pub unsafe extern "C" fn pi_run_prompt_transcript_json(
             0
         }
         Ok(Err(e)) => {
-            write_out(out_error, e.to_string());
+            write_out(out_error, error_json(&e));
+            1
+        }
+        Err(_) => {
+            write_out(out_error, error_json(&PiError::Adapter("panic across FFI boundary".into())));
+            2
+        }
+    }
+}
+
+/// Like `pi_run_prompt`, but streams the run: every `AgentEvent` is passed to `on_event` as a JSON
+/// string (`{"type":"text_delta",...}`) while the agent is working. Passing a `handle` allows the run
+/// to be cancelled from another thread via `pi_run_handle_cancel`.
+///
+/// Returns 0 on success. On failure returns non-zero and writes a JSON error object to `out_error`
+/// (kind `"aborted"` when cancelled).
+///
+/// # Safety
+/// - Same requirements as `pi_run_prompt`.
+/// - `handle` must be null or a live pointer returned by `pi_run_handle_new`.
+/// - `on_event` may be null (no events). It is invoked synchronously from runtime worker threads; `user_data` must be safe to use
+///   from those threads until this function returns.
+#[no_mangle]
+pub unsafe extern "C" fn pi_run_prompt_stream(
+    api_key: *const c_char,
+    base_url: *const c_char,
+    model: *const c_char,
+    system_prompt: *const c_char,
+    cwd: *const c_char,
+    prompt: *const c_char,
+    handle: *const PiRunHandle,
+    on_event: Option<PiEventCallback>,
+    user_data: *mut c_void,
+    out_response: *mut *mut c_char,
+    out_error: *mut *mut c_char,
+) -> i32 {
+    load_dotenv_once();
+    clear_out(out_response);
+    clear_out(out_error);
+
+    let cb = on_event.map(|f| EventCallback { f, user_data });
+    // SAFETY: caller promises `handle` is null or live.
+    let h = unsafe { handle.as_ref() };
+    let cancel = h.map(|h| h.cancel.lock().unwrap().clone()).unwrap_or_default();
+    let approver = h.and_then(|h| *h.approver.lock().unwrap());
+    let queue = h.map(|h| h.queue.clone()).unwrap_or_default();
+    let budget = h.map(|h| h.budget.lock().unwrap().clone()).unwrap_or_default();
+    if let Some(h) = h {
+        *h.summary.lock().unwrap() = None;
+    }
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| -> Result<*mut c_char, PiError> {
+        let api_key = resolve_api_key(cstr_opt(api_key)?)?;
+        let base_url = resolve_base_url(cstr_opt(base_url)?);
+        let model = resolve_model(cstr_opt(model)?);
+        let system_prompt = nonempty_opt(cstr_opt(system_prompt)?);
+        let ctx = ToolContext { cancel, ..ToolContext::new(resolve_cwd(cstr_opt(cwd)?)?) };
+        let prompt = cstr_req(prompt, "prompt")?;
+
+        let run = RT.block_on(run_prompt_inner(api_key, base_url, model, system_prompt, ctx, prompt, cb, approver, queue, budget))?;
+        if let Some(h) = h {
+            *h.summary.lock().unwrap() = Some(run.2.clone());
+        }
+        let tr = completed(run)?;
+        let s = last_assistant_content(&tr)?;
+        Ok(to_c_string(s))
+    }));
+    // Cancellation is permanent; a fresh token keeps the handle usable for the next run.
+    if let Some(h) = h {
+        *h.cancel.lock().unwrap() = CancellationToken::new();
+    }
+
+    match r {
+        Ok(Ok(s)) => {
+            if !out_response.is_null() {
+                // SAFETY: `out_response` points to a `char*` slot.
+                unsafe {
+                    *out_response = s;
+                }
+            } else {
+                // SAFETY: `s` was allocated in this library.
+                unsafe { pi_string_free(s) };
+            }
+            0
+        }
+        Ok(Err(e)) => {
+            write_out(out_error, error_json(&e));
             1
         }
         Err(_) => {
-            write_out(out_error, "panic across FFI boundary");
+            write_out(out_error, error_json(&PiError::Adapter("panic across FFI boundary".into())));
             2
         }
     }
@@ -1,15 +1,33 @@
 #![forbid(unsafe_code)]
 
+mod rpc;
+
+use async_trait::async_trait;
 use clap::Parser;
-use pi_adapter_fs::JsonDirSessionStore;
-use pi_adapter_openai::OpenAiChatProvider;
+use pi_adapter_fs::{
+    attachment_mime, load_attachment, load_permission_policy, load_tokenizers, JsonDirFixtureStore,
+    JsonDirSessionStore,
+};
+use pi_adapter_openai::{OpenAiChatProvider, TokioSleeper};
 use pi_adapter_shell::bash_tool;
-use pi_contracts::{ChatMessage, NonEmptyString, PiError, SessionId};
-use pi_core::{Agent, AgentConfig, SessionStore, ToolContext, ToolSet};
+use pi_contracts::{
+    AgentEvent, ApiKind, BudgetUsage, ChatMessage, NonEmptyString, PiError, QueuedMessageKind,
+    ReasoningEffort, RunSummary, SessionId, StopReason, ToolCall,
+};
+use pi_core::{
+    Agent, AgentConfig, AgentEvents, AiProvider, ApprovalDecision, CancellationToken, CompactionConfig,
+    ModelCatalog, RecordingProvider, ReplayProvider, RetryPolicy, RetryProvider, RunBudget, SessionStore, ToolApprover, ToolContext, ToolSet, Transcript, UserInput,
+};
 use std::{
-    io::{self, Write},
+    io::{self, BufRead, Write},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
+use tokio::sync::{mpsc, oneshot};
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser, Debug)]
@@ -30,6 +48,42 @@ struct Args {
     /// System prompt.
     #[arg(long)]
     system: Option<String>,
+
+    /// Serve JSON-lines RPC on stdin/stdout instead of the interactive prompt.
+    #[arg(long, conflicts_with = "prompt")]
+    rpc: bool,
+
+    /// Provider attempts per request, including retries of rate limits and server errors.
+    #[arg(long, default_value_t = 4)]
+    max_attempts: u32,
+
+    /// Stop a run once it has used this many tokens.
+    #[arg(long, value_name = "TOKENS")]
+    max_run_tokens: Option<u64>,
+
+    /// Stop a run once its estimated cost reaches this many USD.
+    #[arg(long, value_name = "USD")]
+    max_run_cost: Option<f64>,
+
+    /// Stop a run after this many seconds (checked between steps).
+    #[arg(long, value_name = "SECONDS")]
+    max_run_secs: Option<u64>,
+
+    /// Save every provider request and response as a fixture in this directory.
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Answer from fixtures recorded with `--record` instead of calling the provider.
+    #[arg(long, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    /// Reasoning effort for reasoning models: minimal, low, medium or high.
+    #[arg(long, value_name = "EFFORT")]
+    reasoning_effort: Option<ReasoningEffort>,
+
+    /// Print the model's reasoning as it streams (when the provider returns it).
+    #[arg(long)]
+    show_thinking: bool,
 }
 
 fn pi_dir(cwd: &Path) -> PathBuf {
@@ -53,26 +107,210 @@ async fn load_or_create_session_id(cwd: &Path) -> Result<SessionId, PiError> {
     Ok(id)
 }
 
-fn print_new_messages(tr: &[ChatMessage], from_idx: usize) {
-    for m in &tr[from_idx..] {
-        match m {
-            ChatMessage::Assistant { content, tool_calls } => {
-                if !content.trim().is_empty() {
-                    println!("\nassistant> {content}");
+/// Reads stdin lines on a dedicated thread so they can be awaited alongside a run.
+fn stdin_lines() -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Where the next stdin line goes while an approval prompt is waiting.
+type PendingAnswer = Arc<Mutex<Option<oneshot::Sender<String>>>>;
+
+/// Asks on the terminal before running tool calls the permission policy marks as `ask`.
+struct StdinApprover {
+    answer: PendingAnswer,
+}
+
+#[async_trait]
+impl ToolApprover for StdinApprover {
+    async fn approve(&self, call: &ToolCall) -> Result<ApprovalDecision, PiError> {
+        let (tx, rx) = oneshot::channel();
+        *self.answer.lock().unwrap() = Some(tx);
+        print!("\nallow {} {}? [y]es/[n]o/[a]lways: ", call.name, call.arguments);
+        io::stdout().flush().ok();
+        // A closed stdin drops the sender, which denies.
+        let answer = rx.await.unwrap_or_default();
+        Ok(match answer.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => ApprovalDecision::Approve,
+            "a" | "always" => ApprovalDecision::ApproveAlways,
+            _ => ApprovalDecision::Deny,
+        })
+    }
+}
+
+/// E.g. `1234/5000 tokens, $0.0123, 4.2s/60s`; limits are shown only when set.
+fn format_usage(u: &BudgetUsage) -> String {
+    let limit = |max: Option<String>| max.map(|m| format!("/{m}")).unwrap_or_default();
+    format!(
+        "{}{} tokens, ${:.4}{}, {:.1}s{}",
+        u.tokens,
+        limit(u.max_tokens.map(|m| m.to_string())),
+        u.cost_usd,
+        limit(u.max_cost_usd.map(|m| format!("${m:.4}"))),
+        u.elapsed_ms as f64 / 1000.0,
+        limit(u.max_elapsed_ms.map(|m| format!("{:.0}s", m as f64 / 1000.0))),
+    )
+}
+
+/// E.g. `completed after 3 steps, 2 tool calls (1 failed); used 1234 tokens, ...`.
+fn format_summary(s: &RunSummary) -> String {
+    let reason = match &s.stop_reason {
+        StopReason::Completed if s.truncated => "wrapped up at the step limit".to_string(),
+        StopReason::Completed => "completed".to_string(),
+        StopReason::MaxSteps => "stopped at max steps".to_string(),
+        StopReason::Budget { kind } => format!("stopped by {kind} budget"),
+        StopReason::Aborted => "aborted".to_string(),
+        StopReason::Error => "failed".to_string(),
+    };
+    let failed = s.tool_calls.iter().filter(|c| c.error.is_some()).count();
+    let failed = if failed > 0 { format!(" ({failed} failed)") } else { String::new() };
+    format!(
+        "{reason} after {} steps, {} tool calls{failed}; used {}",
+        s.steps,
+        s.tool_calls.len(),
+        format_usage(&s.budget)
+    )
+}
+
+/// Prints events as a transcript. Reasoning is hidden unless `show_thinking`.
+struct Renderer {
+    show_thinking: bool,
+    /// Reasoning is being printed; the answer starts on a new line.
+    thinking: AtomicBool,
+}
+
+impl Renderer {
+    fn render(&self, ev: &AgentEvent) {
+        match ev {
+            AgentEvent::ThinkingDelta { delta } if self.show_thinking => {
+                if !self.thinking.swap(true, Ordering::Relaxed) {
+                    print!("(thinking) ");
                 }
-                if !tool_calls.is_empty() {
-                    println!("\nassistant(tool_calls)> {} call(s)", tool_calls.len());
-                    for tc in tool_calls {
-                        println!(" - {} {}", tc.name, tc.id);
-                    }
+                print!("{delta}");
+                io::stdout().flush().ok();
+            }
+            AgentEvent::TextDelta { .. } | AgentEvent::MessageEnd { .. } => {
+                if self.thinking.swap(false, Ordering::Relaxed) {
+                    print!("\n\n");
                 }
+                render_event(ev);
             }
-            ChatMessage::Tool { tool_call_id, content } => {
-                println!("\ntool[{tool_call_id}]>\n{content}");
+            _ => render_event(ev),
+        }
+    }
+}
+
+fn render_event(ev: &AgentEvent) {
+    match ev {
+        AgentEvent::TextDelta { delta } => {
+            print!("{delta}");
+            io::stdout().flush().ok();
+        }
+        AgentEvent::MessageEnd { message: ChatMessage::Assistant { content, tool_calls, .. } } => {
+            if !content.is_empty() {
+                println!();
             }
-            _ => {}
+            for tc in tool_calls {
+                println!("\nassistant(tool_call)> {} {} {}", tc.name, tc.id, tc.arguments);
+            }
+        }
+        AgentEvent::ToolExecutionEnd { id, content, is_error, details, .. } => {
+            let tag = if *is_error { " error" } else { "" };
+            println!("\ntool[{id}]{tag}>\n{content}");
+            // File edits carry a unified diff, which says more than "edited <path>".
+            if let Some(diff) = details.as_ref().and_then(|d| d["diff"].as_str()) {
+                print!("{diff}");
+            }
+        }
+        AgentEvent::TurnStart { .. } => print!("\nassistant> "),
+        AgentEvent::Compaction { tokens_before, tokens_after } => {
+            println!("\n(compacted context: ~{tokens_before} -> ~{tokens_after} tokens)");
+        }
+        AgentEvent::QueuedMessage { content, kind } => {
+            let tag = match kind {
+                QueuedMessageKind::Steering => "steering",
+                QueuedMessageKind::FollowUp => "follow-up",
+            };
+            println!("\nuser({tag})> {content}");
+        }
+        AgentEvent::Retry { attempt, max_attempts, delay_ms, error } => {
+            eprintln!(
+                "\n({error}; retrying in {:.1}s, attempt {attempt}/{max_attempts})",
+                *delay_ms as f64 / 1000.0
+            );
+        }
+        AgentEvent::BudgetExceeded { kind, .. } => println!("\n({kind} budget exceeded; stopping)"),
+        AgentEvent::RunEnd { summary, .. } => println!("\n({})", format_summary(summary)),
+        _ => {}
+    }
+}
+
+/// Attaches the files named by `@path` words (images and audio, relative to `cwd`). The words stay
+/// in the text so the model can tell attachments apart.
+async fn with_attachments(input: &str, cwd: &Path) -> Result<UserInput, PiError> {
+    let mut parts = vec![];
+    for word in input.split_whitespace() {
+        let Some(path) = word.strip_prefix('@') else { continue };
+        if attachment_mime(Path::new(path)).is_some() {
+            parts.push(load_attachment(&cwd.join(path)).await?);
+        }
+    }
+    Ok(UserInput::with_parts(input, parts))
+}
+
+/// Runs one prompt; Ctrl-C aborts the run (not the process).
+///
+/// `@path` words attach images or audio. Lines typed meanwhile answer a pending approval prompt,
+/// or else steer the run; `/followup <text>` queues a message for after the run instead.
+async fn run_interruptible<P: AiProvider>(
+    agent: &Agent<P>,
+    tr: &mut Transcript,
+    input: &str,
+    cwd: &Path,
+    lines: &mut mpsc::UnboundedReceiver<String>,
+    answer: &PendingAnswer,
+) -> Result<RunSummary, PiError> {
+    let input = with_attachments(input, cwd).await?;
+    let cancel = CancellationToken::new();
+    let ctx = ToolContext { cancel: cancel.clone(), ..ToolContext::new(cwd) };
+    let run = agent.run_stream(tr, input, ctx);
+    tokio::pin!(run);
+    let mut stdin_open = true;
+    let r = loop {
+        tokio::select! {
+            r = &mut run => break r,
+            _ = tokio::signal::ctrl_c(), if !cancel.is_cancelled() => {
+                eprintln!("\n(aborting)");
+                cancel.cancel();
+            }
+            line = lines.recv(), if stdin_open => match line {
+                None => stdin_open = false,
+                Some(line) => {
+                    if let Some(tx) = answer.lock().unwrap().take() {
+                        tx.send(line).ok();
+                    } else if let Some(text) = line.strip_prefix("/followup ") {
+                        agent.queue().follow_up(text);
+                        println!("(follow-up queued)");
+                    } else if !line.trim().is_empty() {
+                        agent.queue().steer(line);
+                    }
+                }
+            },
         }
+    };
+    // Queued messages only make sense for a run that finished normally.
+    if !matches!(&r, Ok(s) if s.stop_reason == StopReason::Completed) {
+        agent.queue().clear();
     }
+    r
 }
 
 #[tokio::main]
@@ -87,46 +325,102 @@ async fn main() -> Result<(), PiError> {
     let args = Args::parse();
     let cwd = args.cwd.unwrap_or(std::env::current_dir().map_err(PiError::from)?);
 
+    // Unknown models get no automatic compaction (`/compact` still works) and are priced at zero,
+    // so they can't take a cost limit.
+    let known = ModelCatalog::builtin().find("openai", &args.model);
+    let context_window = known.as_ref().map_or(0, |m| m.context_window);
+    let pricing = known.map(|m| m.cost).unwrap_or_default();
+    let budget = RunBudget {
+        max_tokens: args.max_run_tokens,
+        max_cost_usd: args.max_run_cost,
+        max_duration: args.max_run_secs.map(Duration::from_secs),
+    };
+    budget.check_pricing(&args.model, &pricing)?;
+    let tokens = load_tokenizers(&pi_dir(&cwd).join("tokenizers"))
+        .await?
+        .counter(ApiKind::OpenAiCompletions, &args.model);
     let model = NonEmptyString::new(args.model)?;
-    let provider = OpenAiChatProvider::from_env()?;
+    let events = AgentEvents::new();
+    let provider: Arc<dyn AiProvider> = match (args.replay, args.record) {
+        (Some(dir), _) => {
+            let replay = ReplayProvider::load(&JsonDirFixtureStore::new(&dir)).await?;
+            if replay.remaining() == 0 {
+                return Err(PiError::Invalid(format!("no fixtures in {}", dir.display())));
+            }
+            Arc::new(replay)
+        }
+        (None, record) => {
+            let live = RetryProvider::new(
+                OpenAiChatProvider::from_env()?,
+                RetryPolicy { max_attempts: args.max_attempts.max(1), ..RetryPolicy::default() },
+                Arc::new(TokioSleeper),
+            )
+            .with_events(events.clone());
+            match record {
+                Some(dir) => {
+                    let store = JsonDirFixtureStore::new(dir);
+                    store.clear().await?;
+                    Arc::new(RecordingProvider::new(live, Arc::new(store)))
+                }
+                None => Arc::new(live),
+            }
+        }
+    };
 
     let mut tools = pi_adapter_fs::coding_tools();
     tools.push(bash_tool());
 
+    let permissions = load_permission_policy(&pi_dir(&cwd).join("permissions.json"))
+        .await?
+        .unwrap_or_default();
+
     let agent = Agent::new(
         provider,
-        ToolSet::new(tools),
+        ToolSet::new(tools)?,
         AgentConfig {
-            model,
             system_prompt: args.system,
-            max_steps: 32,
-            temperature: None,
-            max_tokens: None,
+            permissions,
+            compaction: CompactionConfig::for_window(context_window),
+            budget,
+            pricing,
+            reasoning_effort: args.reasoning_effort,
+            ..AgentConfig::minimal(model)
         },
-    );
+    )
+    .with_token_counter(tokens)
+    .with_sleeper(Arc::new(TokioSleeper))
+    .with_events(events);
 
     let session_id = load_or_create_session_id(cwd.as_path()).await?;
     let store = JsonDirSessionStore::new(pi_dir(cwd.as_path()).join("sessions"));
 
     let mut tr = store.load(session_id.clone()).await?.unwrap_or_default();
 
+    // No approver in RPC mode: `ask` rules deny.
+    if args.rpc {
+        return rpc::serve(&agent, tr, &cwd, &store, session_id).await;
+    }
+
+    let answer = PendingAnswer::default();
+    let agent = agent.with_approver(Arc::new(StdinApprover { answer: answer.clone() }));
+    let renderer = Renderer { show_thinking: args.show_thinking, thinking: AtomicBool::new(false) };
+    agent.events().on_event(move |ev| renderer.render(ev));
+    let mut lines = stdin_lines();
+
     if let Some(p) = args.prompt {
-        let before = tr.len();
-        agent.run_to_end(&mut tr, &p, ToolContext { cwd: cwd.clone() }).await?;
+        let r = run_interruptible(&agent, &mut tr, &p, &cwd, &mut lines, &answer).await;
         store.save(session_id, &tr).await?;
-        print_new_messages(&tr, before);
-        return Ok(());
+        return r.and_then(|s| s.stop_error().map_or(Ok(()), Err));
     }
 
-    println!("pi-mono-rust interactive. /exit, /quit, /reset");
-    let mut input = String::new();
+    println!("pi-mono-rust interactive. /exit, /quit, /reset, /compact");
+    println!("Attach images or audio with `@path`. While a run is going, type to steer it or `/followup <text>` to queue a message.");
     loop {
-        input.clear();
         print!("\nuser> ");
         io::stdout().flush().ok();
-        if io::stdin().read_line(&mut input).is_err() {
+        let Some(input) = lines.recv().await else {
             break;
-        }
+        };
         let line = input.trim_end().to_string();
         if line.is_empty() {
             continue;
@@ -139,15 +433,22 @@ async fn main() -> Result<(), PiError> {
                 println!("(reset)");
                 continue;
             }
+            "/compact" => {
+                match agent.compact(&mut tr).await {
+                    Ok(true) => store.save(session_id.clone(), &tr).await?,
+                    Ok(false) => println!("(nothing to compact)"),
+                    Err(e) => eprintln!("error: {e}"),
+                }
+                continue;
+            }
             _ => {}
         }
 
-        let before = tr.len();
-        if let Err(e) = agent.run_to_end(&mut tr, &line, ToolContext { cwd: cwd.clone() }).await {
+        // Early stops are reported by the run summary line.
+        if let Err(e) = run_interruptible(&agent, &mut tr, &line, &cwd, &mut lines, &answer).await {
             eprintln!("error: {e}");
         }
         store.save(session_id.clone(), &tr).await?;
-        print_new_messages(&tr, before);
     }
 
     Ok(())
@@ -0,0 +1,122 @@
+//! JSON-lines RPC mode: one command per stdin line, one JSON object per stdout line.
+//!
+//! Commands: `{"type":"prompt","message":"..."}`, `{"type":"steer","message":"..."}`,
+//! `{"type":"follow_up","message":"..."}`, `{"type":"abort"}`. Output is every [`AgentEvent`] as
+//! serialized, plus `run_end` (with the run's [`RunSummary`] and, unless it completed, an error as
+//! [`ErrorInfo`]) after each prompt and `error` for rejected commands.
+
+use pi_contracts::{AgentEvent, ErrorInfo, PiError, RunSummary, SessionId};
+use pi_core::{Agent, AiProvider, CancellationToken, SessionStore, ToolContext, Transcript};
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    io::{self, Write},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RpcCommand {
+    Prompt { message: String },
+    Steer { message: String },
+    FollowUp { message: String },
+    Abort,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RpcOutput {
+    RunEnd {
+        error: Option<ErrorInfo>,
+        summary: Option<Box<RunSummary>>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+fn write_line(value: &impl Serialize) {
+    let Ok(json) = serde_json::to_string(value) else {
+        return;
+    };
+    let mut out = io::stdout().lock();
+    writeln!(out, "{json}").ok();
+    out.flush().ok();
+}
+
+type Run<'a> = Pin<Box<dyn Future<Output = (Transcript, Result<RunSummary, PiError>)> + Send + 'a>>;
+
+/// Serves commands until stdin closes. A run still in progress at that point is aborted.
+pub async fn serve<P: AiProvider>(
+    agent: &Agent<P>,
+    mut tr: Transcript,
+    cwd: &Path,
+    store: &dyn SessionStore,
+    session_id: SessionId,
+) -> Result<(), PiError> {
+    // The agent's own `run_end` event is folded into ours, so each prompt ends with one line.
+    // A failed run has no `RunSummary` result, but its event still carries one.
+    let summary = Arc::new(Mutex::new(None));
+    let last_summary = summary.clone();
+    agent.events().on_event(move |ev| match ev {
+        AgentEvent::RunEnd { summary, .. } => *last_summary.lock().unwrap() = Some(summary.clone()),
+        ev => write_line(ev),
+    });
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut cancel = CancellationToken::new();
+    // The run owns the transcript while it is in flight and hands it back when done.
+    let mut run: Option<Run<'_>> = None;
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else { break };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<RpcCommand>(&line) {
+                    Err(e) => write_line(&RpcOutput::Error { message: format!("invalid command: {e}") }),
+                    Ok(RpcCommand::Prompt { .. }) if run.is_some() => write_line(&RpcOutput::Error {
+                        message: "a run is in progress; use steer or follow_up".into(),
+                    }),
+                    Ok(RpcCommand::Prompt { message }) => {
+                        cancel = CancellationToken::new();
+                        let ctx = ToolContext { cancel: cancel.clone(), ..ToolContext::new(cwd) };
+                        let mut owned = std::mem::take(&mut tr);
+                        run = Some(Box::pin(async move {
+                            let r = agent.run_stream(&mut owned, message, ctx).await;
+                            (owned, r)
+                        }));
+                    }
+                    Ok(RpcCommand::Steer { message }) => agent.queue().steer(message),
+                    Ok(RpcCommand::FollowUp { message }) => agent.queue().follow_up(message),
+                    Ok(RpcCommand::Abort) => cancel.cancel(),
+                }
+            }
+            (owned, r) = async { run.as_mut().expect("guarded by precondition").await }, if run.is_some() => {
+                run = None;
+                tr = owned;
+                let error = r.and_then(|s| s.stop_error().map_or(Ok(()), Err)).err();
+                if error.is_some() {
+                    agent.queue().clear();
+                }
+                store.save(session_id.clone(), &tr).await?;
+                write_line(&RpcOutput::RunEnd {
+                    error: error.map(|e| e.info()),
+                    summary: summary.lock().unwrap().take(),
+                });
+            }
+        }
+    }
+
+    if let Some(run) = run {
+        cancel.cancel();
+        let (owned, _) = run.await;
+        store.save(session_id, &owned).await?;
+    }
+    Ok(())
+}
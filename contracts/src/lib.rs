@@ -2,7 +2,7 @@
 //! Public types and errors for the pi-mono-rust workspace.
 
 use serde::{Deserialize, Serialize};
-use std::{fmt, num::NonZeroUsize};
+use std::{collections::BTreeMap, fmt, num::NonZeroUsize};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -36,13 +36,147 @@ pub enum PiError {
     #[error(transparent)]
     Json(#[from] serde_json::Error),
 
-    /// HTTP client.
+    /// HTTP client (connection, TLS, body decoding); the client's error is kept as the source.
     #[error("http: {0}")]
-    Http(String),
+    Http(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    /// The provider answered with a non-success HTTP status.
+    #[error("provider: {message}")]
+    HttpStatus {
+        status: u16,
+        message: String,
+        /// Provider error code, e.g. `rate_limit_exceeded`.
+        code: Option<String>,
+        /// Provider error type, e.g. `insufficient_quota`.
+        error_type: Option<String>,
+        /// Server-requested delay before retrying (`Retry-After`), if any.
+        retry_after_ms: Option<u64>,
+    },
 
     /// Timeout.
     #[error("timeout: {0}")]
     Timeout(String),
+
+    /// Cancelled by the caller.
+    #[error("aborted")]
+    Aborted,
+
+    /// A per-run budget (tokens, cost, time) ran out; the run stopped cleanly.
+    #[error("{0} budget exceeded")]
+    BudgetExceeded(BudgetKind),
+}
+
+impl PiError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            PiError::Invalid(_) => ErrorKind::Invalid,
+            PiError::Tool(_) => ErrorKind::Tool,
+            PiError::Provider(_) | PiError::HttpStatus { .. } => ErrorKind::Provider,
+            PiError::Adapter(_) => ErrorKind::Adapter,
+            PiError::Io(_) => ErrorKind::Io,
+            PiError::Json(_) => ErrorKind::Json,
+            PiError::Http(_) => ErrorKind::Http,
+            PiError::Timeout(_) => ErrorKind::Timeout,
+            PiError::Aborted => ErrorKind::Aborted,
+            PiError::BudgetExceeded(_) => ErrorKind::BudgetExceeded,
+        }
+    }
+
+    /// Machine-readable form for front ends (RPC, FFI, web UI).
+    pub fn info(&self) -> ErrorInfo {
+        let (status, code, error_type, retry_after_ms) = match self {
+            PiError::HttpStatus {
+                status,
+                code,
+                error_type,
+                retry_after_ms,
+                ..
+            } => (
+                Some(*status),
+                code.clone(),
+                error_type.clone(),
+                *retry_after_ms,
+            ),
+            _ => (None, None, None, None),
+        };
+        let mut sources = vec![];
+        let mut next = std::error::Error::source(self);
+        while let Some(e) = next {
+            sources.push(e.to_string());
+            next = e.source();
+        }
+        ErrorInfo {
+            kind: self.kind(),
+            message: self.to_string(),
+            status,
+            code,
+            error_type,
+            retryable: self.is_retryable(),
+            retry_after_ms,
+            sources,
+        }
+    }
+
+    /// Whether the same request may succeed if sent again: network failures, timeouts, rate
+    /// limits and server errors.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            PiError::Http(_) | PiError::Timeout(_) => true,
+            PiError::HttpStatus { status, .. } => {
+                matches!(status, 408 | 409 | 425 | 429) || *status >= 500
+            }
+            _ => false,
+        }
+    }
+
+    /// How long the server asked us to wait before retrying.
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            PiError::HttpStatus {
+                retry_after_ms: Some(ms),
+                ..
+            } => Some(std::time::Duration::from_millis(*ms)),
+            _ => None,
+        }
+    }
+}
+
+/// Error category; one per [`PiError`] variant, except that HTTP status failures are
+/// [`ErrorKind::Provider`] errors with [`ErrorInfo::status`] set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Invalid,
+    Tool,
+    Provider,
+    Adapter,
+    Io,
+    Json,
+    Http,
+    Timeout,
+    Aborted,
+    BudgetExceeded,
+}
+
+/// Serializable description of a [`PiError`] (see [`PiError::info`]).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorInfo {
+    pub kind: ErrorKind,
+    /// The error's display text.
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_type: Option<String>,
+    /// Whether sending the same request again may succeed.
+    pub retryable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+    /// Underlying causes, outermost first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
 }
 
 /// A validated, non-empty string.
@@ -137,6 +271,11 @@ impl TokenCost {
         }
     }
 
+    /// No price is set: either the model is free to run or its pricing is unknown.
+    pub fn is_free(&self) -> bool {
+        *self == Self::free()
+    }
+
     /// Best-effort estimate of USD cost for the given usage.
     pub fn estimate_usd(&self, usage: &TokenUsage) -> CostBreakdown {
         let per_m = 1_000_000.0;
@@ -175,6 +314,124 @@ pub struct CostBreakdown {
     pub currency: Currency,
 }
 
+impl Default for CostBreakdown {
+    fn default() -> Self {
+        Self {
+            input: 0.0,
+            output: 0.0,
+            cache_read: 0.0,
+            cache_write: 0.0,
+            total: 0.0,
+            currency: Currency::Usd,
+        }
+    }
+}
+
+impl std::ops::AddAssign<&CostBreakdown> for CostBreakdown {
+    fn add_assign(&mut self, other: &CostBreakdown) {
+        self.input += other.input;
+        self.output += other.output;
+        self.cache_read += other.cache_read;
+        self.cache_write += other.cache_write;
+        self.total += other.total;
+    }
+}
+
+/// Which per-run budget ran out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetKind {
+    Tokens,
+    Cost,
+    Time,
+}
+
+impl fmt::Display for BudgetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BudgetKind::Tokens => "token",
+            BudgetKind::Cost => "cost",
+            BudgetKind::Time => "time",
+        })
+    }
+}
+
+/// What a run has used, next to its limits (`None` = unlimited).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BudgetUsage {
+    /// Prompt + completion tokens across every provider call of the run.
+    pub tokens: u64,
+    pub cost_usd: f64,
+    pub elapsed_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cost_usd: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_elapsed_ms: Option<u64>,
+}
+
+/// Why a run stopped.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum StopReason {
+    /// The model answered without requesting more tools.
+    Completed,
+    /// `max_steps` provider calls were made without a final answer (and no wrap-up was asked for).
+    MaxSteps,
+    /// A budget ran out.
+    Budget { kind: BudgetKind },
+    /// The caller cancelled the run.
+    Aborted,
+    /// The run failed. Reported by [`AgentEvent::RunEnd`] and by the summaries that come with a
+    /// failed run's error (`Agent::run_tracked`); the error itself says why.
+    Error,
+}
+
+/// One executed (or skipped) tool call.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolCallSummary {
+    pub id: ToolCallId,
+    pub name: ToolName,
+    pub duration_ms: u64,
+    /// The error reported to the model, if the call failed or was skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What happened during one agent run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub stop_reason: StopReason,
+    /// Provider calls made (compaction summaries not included).
+    pub steps: usize,
+    pub tool_calls: Vec<ToolCallSummary>,
+    /// Aggregated over every provider call of the run, including compaction and sub-agents.
+    pub usage: TokenUsage,
+    pub cost: CostBreakdown,
+    /// Usage against the run's budgets.
+    pub budget: BudgetUsage,
+    /// The run completed with a wrap-up answer because it reached `max_steps`; the work may be
+    /// unfinished.
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+impl RunSummary {
+    /// The error a caller expecting a completed run should report, if the run stopped early.
+    ///
+    /// `None` for [`StopReason::Error`] too: a failed run's cause is the `Err` it returned, which
+    /// callers must report instead.
+    pub fn stop_error(&self) -> Option<PiError> {
+        match self.stop_reason {
+            StopReason::Completed | StopReason::Error => None,
+            StopReason::MaxSteps => Some(PiError::Provider("max_steps reached".into())),
+            StopReason::Budget { kind } => Some(PiError::BudgetExceeded(kind)),
+            StopReason::Aborted => Some(PiError::Aborted),
+        }
+    }
+}
+
 /// Standardized model descriptor.
 ///
 /// This mirrors the rough shape used in `@mariozechner/pi-ai`: a stable identifier, a provider,
@@ -282,19 +539,110 @@ pub struct ToolSpec {
 pub enum ChatMessage {
     /// System message.
     System { content: String },
-    /// User message.
-    User { content: String },
-    /// Assistant message, optionally with tool calls.
+    /// User message. `parts` (images, audio) follow `content`.
+    User {
+        content: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        parts: Vec<ContentPart>,
+    },
+    /// Assistant message, optionally with tool calls and the reasoning that preceded them.
     Assistant {
         content: String,
         #[serde(default, skip_serializing_if = "Vec::is_empty")]
         tool_calls: Vec<ToolCall>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        thinking: Option<Thinking>,
     },
-    /// Tool result message.
+    /// Tool result message. `is_error` marks failed executions (unknown tool, bad args, tool error).
     Tool {
         tool_call_id: ToolCallId,
         content: String,
+        #[serde(default, skip_serializing_if = "is_false")]
+        is_error: bool,
+        /// Structured data for front ends (diffs, exit codes, paths); not sent to the model.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        details: Option<serde_json::Value>,
+        /// Content after `content`, such as screenshots.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        parts: Vec<ContentPart>,
     },
+    /// Summary of earlier turns written by context compaction. Provider requests replace every
+    /// non-system message before transcript index `first_kept` with this summary.
+    Compaction {
+        summary: String,
+        first_kept: usize,
+        /// Estimated prompt size before compacting.
+        #[serde(default)]
+        tokens_before: u32,
+    },
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// Reasoning a model produced before answering.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Thinking {
+    /// Readable reasoning (possibly a summary). Empty when the provider only returns it encrypted.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub text: String,
+    /// Opaque provider data (a signature, or encrypted/redacted reasoning) to send back unchanged
+    /// so the provider can continue from it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// How much a reasoning model should think before answering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Minimal,
+    Low,
+    Medium,
+    High,
+}
+
+impl std::str::FromStr for ReasoningEffort {
+    type Err = PiError;
+
+    fn from_str(s: &str) -> Result<Self, PiError> {
+        serde_json::from_value(serde_json::Value::String(s.to_string())).map_err(|_| {
+            PiError::Invalid(format!(
+                "unknown reasoning effort `{s}` (use minimal, low, medium or high)"
+            ))
+        })
+    }
+}
+
+/// One piece of multi-part message content.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    /// Base64-encoded image data.
+    Image {
+        mime_type: String,
+        data: String,
+    },
+    /// Base64-encoded audio data.
+    Audio {
+        mime_type: String,
+        data: String,
+    },
+}
+
+impl ContentPart {
+    /// The input modality a model needs to accept this part.
+    pub fn modality(&self) -> InputModality {
+        match self {
+            Self::Text { .. } => InputModality::Text,
+            Self::Image { .. } => InputModality::Image,
+            Self::Audio { .. } => InputModality::Audio,
+        }
+    }
 }
 
 impl ChatMessage {
@@ -309,6 +657,15 @@ impl ChatMessage {
     pub fn user(content: impl Into<String>) -> Self {
         Self::User {
             content: content.into(),
+            parts: vec![],
+        }
+    }
+
+    /// Creates a user message with attachments.
+    pub fn user_with_parts(content: impl Into<String>, parts: Vec<ContentPart>) -> Self {
+        Self::User {
+            content: content.into(),
+            parts,
         }
     }
 
@@ -317,6 +674,7 @@ impl ChatMessage {
         Self::Assistant {
             content: content.into(),
             tool_calls,
+            thinking: None,
         }
     }
 
@@ -325,22 +683,54 @@ impl ChatMessage {
         Self::Tool {
             tool_call_id,
             content: content.into(),
+            is_error: false,
+            details: None,
+            parts: vec![],
+        }
+    }
+
+    /// Creates a tool result message flagged as an error.
+    pub fn tool_error(tool_call_id: ToolCallId, content: impl Into<String>) -> Self {
+        Self::Tool {
+            tool_call_id,
+            content: content.into(),
+            is_error: true,
+            details: None,
+            parts: vec![],
         }
     }
 
-    /// Returns role.
+    /// Creates a compaction entry summarizing everything before `first_kept`.
+    pub fn compaction(summary: impl Into<String>, first_kept: usize, tokens_before: u32) -> Self {
+        Self::Compaction {
+            summary: summary.into(),
+            first_kept,
+            tokens_before,
+        }
+    }
+
+    /// Multi-part content after the text (user and tool messages only).
+    pub fn parts(&self) -> &[ContentPart] {
+        match self {
+            Self::User { parts, .. } | Self::Tool { parts, .. } => parts,
+            _ => &[],
+        }
+    }
+
+    /// Returns role. Compaction summaries are presented to the model as user messages.
     pub fn role(&self) -> Role {
         match self {
             ChatMessage::System { .. } => Role::System,
             ChatMessage::User { .. } => Role::User,
             ChatMessage::Assistant { .. } => Role::Assistant,
             ChatMessage::Tool { .. } => Role::Tool,
+            ChatMessage::Compaction { .. } => Role::User,
         }
     }
 }
 
 /// Token usage info (if the provider returns it).
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TokenUsage {
     pub prompt_tokens: u64,
     pub completion_tokens: u64,
@@ -349,6 +739,9 @@ pub struct TokenUsage {
     pub cache_read_tokens: u64,
     #[serde(default)]
     pub cache_write_tokens: u64,
+    /// Part of `completion_tokens` spent on reasoning.
+    #[serde(default)]
+    pub reasoning_tokens: u64,
 }
 
 impl TokenUsage {
@@ -359,10 +752,22 @@ impl TokenUsage {
             total_tokens,
             cache_read_tokens: 0,
             cache_write_tokens: 0,
+            reasoning_tokens: 0,
         }
     }
 }
 
+impl std::ops::AddAssign<&TokenUsage> for TokenUsage {
+    fn add_assign(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
+        self.reasoning_tokens += other.reasoning_tokens;
+    }
+}
+
 /// Chat request passed to a provider.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ChatRequest {
@@ -374,6 +779,85 @@ pub struct ChatRequest {
     pub temperature: Option<f32>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
+    /// For reasoning models; ignored by providers that don't support it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Whether and which tool the model must call; the provider default (`Auto`) when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    /// Whether the model may call several tools in one reply; the provider default when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    /// Constrains the reply to JSON; plain text when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(default, skip_serializing_if = "RequestParams::is_empty")]
+    pub params: RequestParams,
+}
+
+/// Request parameters beyond `temperature` and `max_tokens`; all unset by default.
+///
+/// Providers drop the ones the target model doesn't support.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Sequences that end the reply when generated; not included in it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// Best-effort deterministic sampling.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    /// Stable end-user id, for the provider's abuse monitoring.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// Provider-specific fields merged into the request body as-is, for knobs this contract
+    /// doesn't model yet. Providers reject keys that name a field they set themselves.
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl RequestParams {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Whether and which tool the model must call.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolChoice {
+    /// The model decides.
+    Auto,
+    /// No tool calls; the tools are still described to the model.
+    None,
+    /// At least one tool call.
+    Required,
+    /// A call to this tool.
+    Tool { name: ToolName },
+}
+
+/// Shape the assistant reply must take.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    /// Any syntactically valid JSON object.
+    JsonObject,
+    /// JSON matching `schema`. With `strict`, providers that support it guarantee the match
+    /// (the schema must then fit the provider's strict subset).
+    JsonSchema {
+        name: String,
+        schema: serde_json::Value,
+        #[serde(default)]
+        strict: bool,
+    },
 }
 
 /// Chat response returned by a provider.
@@ -385,6 +869,36 @@ pub struct ChatResponse {
     /// Optional best-effort cost estimate.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub cost: Option<CostBreakdown>,
+    /// Why generation stopped, when the provider says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<FinishReason>,
+}
+
+/// Why a provider stopped generating (normalized).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// The reply is complete.
+    Stop,
+    /// Cut off by `max_tokens` (or the context window).
+    Length,
+    /// The model is waiting for tool results.
+    #[serde(alias = "function_call")]
+    ToolCalls,
+    /// Withheld or cut off by the provider's content filter.
+    ContentFilter,
+    #[serde(other)]
+    Other,
+}
+
+/// One recorded provider exchange, replayed by `pi_core::ReplayProvider`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProviderFixture {
+    pub request: ChatRequest,
+    pub response: ChatResponse,
+    /// The events delivered before `response`, if it was streamed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<Vec<ChatStreamEvent>>,
 }
 
 /// Streaming error category (normalized).
@@ -406,6 +920,9 @@ pub enum ChatStreamEvent {
     TextDelta {
         delta: String,
     },
+    ThinkingDelta {
+        delta: String,
+    },
     ToolCallDelta {
         id: ToolCallId,
         name: ToolName,
@@ -416,13 +933,194 @@ pub enum ChatStreamEvent {
     Usage {
         usage: TokenUsage,
     },
-    Done,
+    Done {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        finish_reason: Option<FinishReason>,
+    },
     Error {
         reason: StreamErrorReason,
         message: String,
     },
 }
 
+/// Normalized agent-loop events.
+///
+/// One vocabulary for every front end (CLI, TUI, RPC mode, FFI). Streaming deltas are only emitted
+/// by streaming runs; everything else is emitted regardless of transport.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentEvent {
+    /// A provider step is about to start (0-based).
+    TurnStart {
+        step: usize,
+    },
+    TextDelta {
+        delta: String,
+    },
+    /// Reasoning text, streamed before the answer.
+    ThinkingDelta {
+        delta: String,
+    },
+    ToolCallDelta {
+        id: ToolCallId,
+        name: ToolName,
+        arguments_delta: String,
+    },
+    Usage {
+        usage: TokenUsage,
+    },
+    /// Final assistant message for the current step (as appended to the transcript).
+    MessageEnd {
+        message: ChatMessage,
+    },
+    ToolExecutionStart {
+        id: ToolCallId,
+        name: ToolName,
+        arguments: serde_json::Value,
+    },
+    /// Tool finished; `content` is exactly what the model will see.
+    ToolExecutionEnd {
+        id: ToolCallId,
+        name: ToolName,
+        content: String,
+        #[serde(default, skip_serializing_if = "is_false")]
+        is_error: bool,
+        /// The tool's structured details (see [`ChatMessage::Tool`]), for rendering.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        details: Option<serde_json::Value>,
+    },
+    /// The step (assistant message + tool executions) is complete.
+    TurnEnd {
+        step: usize,
+    },
+    /// Older turns were summarized; sizes are estimated prompt tokens.
+    Compaction {
+        tokens_before: u32,
+        tokens_after: u32,
+    },
+    /// A provider call failed with a retryable error and is retried after `delay_ms`.
+    Retry {
+        /// The attempt that is about to start (2 = first retry).
+        attempt: u32,
+        max_attempts: u32,
+        delay_ms: u64,
+        error: String,
+    },
+    /// A message queued during the run was appended to the transcript as a user message.
+    QueuedMessage {
+        content: String,
+        kind: QueuedMessageKind,
+    },
+    /// An event from a sub-agent run by the tool named `agent`.
+    SubAgent {
+        agent: String,
+        event: Box<AgentEvent>,
+    },
+    /// A budget ran out; the run stops with [`StopReason::Budget`].
+    BudgetExceeded {
+        kind: BudgetKind,
+        usage: BudgetUsage,
+    },
+    /// The run finished, successfully or not (then `error` is set).
+    RunEnd {
+        summary: Box<RunSummary>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<ErrorInfo>,
+    },
+}
+
+/// How a message submitted during a run is delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueuedMessageKind {
+    /// Injected before the next provider call; remaining tool calls of the step are skipped.
+    Steering,
+    /// Sent as a new user turn once the current one ends.
+    FollowUp,
+}
+
+/// Identifies a [`SessionEntry`] within a session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EntryId(pub Uuid);
+
+impl EntryId {
+    /// New random entry id.
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for EntryId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A transcript entry: a [`ChatMessage`] plus where, when and at what cost it was produced.
+///
+/// Provider requests only need [`SessionEntry::message`]; the rest is for front ends and
+/// accounting. Provenance fields are set on assistant replies (and `duration_ms` on tool results).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionEntry {
+    pub id: EntryId,
+    /// The entry this one follows; `None` for the first entry of a session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<EntryId>,
+    /// Unix time in milliseconds.
+    pub timestamp_ms: u64,
+    pub message: ChatMessage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<ProviderId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<ModelId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<CostBreakdown>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<FinishReason>,
+    /// Time spent producing the message: the provider request for a reply, the call for a tool
+    /// result.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+impl SessionEntry {
+    /// A new entry for `message`, stamped now, with no parent or provenance.
+    pub fn new(message: ChatMessage) -> Self {
+        let timestamp_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+        Self {
+            id: EntryId::new(),
+            parent_id: None,
+            timestamp_ms,
+            message,
+            provider: None,
+            model: None,
+            usage: None,
+            cost: None,
+            stop_reason: None,
+            duration_ms: None,
+        }
+    }
+
+    /// Wraps plain messages (e.g. a transcript saved before entries existed), each parented to
+    /// the one before.
+    pub fn chain(messages: impl IntoIterator<Item = ChatMessage>) -> Vec<Self> {
+        let mut out: Vec<Self> = vec![];
+        for message in messages {
+            let parent_id = out.last().map(|e| e.id);
+            out.push(Self {
+                parent_id,
+                ..Self::new(message)
+            });
+        }
+        out
+    }
+}
+
 /// A session identifier.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
@@ -462,6 +1160,28 @@ impl LineRange {
 mod tests {
     use super::*;
 
+    #[test]
+    fn stop_errors_leave_failures_to_the_run_error() {
+        let summary = |stop_reason| RunSummary {
+            stop_reason,
+            steps: 1,
+            tool_calls: vec![],
+            usage: TokenUsage::default(),
+            cost: CostBreakdown::default(),
+            budget: BudgetUsage::default(),
+            truncated: false,
+        };
+        assert!(summary(StopReason::Completed).stop_error().is_none());
+        assert!(summary(StopReason::Error).stop_error().is_none());
+        assert!(matches!(
+            summary(StopReason::Budget {
+                kind: BudgetKind::Time
+            })
+            .stop_error(),
+            Some(PiError::BudgetExceeded(BudgetKind::Time))
+        ));
+    }
+
     #[test]
     fn token_cost_estimate_is_additive() {
         let c = TokenCost {
@@ -476,10 +1196,55 @@ mod tests {
             total_tokens: 600_000,
             cache_read_tokens: 200_000,
             cache_write_tokens: 50_000,
+            // Already counted in `completion_tokens`.
+            reasoning_tokens: 80_000,
         };
         let cost = c.estimate_usd(&usage);
         // 0.5*2 + 0.1*10 + 0.2*1 + 0.05*5 = 1 + 1 + 0.2 + 0.25 = 2.45
         assert!((cost.total - 2.45).abs() < 1e-9);
         assert_eq!(cost.currency, Currency::Usd);
     }
+
+    #[test]
+    fn error_info_carries_http_details_and_sources() {
+        let e = PiError::HttpStatus {
+            status: 429,
+            message: "openai 429 Too Many Requests: slow down".into(),
+            code: Some("rate_limit_exceeded".into()),
+            error_type: Some("requests".into()),
+            retry_after_ms: Some(1_200),
+        };
+        let json = serde_json::to_value(e.info()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "kind": "provider",
+                "message": "provider: openai 429 Too Many Requests: slow down",
+                "status": 429,
+                "code": "rate_limit_exceeded",
+                "error_type": "requests",
+                "retryable": true,
+                "retry_after_ms": 1200,
+            })
+        );
+
+        #[derive(Debug, Error)]
+        #[error("connect failed")]
+        struct Connect(#[source] PiError);
+
+        let io = std::io::Error::other(Connect(PiError::Timeout("connect".into())));
+        let info = PiError::Io(io).info();
+        assert_eq!(info.kind, ErrorKind::Io);
+        assert_eq!(info.message, "connect failed");
+        assert!(!info.retryable);
+        assert_eq!(info.sources, vec!["timeout: connect".to_string()]);
+
+        let e = PiError::Http(Box::new(Connect(PiError::Timeout("dns".into()))));
+        let source = std::error::Error::source(&e).unwrap();
+        assert!(source.downcast_ref::<Connect>().is_some());
+        let info = e.info();
+        assert_eq!(info.message, "http: connect failed");
+        assert!(info.retryable);
+        assert_eq!(info.sources, ["connect failed", "timeout: dns"]);
+    }
 }
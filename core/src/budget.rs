@@ -0,0 +1,200 @@
+//! Per-run budgets.
+//!
+//! A run accumulates the usage reported by every provider response (including compaction
+//! summaries) and stops before the next step once any limit is reached. The time limit also
+//! cuts short the step or tool calls in flight when the agent has a [`Sleeper`](crate::Sleeper).
+
+use pi_contracts::{
+    BudgetKind, BudgetUsage, ChatResponse, CostBreakdown, PiError, TokenCost, TokenUsage,
+};
+use std::time::{Duration, Instant};
+
+/// Limits for a single run (`None` = unlimited).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunBudget {
+    /// Prompt + completion tokens across all provider calls.
+    pub max_tokens: Option<u64>,
+    /// Estimated cost in USD, priced with [`AgentConfig::pricing`](crate::AgentConfig::pricing)
+    /// unless the provider reports a cost itself.
+    pub max_cost_usd: Option<f64>,
+    /// Wall-clock time. With [`Agent::with_sleeper`](crate::Agent::with_sleeper) an in-flight
+    /// request or tool call is cancelled at the deadline; without a sleeper it is only checked
+    /// between steps.
+    pub max_duration: Option<Duration>,
+}
+
+impl RunBudget {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Fails if a cost limit is set but `model`'s `pricing` is free or unknown. Meant for
+    /// providers that report no cost of their own, where such a limit could never be reached.
+    pub fn check_pricing(&self, model: &str, pricing: &TokenCost) -> Result<(), PiError> {
+        if self.max_cost_usd.is_some() && pricing.is_free() {
+            return Err(PiError::Invalid(format!(
+                "a cost limit needs token prices, and none are known for model `{model}`"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Running totals for one run.
+pub(crate) struct BudgetTracker {
+    budget: RunBudget,
+    pricing: TokenCost,
+    started: Instant,
+    tokens: u64,
+    usage: TokenUsage,
+    cost: CostBreakdown,
+}
+
+impl BudgetTracker {
+    pub fn new(budget: RunBudget, pricing: TokenCost) -> Self {
+        Self {
+            budget,
+            pricing,
+            started: Instant::now(),
+            tokens: 0,
+            usage: TokenUsage::default(),
+            cost: CostBreakdown::default(),
+        }
+    }
+
+    pub fn record(&mut self, resp: &ChatResponse) {
+        if let Some(usage) = &resp.usage {
+            self.record_usage(usage, resp.cost.as_ref());
+        }
+    }
+
+    /// Adds usage; `cost` is estimated with the run's pricing when missing.
+    pub fn record_usage(&mut self, usage: &TokenUsage, cost: Option<&CostBreakdown>) {
+        self.tokens += match usage.total_tokens {
+            0 => usage.prompt_tokens + usage.completion_tokens,
+            n => n,
+        };
+        self.usage += usage;
+        match cost {
+            Some(c) => self.cost += c,
+            None => self.cost += &self.pricing.estimate_usd(usage),
+        }
+    }
+
+    /// Aggregated usage and cost so far.
+    pub fn totals(&self) -> (TokenUsage, CostBreakdown) {
+        (self.usage.clone(), self.cost)
+    }
+
+    /// Time until the time limit is reached (zero once it has been), if there is one.
+    pub fn time_left(&self) -> Option<Duration> {
+        self.budget
+            .max_duration
+            .map(|max| max.saturating_sub(self.started.elapsed()))
+    }
+
+    /// The first limit that has been reached, if any.
+    pub fn exceeded(&self) -> Option<BudgetKind> {
+        if self.budget.max_tokens.is_some_and(|max| self.tokens >= max) {
+            Some(BudgetKind::Tokens)
+        } else if self
+            .budget
+            .max_cost_usd
+            .is_some_and(|max| self.cost.total >= max)
+        {
+            Some(BudgetKind::Cost)
+        } else if self
+            .budget
+            .max_duration
+            .is_some_and(|max| self.started.elapsed() >= max)
+        {
+            Some(BudgetKind::Time)
+        } else {
+            None
+        }
+    }
+
+    pub fn usage(&self) -> BudgetUsage {
+        BudgetUsage {
+            tokens: self.tokens,
+            cost_usd: self.cost.total,
+            elapsed_ms: millis(self.started.elapsed()),
+            max_tokens: self.budget.max_tokens,
+            max_cost_usd: self.budget.max_cost_usd,
+            max_elapsed_ms: self.budget.max_duration.map(millis),
+        }
+    }
+}
+
+pub(crate) fn millis(d: Duration) -> u64 {
+    u64::try_from(d.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pi_contracts::{ChatMessage, TokenUsage};
+
+    fn response(prompt: u64, completion: u64) -> ChatResponse {
+        ChatResponse {
+            assistant: ChatMessage::assistant("", vec![]),
+            usage: Some(TokenUsage::new(prompt, completion, prompt + completion)),
+            cost: None,
+            finish_reason: None,
+        }
+    }
+
+    #[test]
+    fn tokens_and_cost_accumulate_against_their_limits() {
+        let pricing = TokenCost {
+            input: 1.0,
+            output: 2.0,
+            ..TokenCost::free()
+        };
+        let budget = RunBudget {
+            max_cost_usd: Some(0.5),
+            ..RunBudget::unlimited()
+        };
+        let mut t = BudgetTracker::new(budget, pricing);
+        t.record(&response(100_000, 50_000));
+        assert_eq!(t.exceeded(), None);
+        t.record(&response(300_000, 0));
+        assert_eq!(t.exceeded(), Some(BudgetKind::Cost));
+
+        let usage = t.usage();
+        assert_eq!(usage.tokens, 450_000);
+        assert!((usage.cost_usd - 0.5).abs() < 1e-9);
+        assert_eq!(usage.max_cost_usd, Some(0.5));
+        assert_eq!(usage.max_tokens, None);
+    }
+
+    #[test]
+    fn cost_limits_need_pricing() {
+        let budget = RunBudget {
+            max_cost_usd: Some(1.0),
+            ..RunBudget::unlimited()
+        };
+        let priced = TokenCost {
+            input: 1.0,
+            ..TokenCost::free()
+        };
+        assert!(budget.check_pricing("m", &priced).is_ok());
+        assert!(matches!(
+            budget.check_pricing("m", &TokenCost::free()),
+            Err(PiError::Invalid(_))
+        ));
+        assert!(RunBudget::unlimited()
+            .check_pricing("m", &TokenCost::free())
+            .is_ok());
+    }
+
+    #[test]
+    fn zero_duration_is_exceeded_immediately() {
+        let budget = RunBudget {
+            max_duration: Some(Duration::ZERO),
+            ..RunBudget::unlimited()
+        };
+        let t = BudgetTracker::new(budget, TokenCost::free());
+        assert_eq!(t.exceeded(), Some(BudgetKind::Time));
+    }
+}
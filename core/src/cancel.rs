@@ -0,0 +1,200 @@
+//! Runtime-agnostic cancellation.
+
+use futures::future::{self, Either};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    /// One waker per pending [`Cancelled`] future, keyed by its slot.
+    wakers: Mutex<HashMap<u64, Waker>>,
+    next_slot: AtomicU64,
+    /// Tokens from [`CancellationToken::child_token`], cancelled along with this one.
+    children: Mutex<Vec<Weak<Inner>>>,
+}
+
+/// A cloneable cancellation flag that can also be awaited.
+///
+/// Clones share state: cancelling any clone cancels all of them. Cancellation is permanent.
+/// A [`child_token`](CancellationToken::child_token) can be cancelled on its own, and is
+/// cancelled with its parent.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the token and wakes every pending [`CancellationToken::cancelled`] future.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for (_, w) in self.inner.wakers.lock().unwrap().drain() {
+            w.wake();
+        }
+        let children = std::mem::take(&mut *self.inner.children.lock().unwrap());
+        for inner in children.iter().filter_map(Weak::upgrade) {
+            CancellationToken { inner }.cancel();
+        }
+    }
+
+    /// A new token that is cancelled when this one is, but whose own cancellation leaves this
+    /// one alone. Used to stop one piece of work (e.g. at a deadline) without ending the rest.
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut children = self.inner.children.lock().unwrap();
+        // Checked under the lock, so a concurrent `cancel` either sees the child or ran first.
+        if self.is_cancelled() {
+            child.cancel();
+        } else {
+            children.retain(|c| c.strong_count() > 0);
+            children.push(Arc::downgrade(&child.inner));
+        }
+        drop(children);
+        child
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the token is cancelled.
+    pub fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled {
+            token: self,
+            slot: None,
+        }
+    }
+
+    /// Runs `fut` to completion unless the token is cancelled first, in which case `fut` is dropped
+    /// and `None` is returned.
+    pub async fn run_until_cancelled<F: Future>(&self, fut: F) -> Option<F::Output> {
+        if self.is_cancelled() {
+            return None;
+        }
+        let fut = std::pin::pin!(fut);
+        match future::select(fut, self.cancelled()).await {
+            Either::Left((out, _)) => Some(out),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Future returned by [`CancellationToken::cancelled`].
+///
+/// Keeps at most one waker registered (the latest), and removes it when dropped.
+pub struct Cancelled<'a> {
+    token: &'a CancellationToken,
+    slot: Option<u64>,
+}
+
+impl Future for Cancelled<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let inner = &self.token.inner;
+        if inner.cancelled.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+        let slot = *self
+            .slot
+            .get_or_insert_with(|| inner.next_slot.fetch_add(1, Ordering::Relaxed));
+        {
+            let mut wakers = inner.wakers.lock().unwrap();
+            match wakers.get_mut(&slot) {
+                Some(w) if w.will_wake(cx.waker()) => {}
+                Some(w) => w.clone_from(cx.waker()),
+                None => {
+                    wakers.insert(slot, cx.waker().clone());
+                }
+            }
+        }
+        // Re-check: `cancel` may have drained the list before we registered.
+        if inner.cancelled.load(Ordering::SeqCst) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            self.token.inner.wakers.lock().unwrap().remove(&slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cancel_wakes_waiters_and_drops_work() {
+        let token = CancellationToken::new();
+        let t = token.clone();
+        let waiter = tokio::spawn(async move { t.cancelled().await });
+        tokio::task::yield_now().await;
+        token.cancel();
+        waiter.await.unwrap();
+
+        let out = token
+            .run_until_cancelled(futures::future::pending::<()>())
+            .await;
+        assert!(out.is_none());
+    }
+
+    #[test]
+    fn children_are_cancelled_with_their_parent_but_not_the_reverse() {
+        let parent = CancellationToken::new();
+        let child = parent.child_token();
+        child.cancel();
+        assert!(!parent.is_cancelled());
+
+        let child = parent.child_token();
+        parent.cancel();
+        assert!(child.is_cancelled());
+        assert!(parent.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn waiters_keep_one_waker_and_remove_it_when_dropped() {
+        use futures::task::noop_waker;
+
+        let token = CancellationToken::new();
+        let mut fut = Box::pin(token.cancelled());
+        for _ in 0..3 {
+            let waker = noop_waker();
+            assert!(fut
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_pending());
+        }
+        assert_eq!(token.inner.wakers.lock().unwrap().len(), 1);
+        drop(fut);
+        assert!(token.inner.wakers.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+//! Context compaction.
+//!
+//! Long sessions eventually outgrow the model's context window. Compaction summarizes older turns
+//! into a [`ChatMessage::Compaction`] entry appended to the transcript. The transcript itself is
+//! never truncated; [`context_messages`] projects it onto what is actually sent to the provider.
+
+use crate::TokenCounter;
+use pi_contracts::{ChatMessage, ContentPart, SessionEntry};
+
+/// When and how much to compact.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompactionConfig {
+    /// Model context window in tokens; `0` (unknown) disables automatic compaction.
+    pub context_window: u32,
+    /// Compact before a step once the estimated prompt exceeds this fraction of the window.
+    pub threshold: f32,
+    /// Roughly how many tokens of the most recent context are kept verbatim.
+    pub keep_recent_tokens: u32,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            context_window: 0,
+            threshold: 0.8,
+            keep_recent_tokens: 20_000,
+        }
+    }
+}
+
+impl CompactionConfig {
+    /// Automatic compaction for a model with the given context window.
+    pub fn for_window(context_window: u32) -> Self {
+        Self {
+            context_window,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn should_compact(&self, estimated_tokens: u32) -> bool {
+        self.context_window > 0
+            && estimated_tokens as f64 > self.context_window as f64 * self.threshold as f64
+    }
+}
+
+/// The messages to send to the provider: leading system messages, then the latest compaction
+/// summary and everything from its `first_kept` onward.
+pub fn context_messages(transcript: &[SessionEntry]) -> Vec<ChatMessage> {
+    let messages = |entries: &[SessionEntry]| -> Vec<ChatMessage> {
+        entries.iter().map(|e| e.message.clone()).collect()
+    };
+    let Some((idx, first_kept, _)) = latest_compaction(transcript) else {
+        return messages(transcript);
+    };
+    let mut out = messages(leading_system(transcript));
+    out.push(transcript[idx].message.clone());
+    out.extend(
+        transcript[first_kept..]
+            .iter()
+            .map(|e| &e.message)
+            .filter(|m| {
+                !matches!(
+                    m,
+                    ChatMessage::System { .. } | ChatMessage::Compaction { .. }
+                )
+            })
+            .cloned(),
+    );
+    out
+}
+
+fn leading_system(transcript: &[SessionEntry]) -> &[SessionEntry] {
+    let n = transcript
+        .iter()
+        .take_while(|e| matches!(e.message, ChatMessage::System { .. }))
+        .count();
+    &transcript[..n]
+}
+
+/// Index, `first_kept` and summary of the most recent compaction entry.
+fn latest_compaction(transcript: &[SessionEntry]) -> Option<(usize, usize, &str)> {
+    transcript
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(i, e)| match &e.message {
+            ChatMessage::Compaction {
+                first_kept,
+                summary,
+                ..
+            } => Some((i, *first_kept, summary.as_str())),
+            _ => None,
+        })
+}
+
+/// What a compaction will summarize and where the kept tail starts.
+pub(crate) struct CompactionPlan {
+    pub previous_summary: Option<String>,
+    pub to_summarize: Vec<ChatMessage>,
+    /// Transcript index of the first message kept verbatim.
+    pub first_kept: usize,
+}
+
+/// Picks the cut point: the newest turn boundary that keeps at least `keep_recent_tokens` of
+/// context. Cuts only land on user or assistant messages, so tool results always stay with the
+/// assistant message that requested them. Returns `None` when there is nothing to summarize.
+pub(crate) fn plan(
+    transcript: &[SessionEntry],
+    keep_recent_tokens: u32,
+    tokens: &dyn TokenCounter,
+) -> Option<CompactionPlan> {
+    let (previous_summary, start) = match latest_compaction(transcript) {
+        Some((_, first_kept, summary)) => (Some(summary.to_string()), first_kept),
+        None => (None, leading_system(transcript).len()),
+    };
+    let live: Vec<usize> = (start..transcript.len())
+        .filter(|&i| {
+            !matches!(
+                transcript[i].message,
+                ChatMessage::System { .. } | ChatMessage::Compaction { .. }
+            )
+        })
+        .collect();
+
+    let mut kept = 0usize;
+    let mut cut = None;
+    for (pos, &i) in live.iter().enumerate().rev() {
+        kept += tokens.count_message(&transcript[i].message);
+        let boundary = matches!(
+            transcript[i].message,
+            ChatMessage::User { .. } | ChatMessage::Assistant { .. }
+        );
+        if kept >= keep_recent_tokens as usize && boundary {
+            cut = Some(pos);
+            break;
+        }
+    }
+    let cut = cut.filter(|&pos| pos > 0)?;
+    Some(CompactionPlan {
+        previous_summary,
+        to_summarize: live[..cut]
+            .iter()
+            .map(|&i| transcript[i].message.clone())
+            .collect(),
+        first_kept: live[cut],
+    })
+}
+
+const SUMMARY_INSTRUCTIONS: &str = "You summarize a conversation between a user and a coding \
+assistant so the assistant can continue the work with less context. Preserve the user's goals and \
+constraints, decisions made, files and code touched, tool results that still matter, and open tasks \
+or next steps. Be concise. Output only the summary.";
+
+/// Tool results are truncated in the summary prompt; the model rarely needs them verbatim.
+const MAX_TOOL_RESULT_CHARS: usize = 2_000;
+
+impl CompactionPlan {
+    /// The request messages asking the model for a summary.
+    pub fn summary_request(&self) -> Vec<ChatMessage> {
+        let mut text = String::new();
+        if let Some(prev) = &self.previous_summary {
+            text.push_str("Summary of the conversation before this excerpt:\n");
+            text.push_str(prev);
+            text.push_str("\n\n");
+        }
+        text.push_str("Conversation:\n");
+        for m in &self.to_summarize {
+            match m {
+                ChatMessage::User { content, parts } => {
+                    text.push_str(&format!("[user] {content}"));
+                    for part in parts {
+                        match part {
+                            ContentPart::Text { text: t } => text.push_str(&format!(" {t}")),
+                            ContentPart::Image { .. } => text.push_str(" [image]"),
+                            ContentPart::Audio { .. } => text.push_str(" [audio]"),
+                        }
+                    }
+                    text.push('\n');
+                }
+                ChatMessage::Assistant {
+                    content,
+                    tool_calls,
+                    ..
+                } => {
+                    if !content.is_empty() {
+                        text.push_str(&format!("[assistant] {content}\n"));
+                    }
+                    for tc in tool_calls {
+                        text.push_str(&format!("[tool call] {} {}\n", tc.name, tc.arguments));
+                    }
+                }
+                ChatMessage::Tool {
+                    content, is_error, ..
+                } => {
+                    let tag = if *is_error {
+                        "tool error"
+                    } else {
+                        "tool result"
+                    };
+                    let content = match content.char_indices().nth(MAX_TOOL_RESULT_CHARS) {
+                        Some((cut, _)) => format!("{}...(truncated)", &content[..cut]),
+                        None => content.clone(),
+                    };
+                    text.push_str(&format!("[{tag}] {content}\n"));
+                }
+                ChatMessage::System { .. } | ChatMessage::Compaction { .. } => {}
+            }
+        }
+        vec![
+            ChatMessage::system(SUMMARY_INSTRUCTIONS),
+            ChatMessage::user(text),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HeuristicCounter;
+    use pi_contracts::{NonEmptyString, ToolCall};
+
+    fn call(id: &str) -> ToolCall {
+        ToolCall {
+            id: NonEmptyString::new(id).unwrap(),
+            name: NonEmptyString::new("read").unwrap(),
+            arguments: serde_json::json!({"path": "a.rs"}),
+        }
+    }
+
+    fn long(s: &str) -> String {
+        s.repeat(400)
+    }
+
+    #[test]
+    fn plan_keeps_tool_pairs_and_projection_uses_summary() {
+        let mut tr = SessionEntry::chain([
+            ChatMessage::system("sys"),
+            ChatMessage::user(long("a")),
+            ChatMessage::assistant("", vec![call("c1")]),
+            ChatMessage::tool(NonEmptyString::new("c1").unwrap(), long("b")),
+            ChatMessage::assistant(long("c"), vec![]),
+            ChatMessage::user("next"),
+            ChatMessage::assistant("", vec![call("c2")]),
+            ChatMessage::tool(NonEmptyString::new("c2").unwrap(), long("d")),
+        ]);
+
+        // The newest tool result alone exceeds the budget, so the cut lands on its assistant.
+        let p = plan(&tr, 50, &HeuristicCounter::default()).unwrap();
+        assert_eq!(p.first_kept, 6);
+        assert_eq!(p.to_summarize.len(), 5);
+        assert!(p.previous_summary.is_none());
+
+        tr.extend(SessionEntry::chain([
+            ChatMessage::compaction("summary one", p.first_kept, 0),
+            ChatMessage::user("after"),
+        ]));
+        let ctx = context_messages(&tr);
+        assert_eq!(ctx.len(), 5);
+        assert_eq!(ctx[0], ChatMessage::system("sys"));
+        assert!(matches!(ctx[1], ChatMessage::Compaction { .. }));
+        assert!(
+            matches!(&ctx[2], ChatMessage::Assistant { tool_calls, .. } if tool_calls[0].id.as_str() == "c2")
+        );
+        assert_eq!(ctx[4], ChatMessage::user("after"));
+
+        // A second compaction builds on the first summary and skips the old entry.
+        let p = plan(&tr, 0, &HeuristicCounter::default()).unwrap();
+        assert_eq!(p.previous_summary.as_deref(), Some("summary one"));
+        assert_eq!(p.first_kept, 9);
+        assert_eq!(p.to_summarize.len(), 2);
+    }
+
+    #[test]
+    fn nothing_to_compact_when_everything_is_recent() {
+        let tr = SessionEntry::chain([
+            ChatMessage::user("hi"),
+            ChatMessage::assistant("hello", vec![]),
+        ]);
+        assert!(plan(&tr, 20_000, &HeuristicCounter::default()).is_none());
+        assert!(!CompactionConfig::default().should_compact(u32::MAX));
+        assert!(CompactionConfig::for_window(1_000).should_compact(900));
+    }
+}
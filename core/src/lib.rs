@@ -4,36 +4,188 @@
 //!
 //! `pi_core` MUST NOT do I/O. All I/O lives in `adapters/*`.
 
+mod budget;
+mod cancel;
+mod compaction;
+mod permissions;
+mod queue;
+mod replay;
+mod retry;
+mod schema;
+mod structured;
+mod subagent;
+mod tokenizer;
+mod toolset;
+
+pub use budget::RunBudget;
+pub use cancel::{CancellationToken, Cancelled};
+pub use compaction::{context_messages, CompactionConfig};
+pub use permissions::{
+    ApprovalDecision, Permission, PermissionPolicy, PermissionRule, ToolApprover,
+};
+pub use queue::MessageQueue;
+pub use replay::{FixtureStore, RecordingProvider, ReplayProvider};
+pub use retry::{RetryPolicy, RetryProvider, Sleeper};
+pub use schema::{validate_arguments, validate_value, ArgumentError};
+pub use structured::{JsonCompletion, JSON_ATTEMPTS};
+pub use subagent::SubAgentTool;
+pub use tokenizer::{BpeEncoding, BpeTokenizer, HeuristicCounter, TokenCounter, Tokenizers};
+pub use toolset::{ToolFilter, ToolSet, NAMESPACE_SEPARATOR};
+
 use async_trait::async_trait;
-use futures::{channel::mpsc, future::BoxFuture, stream::Stream};
+use budget::{millis, BudgetTracker};
+use futures::{
+    channel::mpsc,
+    future::{self, BoxFuture, Either},
+    stream::{BoxStream, Peekable, Stream, StreamExt},
+};
 use pi_contracts::{
-    ChatMessage, ChatRequest, ChatResponse, ChatStreamEvent, Context as AiContext, Model, ModelId,
-    PiError, ProviderId, SessionId, ToolCall, ToolName, ToolSpec,
+    AgentEvent, BudgetKind, ChatMessage, ChatRequest, ChatResponse, ChatStreamEvent, ContentPart,
+    Context as AiContext, CostBreakdown, FinishReason, InputModality, Model, ModelId, PiError,
+    ProviderId, QueuedMessageKind, ReasoningEffort, RequestParams, RunSummary, SessionEntry,
+    SessionId, StopReason, TokenCost, TokenUsage, ToolCall, ToolCallSummary, ToolChoice, ToolSpec,
 };
 use serde_json::Value as Json;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    future::Future,
     path::PathBuf,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex},
     task::{Context as TaskContext, Poll},
+    time::{Duration, Instant},
 };
 
-/// A transcript of messages.
-pub type Transcript = Vec<ChatMessage>;
+/// A transcript: messages with their provenance, each entry parented to the one before.
+pub type Transcript = Vec<SessionEntry>;
+
+/// An assistant reply without tool calls (the only kind that is continued when cut off).
+fn is_plain_reply(message: &ChatMessage) -> bool {
+    matches!(message, ChatMessage::Assistant { tool_calls, .. } if tool_calls.is_empty())
+}
+
+/// Appends the continuation `next` to the cut-off reply `prev`: text and thinking are joined,
+/// usage, cost and duration added up, and the tool calls and stop reason taken from `next`.
+fn extend_reply(prev: &mut SessionEntry, next: SessionEntry) {
+    if let (
+        ChatMessage::Assistant {
+            content,
+            tool_calls,
+            thinking,
+        },
+        ChatMessage::Assistant {
+            content: more,
+            tool_calls: calls,
+            thinking: more_thinking,
+        },
+    ) = (&mut prev.message, next.message)
+    {
+        content.push_str(&more);
+        *tool_calls = calls;
+        *thinking = match (thinking.take(), more_thinking) {
+            (Some(mut t), Some(more)) => {
+                t.text.push_str(&more.text);
+                t.signature = more.signature;
+                Some(t)
+            }
+            (t, more) => t.or(more),
+        };
+    }
+    prev.usage = match (prev.usage.take(), next.usage) {
+        (Some(mut u), Some(more)) => {
+            u += &more;
+            Some(u)
+        }
+        (u, more) => u.or(more),
+    };
+    prev.cost = match (prev.cost, next.cost) {
+        (Some(mut c), Some(more)) => {
+            c += &more;
+            Some(c)
+        }
+        (c, more) => c.or(more),
+    };
+    prev.duration_ms = Some(prev.duration_ms.unwrap_or(0) + next.duration_ms.unwrap_or(0));
+    prev.stop_reason = next.stop_reason;
+}
+
+fn unknown_tool(call: &ToolCall) -> PiError {
+    PiError::Tool(format!("unknown tool: {}", call.name))
+}
+
+/// Appends `entry` to `transcript`, parented to the current last entry.
+fn append(transcript: &mut Transcript, entry: SessionEntry) {
+    let parent_id = transcript.last().map(|e| e.id);
+    transcript.push(SessionEntry { parent_id, ..entry });
+}
 
 /// Execution context passed to tools.
 #[derive(Clone, Debug)]
 pub struct ToolContext {
     /// Current working directory (driving adapter decides).
     pub cwd: PathBuf,
+    /// Cancels the whole run; long-running tools should watch it and stop early.
+    pub cancel: CancellationToken,
+    /// Which tools the run offers the model; calls to other tools fail as unknown, without
+    /// asking the approver.
+    pub tools: ToolFilter,
+}
+
+impl ToolContext {
+    pub fn new(cwd: impl Into<PathBuf>) -> Self {
+        Self {
+            cwd: cwd.into(),
+            cancel: CancellationToken::new(),
+            tools: ToolFilter::all(),
+        }
+    }
+}
+
+/// The user message that starts a run: text plus attachments such as images.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UserInput {
+    pub text: String,
+    pub parts: Vec<ContentPart>,
+}
+
+impl UserInput {
+    pub fn with_parts(text: impl Into<String>, parts: Vec<ContentPart>) -> Self {
+        Self {
+            text: text.into(),
+            parts,
+        }
+    }
+}
+
+impl From<&str> for UserInput {
+    fn from(text: &str) -> Self {
+        Self::with_parts(text, vec![])
+    }
+}
+
+impl From<String> for UserInput {
+    fn from(text: String) -> Self {
+        Self::with_parts(text, vec![])
+    }
 }
 
 /// Tool execution result.
 #[derive(Clone, Debug)]
 pub struct ToolResult {
     pub content: String,
+    /// Structured data for front ends (a diff, an exit code); kept in the transcript but not sent
+    /// to the model.
     pub details: Option<Json>,
+    /// Extra content for the model after `content`, such as a screenshot.
+    pub parts: Vec<ContentPart>,
+    /// The tool ran but failed in a way the model should see as an error (e.g. a non-zero exit
+    /// code). Unlike returning `Err`, this keeps `details` and `parts`.
+    pub is_error: bool,
+    /// Provider usage incurred by the tool itself (e.g. a sub-agent); added to the run's totals
+    /// and budget.
+    pub usage: Option<TokenUsage>,
+    /// Cost of `usage`; estimated with the run's pricing when missing.
+    pub cost: Option<CostBreakdown>,
 }
 
 impl ToolResult {
@@ -41,14 +193,37 @@ impl ToolResult {
         Self {
             content: s.into(),
             details: None,
+            parts: vec![],
+            is_error: false,
+            usage: None,
+            cost: None,
+        }
+    }
+
+    /// A result flagged as an error.
+    pub fn error(s: impl Into<String>) -> Self {
+        Self {
+            is_error: true,
+            ..Self::text(s)
         }
     }
+
+    pub fn with_details(mut self, details: Json) -> Self {
+        self.details = Some(details);
+        self
+    }
 }
 
 /// Outbound port: chat completion provider.
 #[async_trait]
 pub trait ChatProvider: Send + Sync {
     async fn chat(&self, req: ChatRequest) -> Result<ChatResponse, PiError>;
+
+    /// Which provider answers (e.g. `openai`), recorded on the transcript entries of replies;
+    /// `None` if unknown.
+    fn id(&self) -> Option<ProviderId> {
+        None
+    }
 }
 
 /// Outbound port: streaming chat completion provider.
@@ -57,6 +232,24 @@ pub trait ChatProviderStream: Send + Sync {
     async fn chat_stream(&self, req: ChatRequest) -> Result<ChatStream, PiError>;
 }
 
+#[async_trait]
+impl<T: ChatProvider + ?Sized> ChatProvider for Arc<T> {
+    async fn chat(&self, req: ChatRequest) -> Result<ChatResponse, PiError> {
+        (**self).chat(req).await
+    }
+
+    fn id(&self) -> Option<ProviderId> {
+        (**self).id()
+    }
+}
+
+#[async_trait]
+impl<T: ChatProviderStream + ?Sized> ChatProviderStream for Arc<T> {
+    async fn chat_stream(&self, req: ChatRequest) -> Result<ChatStream, PiError> {
+        (**self).chat_stream(req).await
+    }
+}
+
 /// Convenience super-trait for providers that support both non-streaming and streaming.
 pub trait AiProvider: ChatProvider + ChatProviderStream {}
 impl<T: ChatProvider + ChatProviderStream> AiProvider for T {}
@@ -64,9 +257,13 @@ impl<T: ChatProvider + ChatProviderStream> AiProvider for T {}
 /// A stream of normalized events plus a retrievable final [`ChatResponse`].
 ///
 /// Pattern: consume deltas for UX, then call `.result().await` for the final message (possibly partial).
+///
+/// Dropping the stream (or calling [`ChatStream::abort`]) cancels the underlying request, provided
+/// the producer watches the token passed via [`ChatStream::with_cancel`].
 pub struct ChatStream {
-    events: mpsc::Receiver<ChatStreamEvent>,
+    events: Peekable<BoxStream<'static, ChatStreamEvent>>,
     result: Option<BoxFuture<'static, Result<ChatResponse, PiError>>>,
+    cancel: CancellationToken,
 }
 
 impl ChatStream {
@@ -75,11 +272,25 @@ impl ChatStream {
         result: BoxFuture<'static, Result<ChatResponse, PiError>>,
     ) -> Self {
         Self {
-            events,
+            events: events.boxed().peekable(),
             result: Some(result),
+            cancel: CancellationToken::new(),
         }
     }
 
+    /// Ties the stream to a producer-side token. On abort the producer should emit
+    /// `ChatStreamEvent::Error { reason: Aborted, .. }`, resolve the result with
+    /// [`PiError::Aborted`] and drop the request.
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Requests cancellation. Events already produced can still be drained.
+    pub fn abort(&self) {
+        self.cancel.cancel();
+    }
+
     /// Returns the final response. May be called after the stream is fully consumed.
     pub async fn result(&mut self) -> Result<ChatResponse, PiError> {
         let fut = self
@@ -89,6 +300,11 @@ impl ChatStream {
         fut.await
     }
 
+    /// Waits for the first event without consuming it; `None` if the stream ended without any.
+    pub(crate) async fn peek(&mut self) -> Option<&ChatStreamEvent> {
+        Pin::new(&mut self.events).peek().await
+    }
+
     /// Maps the final response (e.g. to inject cost tracking) without touching the event stream.
     pub fn map_result<F>(mut self, f: F) -> Self
     where
@@ -99,6 +315,19 @@ impl ChatStream {
         }
         self
     }
+
+    /// Calls `f` on each event as it is delivered, without changing the stream.
+    pub fn inspect_events<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&ChatStreamEvent) + Send + 'static,
+    {
+        let events = std::mem::replace(
+            &mut self.events,
+            futures::stream::empty().boxed().peekable(),
+        );
+        self.events = events.inspect(f).boxed().peekable();
+        self
+    }
 }
 
 impl Stream for ChatStream {
@@ -109,11 +338,26 @@ impl Stream for ChatStream {
     }
 }
 
+impl Drop for ChatStream {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
 /// Tool execution.
 #[async_trait]
 pub trait Tool: Send + Sync {
     fn spec(&self) -> ToolSpec;
+    /// Runs the tool. The agent has already checked `args` against `spec().parameters` (see
+    /// [`validate_arguments`]); invalid calls never get here.
     async fn execute(&self, args: Json, ctx: ToolContext) -> Result<ToolResult, PiError>;
+
+    /// Whether this tool may run concurrently with other tool calls from the same step.
+    ///
+    /// Tools that mutate shared state (e.g. files) should return `false`; they then run alone.
+    fn parallel_safe(&self) -> bool {
+        true
+    }
 }
 
 /// Outbound port: persist sessions.
@@ -123,30 +367,41 @@ pub trait SessionStore: Send + Sync {
     async fn save(&self, id: SessionId, transcript: &Transcript) -> Result<(), PiError>;
 }
 
-/// Tool set (registry + specs).
-#[derive(Clone, Default)]
-pub struct ToolSet {
-    tools: Vec<Arc<dyn Tool>>,
+/// What the agent does when a tool call fails (unknown tool, bad arguments, tool error).
+///
+/// In every case the failure is recorded as an error tool result, so the transcript always pairs
+/// each assistant tool call with a [`ChatMessage::Tool`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToolErrorPolicy {
+    /// Hand the error to the model and keep going. Only non-tool failures (provider errors) end
+    /// the run.
+    #[default]
+    Report,
+    /// Treat every tool failure as fatal: record it, then abort the run with the error.
+    Abort,
 }
 
-impl ToolSet {
-    pub fn new(tools: impl IntoIterator<Item = Arc<dyn Tool>>) -> Self {
-        Self {
-            tools: tools.into_iter().collect(),
-        }
-    }
+/// What the agent does when a run reaches `max_steps`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MaxStepsPolicy {
+    /// Make the last step a wrap-up: tool calls are disabled ([`ToolChoice::None`]) and the model
+    /// is asked to summarize its progress and the remaining work. The run completes with
+    /// [`RunSummary::truncated`] set. With `max_steps` below 2 there is no step to spare, so it
+    /// acts like `Stop`.
+    #[default]
+    WrapUp,
+    /// Stop after the last step with [`StopReason::MaxSteps`].
+    Stop,
+}
 
-    pub fn specs(&self) -> Vec<ToolSpec> {
-        self.tools.iter().map(|t| t.spec()).collect()
-    }
+/// Sent (but not recorded in the transcript) with the wrap-up request of [`MaxStepsPolicy::WrapUp`].
+const WRAP_UP_PROMPT: &str = "You have reached the step limit for this task and can no longer \
+call tools. Reply to the user now: summarize what you have done and found so far, and list what \
+remains to be done.";
 
-    pub fn get(&self, name: &ToolName) -> Option<Arc<dyn Tool>> {
-        self.tools
-            .iter()
-            .find(|t| t.spec().name.as_str() == name.as_str())
-            .cloned()
-    }
-}
+/// Sent (but not recorded in the transcript) after a reply cut off by the output token limit.
+const CONTINUE_PROMPT: &str = "Your previous reply was cut off by the output token limit. \
+Continue exactly where it stopped, without repeating anything.";
 
 /// Agent configuration.
 #[derive(Clone, Debug)]
@@ -154,8 +409,34 @@ pub struct AgentConfig {
     pub model: ModelId,
     pub system_prompt: Option<String>,
     pub max_steps: usize,
+    pub max_steps_policy: MaxStepsPolicy,
     pub temperature: Option<f32>,
     pub max_tokens: Option<u32>,
+    /// For reasoning models (see [`Model::reasoning`]).
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// How many times in a row a reply cut off by `max_tokens` ([`FinishReason::Length`]) is
+    /// continued with another request; `0` accepts cut-off replies as they are.
+    pub max_continuations: usize,
+    /// Tool choice for each request until the model has called a tool in the run; later requests
+    /// use the provider default. Set `Required` or `Tool` to force a tool-first workflow (reset
+    /// so the model can then answer), or `None` to keep the model from calling tools at all.
+    pub tool_choice: Option<ToolChoice>,
+    /// Sent as [`ChatRequest::parallel_tool_calls`].
+    pub parallel_tool_calls: Option<bool>,
+    /// `top_p`, stop sequences, seed, penalties, user id/metadata and provider passthrough,
+    /// sent with every agent step.
+    pub params: RequestParams,
+    pub tool_errors: ToolErrorPolicy,
+    /// Upper bound on tool calls executed concurrently within one step (`1` = sequential).
+    pub max_parallel_tools: usize,
+    /// Which tool calls may run; `Ask` needs an approver (see [`Agent::with_approver`]).
+    pub permissions: PermissionPolicy,
+    /// Automatic context compaction (off unless `context_window` is set).
+    pub compaction: CompactionConfig,
+    /// Token, cost and time limits applied to each run.
+    pub budget: RunBudget,
+    /// Prices usage for the cost budget when the provider doesn't report a cost.
+    pub pricing: TokenCost,
 }
 
 impl AgentConfig {
@@ -164,19 +445,140 @@ impl AgentConfig {
             model,
             system_prompt: None,
             max_steps: 32,
+            max_steps_policy: MaxStepsPolicy::default(),
             temperature: None,
             max_tokens: None,
+            reasoning_effort: None,
+            max_continuations: 2,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            params: RequestParams::default(),
+            tool_errors: ToolErrorPolicy::default(),
+            max_parallel_tools: 4,
+            permissions: PermissionPolicy::allow_all(),
+            compaction: CompactionConfig::default(),
+            budget: RunBudget::unlimited(),
+            pricing: TokenCost::free(),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum EventSink {
+    Channel(mpsc::UnboundedSender<AgentEvent>),
+    Callback(Arc<dyn Fn(&AgentEvent) + Send + Sync>),
+}
+
+/// Fan-out of [`AgentEvent`]s to any number of subscribers.
+///
+/// Cheap to clone; clones share the same subscriber list.
+#[derive(Clone, Default)]
+pub struct AgentEvents {
+    sinks: Arc<Mutex<Vec<EventSink>>>,
+}
+
+impl AgentEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribes via an unbounded channel. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<AgentEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.sinks.lock().unwrap().push(EventSink::Channel(tx));
+        rx
+    }
+
+    /// Subscribes a synchronous callback (invoked inline, in emission order). It may emit or
+    /// subscribe on the same `AgentEvents`.
+    pub fn on_event(&self, f: impl Fn(&AgentEvent) + Send + Sync + 'static) {
+        self.sinks
+            .lock()
+            .unwrap()
+            .push(EventSink::Callback(Arc::new(f)));
+    }
+
+    pub fn emit(&self, ev: AgentEvent) {
+        // Callbacks run without the lock held, so they can't deadlock on it.
+        let sinks = self.sinks.lock().unwrap().clone();
+        let mut closed = false;
+        for sink in &sinks {
+            match sink {
+                EventSink::Channel(tx) => closed |= tx.unbounded_send(ev.clone()).is_err(),
+                EventSink::Callback(f) => f(&ev),
+            }
+        }
+        if closed {
+            self.sinks
+                .lock()
+                .unwrap()
+                .retain(|sink| !matches!(sink, EventSink::Channel(tx) if tx.is_closed()));
+        }
+    }
+}
+
+/// What a run has done so far; becomes its [`RunSummary`].
+struct RunState {
+    budget: BudgetTracker,
+    steps: usize,
+    tool_calls: Vec<ToolCallSummary>,
+    truncated: bool,
+}
+
+impl RunState {
+    fn new(budget: BudgetTracker) -> Self {
+        Self {
+            budget,
+            steps: 0,
+            tool_calls: vec![],
+            truncated: false,
+        }
+    }
+
+    /// Records a tool call that was not executed, answering it with a `why` error.
+    fn skip(&mut self, transcript: &mut Transcript, call: &ToolCall, why: &str) {
+        append(
+            transcript,
+            SessionEntry::new(ChatMessage::tool_error(call.id.clone(), why)),
+        );
+        self.tool_calls.push(ToolCallSummary {
+            id: call.id.clone(),
+            name: call.name.clone(),
+            duration_ms: 0,
+            error: Some(why.to_string()),
+        });
+    }
+
+    fn summary(&self, stop_reason: StopReason) -> RunSummary {
+        let (usage, cost) = self.budget.totals();
+        RunSummary {
+            stop_reason,
+            steps: self.steps,
+            tool_calls: self.tool_calls.clone(),
+            usage,
+            cost,
+            budget: self.budget.usage(),
+            truncated: self.truncated,
         }
     }
 }
 
 /// Agent runtime.
 ///
-/// Drives a [`ChatProvider`] and executes tool calls via a [`ToolSet`].
+/// Drives a [`ChatProvider`] and executes tool calls via a [`ToolSet`]. Progress is published as
+/// [`AgentEvent`]s (see [`Agent::subscribe`]).
 pub struct Agent<P: ChatProvider> {
     provider: P,
     tools: ToolSet,
     cfg: AgentConfig,
+    events: AgentEvents,
+    approver: Option<Arc<dyn ToolApprover>>,
+    tokens: Arc<dyn TokenCounter>,
+    queue: MessageQueue,
+    /// Times the run's time budget while a step or tool calls are in flight.
+    sleeper: Option<Arc<dyn Sleeper>>,
+    /// Tools the user approved with [`ApprovalDecision::ApproveAlways`].
+    always_approved: Mutex<HashSet<String>>,
 }
 
 impl<P: ChatProvider> Agent<P> {
@@ -185,34 +587,247 @@ impl<P: ChatProvider> Agent<P> {
             provider,
             tools,
             cfg,
+            events: AgentEvents::new(),
+            approver: None,
+            tokens: Arc::new(HeuristicCounter::default()),
+            queue: MessageQueue::new(),
+            sleeper: None,
+            always_approved: Mutex::new(HashSet::new()),
         }
     }
 
+    /// Sets the port consulted for tool calls the permission policy marks as `Ask`.
+    /// Without an approver such calls are denied.
+    pub fn with_approver(mut self, approver: Arc<dyn ToolApprover>) -> Self {
+        self.approver = Some(approver);
+        self
+    }
+
+    /// Sets the counter used to size prompts for compaction (default: [`HeuristicCounter`]).
+    pub fn with_token_counter(mut self, tokens: Arc<dyn TokenCounter>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// Sets the timer that enforces [`RunBudget::max_duration`] mid-step: at the deadline the
+    /// provider request or tool calls in flight are cancelled. Without one the time limit is only
+    /// checked between steps.
+    pub fn with_sleeper(mut self, sleeper: Arc<dyn Sleeper>) -> Self {
+        self.sleeper = Some(sleeper);
+        self
+    }
+
+    /// Publishes to `events` instead of a fresh set, e.g. to share them with a [`RetryProvider`].
+    pub fn with_events(mut self, events: AgentEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Shares `queue` with a front end instead of the agent's own (see [`Agent::queue`]).
+    pub fn with_message_queue(mut self, queue: MessageQueue) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    /// Steering and follow-up messages; push to it (or a clone) while a run is in progress.
+    pub fn queue(&self) -> &MessageQueue {
+        &self.queue
+    }
+
+    pub fn events(&self) -> &AgentEvents {
+        &self.events
+    }
+
+    /// The agent's tools; register or unregister through it (or a clone) at any time.
+    pub fn tools(&self) -> &ToolSet {
+        &self.tools
+    }
+
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<AgentEvent> {
+        self.events.subscribe()
+    }
+
     /// Runs one user input to quiescence (until the model stops issuing tool calls or `max_steps` is hit).
+    ///
+    /// At `max_steps` the run wraps up or stops, as set by [`AgentConfig::max_steps_policy`].
+    /// Runs that stop early (at `max_steps`, when `cfg.budget` runs out, or when `ctx.cancel` is
+    /// cancelled) still return a [`RunSummary`], with the matching [`StopReason`]; errors are
+    /// failures such as a provider error. Cancelling drops the in-flight provider request and
+    /// running tools, and records `"aborted"` results for unfinished tool calls. Every run ends
+    /// with an [`AgentEvent::RunEnd`].
     pub async fn run_to_end(
         &self,
         transcript: &mut Transcript,
-        user_input: &str,
+        user_input: impl Into<UserInput>,
+        ctx: ToolContext,
+    ) -> Result<RunSummary, PiError> {
+        self.run_tracked(transcript, user_input.into(), ctx).await.0
+    }
+
+    /// [`Agent::run_to_end`], also returning the summary when the run failed (with
+    /// [`StopReason::Error`]; the `Err` is the cause).
+    pub async fn run_tracked(
+        &self,
+        transcript: &mut Transcript,
+        user_input: UserInput,
+        ctx: ToolContext,
+    ) -> (Result<RunSummary, PiError>, RunSummary) {
+        self.run_loop(transcript, user_input, ctx, |req| {
+            Box::pin(async move {
+                let resp = self.provider.chat(req).await?;
+                if let Some(usage) = &resp.usage {
+                    self.events.emit(AgentEvent::Usage {
+                        usage: usage.clone(),
+                    });
+                }
+                Ok(resp)
+            })
+        })
+        .await
+    }
+
+    async fn run_loop<'a, F>(
+        &'a self,
+        transcript: &mut Transcript,
+        user_input: UserInput,
         ctx: ToolContext,
-    ) -> Result<(), PiError> {
+        step: F,
+    ) -> (Result<RunSummary, PiError>, RunSummary)
+    where
+        F: Fn(ChatRequest) -> BoxFuture<'a, Result<ChatResponse, PiError>>,
+    {
+        let mut run = RunState::new(BudgetTracker::new(
+            self.cfg.budget.clone(),
+            self.cfg.pricing,
+        ));
+        let r = match self
+            .run_steps(transcript, user_input, &ctx, &step, &mut run)
+            .await
+        {
+            Err(PiError::Aborted) => Ok(StopReason::Aborted),
+            r => r,
+        };
+        let summary = run.summary(match &r {
+            Ok(reason) => reason.clone(),
+            Err(_) => StopReason::Error,
+        });
+        self.events.emit(AgentEvent::RunEnd {
+            summary: Box::new(summary.clone()),
+            error: r.as_ref().err().map(PiError::info),
+        });
+        (r.map(|_| summary.clone()), summary)
+    }
+
+    async fn run_steps<'a, F>(
+        &'a self,
+        transcript: &mut Transcript,
+        user_input: UserInput,
+        ctx: &ToolContext,
+        step: &F,
+        run: &mut RunState,
+    ) -> Result<StopReason, PiError>
+    where
+        F: Fn(ChatRequest) -> BoxFuture<'a, Result<ChatResponse, PiError>>,
+    {
         if transcript.is_empty() {
             if let Some(sys) = &self.cfg.system_prompt {
-                transcript.push(ChatMessage::system(sys));
+                append(transcript, SessionEntry::new(ChatMessage::system(sys)));
             }
         }
 
-        transcript.push(ChatMessage::user(user_input));
+        append(
+            transcript,
+            SessionEntry::new(ChatMessage::user_with_parts(
+                user_input.text,
+                user_input.parts,
+            )),
+        );
+
+        // `n` counts steps since the last user input; a follow-up starts over.
+        let mut n = 0;
+        // Requests in a row spent continuing a cut-off reply.
+        let mut continuations = 0;
+        // `cfg.tool_choice` applies until the first tool call.
+        let mut tool_called = false;
+        while n < self.cfg.max_steps {
+            if ctx.cancel.is_cancelled() {
+                return Ok(StopReason::Aborted);
+            }
+            if let Some(kind) = run.budget.exceeded() {
+                return Ok(self.budget_exceeded(kind, &run.budget));
+            }
+            for content in self.queue.take_steering() {
+                self.push_queued(transcript, content, QueuedMessageKind::Steering);
+            }
+            self.events.emit(AgentEvent::TurnStart { step: n });
+
+            let wrap_up = self.cfg.max_steps_policy == MaxStepsPolicy::WrapUp
+                && self.cfg.max_steps >= 2
+                && n + 1 == self.cfg.max_steps;
+            let tools = self.tools.specs_matching(&ctx.tools);
+            let tool_choice = if wrap_up {
+                Some(ToolChoice::None)
+            } else if tool_called {
+                None
+            } else {
+                self.cfg.tool_choice.clone()
+            };
+            if self
+                .cfg
+                .compaction
+                .should_compact(self.prompt_tokens(transcript, &tools))
+            {
+                let work = ctx.cancel.child_token();
+                let summary = work.run_until_cancelled(self.summarize(transcript, &tools));
+                match self.with_deadline(&run.budget, &work, summary).await {
+                    Some(r) => {
+                        if let Some(resp) = r? {
+                            run.budget.record(&resp);
+                        }
+                    }
+                    None => return Ok(self.interrupted(ctx, &run.budget)),
+                };
+            }
 
-        for _ in 0..self.cfg.max_steps {
+            let mut messages = context_messages(transcript);
+            if continuations > 0 {
+                messages.push(ChatMessage::user(CONTINUE_PROMPT));
+            }
+            if wrap_up {
+                messages.push(ChatMessage::user(WRAP_UP_PROMPT));
+            }
             let req = ChatRequest {
                 model: self.cfg.model.clone(),
-                messages: transcript.clone(),
-                tools: self.tools.specs(),
+                messages,
+                tools,
                 temperature: self.cfg.temperature,
                 max_tokens: self.cfg.max_tokens,
+                reasoning_effort: self.cfg.reasoning_effort,
+                tool_choice,
+                parallel_tool_calls: self.cfg.parallel_tool_calls,
+                response_format: None,
+                params: self.cfg.params.clone(),
             };
 
-            let resp = self.provider.chat(req).await?;
+            // Dropping the step future drops the provider request (and aborts its stream).
+            let started = Instant::now();
+            let work = ctx.cancel.child_token();
+            let resp = match self
+                .with_deadline(&run.budget, &work, work.run_until_cancelled(step(req)))
+                .await
+            {
+                Some(r) => r?,
+                None => return Ok(self.interrupted(ctx, &run.budget)),
+            };
+            let took = started.elapsed();
+            run.steps += 1;
+            run.budget.record(&resp);
+            if resp.finish_reason == Some(FinishReason::ContentFilter) {
+                return Err(PiError::Provider(
+                    "the reply was blocked by the provider's content filter".into(),
+                ));
+            }
+            let cut_off = resp.finish_reason == Some(FinishReason::Length);
             let assistant = match &resp.assistant {
                 ChatMessage::Assistant { .. } => resp.assistant,
                 _ => {
@@ -227,89 +842,569 @@ impl<P: ChatProvider> Agent<P> {
                 _ => vec![],
             };
 
-            transcript.push(assistant);
+            self.events.emit(AgentEvent::MessageEnd {
+                message: assistant.clone(),
+            });
+            let entry = SessionEntry {
+                duration_ms: Some(millis(took)),
+                ..self.reply_entry(assistant, &resp.usage, resp.cost, resp.finish_reason)
+            };
+            // A continuation completes the cut-off reply it follows rather than adding another.
+            match transcript.last_mut() {
+                Some(prev) if continuations > 0 && is_plain_reply(&prev.message) => {
+                    extend_reply(prev, entry)
+                }
+                _ => append(transcript, entry),
+            }
+
+            if cut_off
+                && tool_calls.is_empty()
+                && !wrap_up
+                && continuations < self.cfg.max_continuations
+            {
+                continuations += 1;
+                self.events.emit(AgentEvent::TurnEnd { step: n });
+                n += 1;
+                continue;
+            }
+            continuations = 0;
+            tool_called |= !tool_calls.is_empty();
+
+            if tool_calls.is_empty() || wrap_up {
+                // Only a wrap-up answer that calls tools anyway gets here with tool calls.
+                for call in &tool_calls {
+                    run.skip(transcript, call, "skipped: step limit reached");
+                }
+                self.events.emit(AgentEvent::TurnEnd { step: n });
+                run.truncated = wrap_up;
+                if !wrap_up && self.queue.has_steering() {
+                    n += 1;
+                    continue;
+                }
+                match self.queue.pop_follow_up() {
+                    Some(content) => {
+                        self.push_queued(transcript, content, QueuedMessageKind::FollowUp);
+                        n = 0;
+                        continue;
+                    }
+                    None => return Ok(StopReason::Completed),
+                }
+            }
 
-            if tool_calls.is_empty() {
-                return Ok(());
+            if let Some(kind) = run.budget.exceeded() {
+                for call in &tool_calls {
+                    run.skip(transcript, call, "skipped: budget exceeded");
+                }
+                self.events.emit(AgentEvent::TurnEnd { step: n });
+                return Ok(self.budget_exceeded(kind, &run.budget));
             }
 
-            for call in tool_calls {
-                self.exec_tool_call(transcript, call, ctx.clone()).await?;
+            let work = ToolContext {
+                cancel: ctx.cancel.child_token(),
+                ..ctx.clone()
+            };
+            let mut results = self
+                .with_deadline(
+                    &run.budget,
+                    &work.cancel,
+                    self.exec_tool_calls(&tool_calls, &work),
+                )
+                .await
+                .into_iter();
+            let mut fatal = None;
+            for call in &tool_calls {
+                let Some((out, took)) = results.next() else {
+                    let why = if work.cancel.is_cancelled() {
+                        "aborted"
+                    } else if fatal.is_some() {
+                        "skipped: an earlier tool call failed"
+                    } else {
+                        "skipped: the user sent a new message"
+                    };
+                    run.skip(transcript, call, why);
+                    continue;
+                };
+                let error = match &out {
+                    Ok(r) if r.is_error => Some(r.content.clone()),
+                    Ok(_) => None,
+                    Err(e) => Some(e.to_string()),
+                };
+                run.tool_calls.push(ToolCallSummary {
+                    id: call.id.clone(),
+                    name: call.name.clone(),
+                    duration_ms: millis(took),
+                    error: error.clone(),
+                });
+                match out {
+                    Ok(out) => {
+                        if let Some(usage) = &out.usage {
+                            run.budget.record_usage(usage, out.cost.as_ref());
+                        }
+                        append(
+                            transcript,
+                            SessionEntry {
+                                duration_ms: Some(millis(took)),
+                                ..SessionEntry::new(ChatMessage::Tool {
+                                    tool_call_id: call.id.clone(),
+                                    content: out.content,
+                                    is_error: out.is_error,
+                                    details: out.details,
+                                    parts: out.parts,
+                                })
+                            },
+                        );
+                    }
+                    Err(e) => {
+                        append(
+                            transcript,
+                            SessionEntry {
+                                duration_ms: Some(millis(took)),
+                                ..SessionEntry::new(ChatMessage::tool_error(
+                                    call.id.clone(),
+                                    error.unwrap_or_default(),
+                                ))
+                            },
+                        );
+                        if self.cfg.tool_errors == ToolErrorPolicy::Abort && fatal.is_none() {
+                            fatal = Some(e);
+                        }
+                    }
+                }
             }
+            self.events.emit(AgentEvent::TurnEnd { step: n });
+            if work.cancel.is_cancelled() {
+                return Ok(self.interrupted(ctx, &run.budget));
+            }
+            if let Some(e) = fatal {
+                return Err(e);
+            }
+            n += 1;
         }
 
-        Err(PiError::Provider("max_steps reached".into()))
+        Ok(StopReason::MaxSteps)
     }
 
-    async fn exec_tool_call(
+    fn budget_exceeded(&self, kind: BudgetKind, budget: &BudgetTracker) -> StopReason {
+        self.events.emit(AgentEvent::BudgetExceeded {
+            kind,
+            usage: budget.usage(),
+        });
+        StopReason::Budget { kind }
+    }
+
+    /// Runs `fut`, cancelling `work` once the run's time budget is spent (given a sleeper).
+    /// `fut` is still awaited afterwards: it should watch `work` and wind down.
+    async fn with_deadline<F: Future>(
         &self,
-        transcript: &mut Transcript,
-        call: ToolCall,
-        ctx: ToolContext,
-    ) -> Result<(), PiError> {
-        let tool = self
-            .tools
-            .get(&call.name)
-            .ok_or_else(|| PiError::Tool(format!("unknown tool: {}", call.name)))?;
+        budget: &BudgetTracker,
+        work: &CancellationToken,
+        fut: F,
+    ) -> F::Output {
+        let (Some(sleeper), Some(left)) = (&self.sleeper, budget.time_left()) else {
+            return fut.await;
+        };
+        let deadline = async move {
+            sleeper.sleep(left).await;
+            work.cancel();
+        };
+        match future::select(std::pin::pin!(fut), std::pin::pin!(deadline)).await {
+            Either::Left((out, _)) => out,
+            Either::Right(((), fut)) => fut.await,
+        }
+    }
 
-        let out = tool.execute(call.arguments, ctx).await?;
-        transcript.push(ChatMessage::tool(call.id, out.content));
-        Ok(())
+    /// Why work cut short through a child of `ctx.cancel` stopped: the run was cancelled, or
+    /// else its deadline passed.
+    fn interrupted(&self, ctx: &ToolContext, budget: &BudgetTracker) -> StopReason {
+        if ctx.cancel.is_cancelled() {
+            StopReason::Aborted
+        } else {
+            self.budget_exceeded(BudgetKind::Time, budget)
+        }
     }
-}
 
-/// Pure model catalog (built-in + extension).
-#[derive(Clone, Default)]
-pub struct ModelCatalog {
-    models: Vec<Model>,
-}
+    fn push_queued(&self, transcript: &mut Transcript, content: String, kind: QueuedMessageKind) {
+        append(
+            transcript,
+            SessionEntry::new(ChatMessage::user(content.clone())),
+        );
+        self.events
+            .emit(AgentEvent::QueuedMessage { content, kind });
+    }
 
-impl ModelCatalog {
-    pub fn new(models: impl IntoIterator<Item = Model>) -> Self {
-        Self {
-            models: models.into_iter().collect(),
+    /// A transcript entry for `message`, produced by the configured model. The cost is estimated
+    /// with `cfg.pricing` when the provider reports none; without pricing it stays unknown.
+    fn reply_entry(
+        &self,
+        message: ChatMessage,
+        usage: &Option<TokenUsage>,
+        cost: Option<CostBreakdown>,
+        stop_reason: Option<FinishReason>,
+    ) -> SessionEntry {
+        SessionEntry {
+            provider: self.provider.id(),
+            model: Some(self.cfg.model.clone()),
+            usage: usage.clone(),
+            cost: cost.or_else(|| {
+                let pricing = Some(self.cfg.pricing).filter(|p| !p.is_free());
+                Some(pricing?.estimate_usd(usage.as_ref()?))
+            }),
+            stop_reason,
+            ..SessionEntry::new(message)
         }
     }
 
-    /// Small built-in catalog for bootstrapping.
+    /// Estimated prompt size of the next request.
+    fn prompt_tokens(&self, transcript: &Transcript, tools: &[ToolSpec]) -> u32 {
+        let n = self.tokens.count_messages(&context_messages(transcript))
+            + self.tokens.count_tool_specs(tools);
+        u32::try_from(n).unwrap_or(u32::MAX)
+    }
+
+    /// Summarizes older turns into a [`ChatMessage::Compaction`] entry, keeping roughly
+    /// `compaction.keep_recent_tokens` of recent context verbatim.
     ///
-    /// Full parity with upstream's generated catalog is a later drop; this provides the *mechanism*
-    /// for model discovery (list + lookup + extension).
-    pub fn builtin() -> Self {
-        use pi_contracts::{ApiKind, InputModality, NonEmptyString, TokenCost};
+    /// Runs automatically before a step once the prompt nears the context window; call it directly
+    /// for a manual compaction. Returns `false` if there was nothing old enough to summarize.
+    pub async fn compact(&self, transcript: &mut Transcript) -> Result<bool, PiError> {
+        Ok(self
+            .summarize(transcript, &self.tools.specs())
+            .await?
+            .is_some())
+    }
 
-        let m = |provider: &str,
-                 id: &str,
-                 api: ApiKind,
-                 name: &str,
-                 cost: TokenCost,
-                 ctx: u32,
-                 max: u32,
-                 input: Vec<InputModality>,
-                 reasoning: bool,
-                 base: Option<&str>| {
-            Model::new(
-                NonEmptyString::new(provider).unwrap(),
-                NonEmptyString::new(id).unwrap(),
-                api,
-                name,
-                cost,
-                ctx,
-                max,
-                input,
-                reasoning,
-                base.map(|s| s.to_string()),
-            )
+    /// [`Agent::compact`], returning the summarization response for usage accounting. `tools`
+    /// are the specs offered alongside the transcript, for the token estimate.
+    async fn summarize(
+        &self,
+        transcript: &mut Transcript,
+        tools: &[ToolSpec],
+    ) -> Result<Option<ChatResponse>, PiError> {
+        let tokens_before = self.prompt_tokens(transcript, tools);
+        let Some(plan) = compaction::plan(
+            transcript,
+            self.cfg.compaction.keep_recent_tokens,
+            self.tokens.as_ref(),
+        ) else {
+            return Ok(None);
         };
 
-        Self::new([
-            m(
-                "openai",
-                "gpt-4o-mini",
-                ApiKind::OpenAiCompletions,
-                "GPT-4o mini",
-                TokenCost::free(),
-                128_000,
+        let resp = self
+            .provider
+            .chat(ChatRequest {
+                model: self.cfg.model.clone(),
+                messages: plan.summary_request(),
+                tools: vec![],
+                temperature: None,
+                max_tokens: None,
+                reasoning_effort: None,
+                tool_choice: None,
+                parallel_tool_calls: None,
+                response_format: None,
+                params: RequestParams::default(),
+            })
+            .await?;
+        if let Some(usage) = &resp.usage {
+            self.events.emit(AgentEvent::Usage {
+                usage: usage.clone(),
+            });
+        }
+        let summary = match &resp.assistant {
+            ChatMessage::Assistant { content, .. } if !content.trim().is_empty() => content.clone(),
+            _ => {
+                return Err(PiError::Provider(
+                    "compaction returned an empty summary".into(),
+                ))
+            }
+        };
+
+        append(
+            transcript,
+            self.reply_entry(
+                ChatMessage::compaction(summary, plan.first_kept, tokens_before),
+                &resp.usage,
+                resp.cost,
+                resp.finish_reason,
+            ),
+        );
+        self.events.emit(AgentEvent::Compaction {
+            tokens_before,
+            tokens_after: self.prompt_tokens(transcript, tools),
+        });
+        Ok(Some(resp))
+    }
+
+    /// Executes one step's tool calls and returns their results in call order.
+    ///
+    /// Consecutive parallel-safe calls run concurrently in batches of up to `max_parallel_tools`;
+    /// a call to a tool that is not parallel-safe forms a batch of its own. Under
+    /// [`ToolErrorPolicy::Abort`] nothing after the first failing batch is executed, and a queued
+    /// steering message skips the batches after the current one, so the result list may be
+    /// shorter than `calls`. Each result comes with the time the call took.
+    async fn exec_tool_calls(
+        &self,
+        calls: &[ToolCall],
+        ctx: &ToolContext,
+    ) -> Vec<(Result<ToolResult, PiError>, Duration)> {
+        let parallel_safe =
+            |c: &ToolCall| self.tools.get(&c.name).is_none_or(|t| t.parallel_safe());
+        let limit = self.cfg.max_parallel_tools.max(1);
+
+        let mut out = Vec::with_capacity(calls.len());
+        let mut i = 0;
+        while i < calls.len() {
+            let end = if parallel_safe(&calls[i]) {
+                i + calls[i..]
+                    .iter()
+                    .take(limit)
+                    .take_while(|c| parallel_safe(c))
+                    .count()
+            } else {
+                i + 1
+            };
+            // Approvals are asked one at a time, before any call in the batch starts, and only
+            // for tools the run offers.
+            let mut permits = Vec::with_capacity(end - i);
+            for c in &calls[i..end] {
+                permits.push(if self.offers(c, ctx) {
+                    self.authorize(c, &ctx.cancel).await
+                } else {
+                    Err(unknown_tool(c))
+                });
+            }
+            let batch = futures::future::join_all(
+                calls[i..end]
+                    .iter()
+                    .zip(permits)
+                    .map(|(c, permit)| self.exec_tool_call(c, permit, ctx.clone())),
+            )
+            .await;
+            let failed = batch.iter().any(|(r, _)| r.is_err());
+            out.extend(batch);
+            i = end;
+            if ctx.cancel.is_cancelled()
+                || (failed && self.cfg.tool_errors == ToolErrorPolicy::Abort)
+                || self.queue.has_steering()
+            {
+                break;
+            }
+        }
+        out
+    }
+
+    /// Whether `call` names a registered tool that the run's filter lets through.
+    fn offers(&self, call: &ToolCall, ctx: &ToolContext) -> bool {
+        self.tools.get(&call.name).is_some() && ctx.tools.allows(call.name.as_str())
+    }
+
+    /// Applies the permission policy. `Ask` goes to the approver; denials become tool errors.
+    async fn authorize(&self, call: &ToolCall, cancel: &CancellationToken) -> Result<(), PiError> {
+        let denied = |why: String| Err(PiError::Tool(format!("permission denied: {why}")));
+        match self.cfg.permissions.evaluate(call) {
+            Permission::Allow => Ok(()),
+            Permission::Deny => denied(format!("`{}` call blocked by policy", call.name)),
+            Permission::Ask => {
+                if self
+                    .always_approved
+                    .lock()
+                    .unwrap()
+                    .contains(call.name.as_str())
+                {
+                    return Ok(());
+                }
+                let Some(approver) = &self.approver else {
+                    return denied(format!(
+                        "`{}` call requires approval but no approver is configured",
+                        call.name
+                    ));
+                };
+                let decision = cancel
+                    .run_until_cancelled(approver.approve(call))
+                    .await
+                    .ok_or(PiError::Aborted)??;
+                match decision {
+                    ApprovalDecision::Approve => Ok(()),
+                    ApprovalDecision::ApproveAlways => {
+                        self.always_approved
+                            .lock()
+                            .unwrap()
+                            .insert(call.name.as_str().to_string());
+                        Ok(())
+                    }
+                    ApprovalDecision::Deny => {
+                        denied(format!("`{}` call rejected by user", call.name))
+                    }
+                }
+            }
+        }
+    }
+
+    async fn exec_tool_call(
+        &self,
+        call: &ToolCall,
+        permit: Result<(), PiError>,
+        ctx: ToolContext,
+    ) -> (Result<ToolResult, PiError>, Duration) {
+        let started = Instant::now();
+        self.events.emit(AgentEvent::ToolExecutionStart {
+            id: call.id.clone(),
+            name: call.name.clone(),
+            arguments: call.arguments.clone(),
+        });
+
+        let cancel = ctx.cancel.clone();
+        let out = match (permit, self.tools.resolve(&call.name)) {
+            (Err(e), _) => Err(e),
+            (Ok(()), Some((tool, spec))) => {
+                match validate_arguments(&spec.parameters, call.arguments.clone()) {
+                    Ok(args) => cancel
+                        .run_until_cancelled(tool.execute(args, ctx))
+                        .await
+                        .unwrap_or(Err(PiError::Aborted)),
+                    Err(errors) => Err(PiError::Tool(format!(
+                        "invalid arguments for `{}`: {}",
+                        call.name,
+                        errors
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join("; ")
+                    ))),
+                }
+            }
+            (Ok(()), None) => Err(unknown_tool(call)),
+        };
+
+        let (content, is_error, details) = match &out {
+            Ok(r) => (r.content.clone(), r.is_error, r.details.clone()),
+            Err(e) => (e.to_string(), true, None),
+        };
+        self.events.emit(AgentEvent::ToolExecutionEnd {
+            id: call.id.clone(),
+            name: call.name.clone(),
+            content,
+            is_error,
+            details,
+        });
+        (out, started.elapsed())
+    }
+}
+
+impl<P: AiProvider> Agent<P> {
+    /// Like [`Agent::run_to_end`], but drives the provider through [`ChatProviderStream`] and
+    /// forwards text/tool-call deltas as they arrive. The resulting transcript is identical.
+    pub async fn run_stream(
+        &self,
+        transcript: &mut Transcript,
+        user_input: impl Into<UserInput>,
+        ctx: ToolContext,
+    ) -> Result<RunSummary, PiError> {
+        self.run_stream_tracked(transcript, user_input.into(), ctx)
+            .await
+            .0
+    }
+
+    /// [`Agent::run_stream`], also returning the summary when the run failed (with
+    /// [`StopReason::Error`]; the `Err` is the cause).
+    pub async fn run_stream_tracked(
+        &self,
+        transcript: &mut Transcript,
+        user_input: UserInput,
+        ctx: ToolContext,
+    ) -> (Result<RunSummary, PiError>, RunSummary) {
+        self.run_loop(transcript, user_input, ctx, |req| {
+            Box::pin(self.stream_step(req))
+        })
+        .await
+    }
+
+    async fn stream_step(&self, req: ChatRequest) -> Result<ChatResponse, PiError> {
+        let mut stream = self.provider.chat_stream(req).await?;
+        while let Some(ev) = stream.next().await {
+            match ev {
+                ChatStreamEvent::TextDelta { delta } => {
+                    self.events.emit(AgentEvent::TextDelta { delta })
+                }
+                ChatStreamEvent::ThinkingDelta { delta } => {
+                    self.events.emit(AgentEvent::ThinkingDelta { delta })
+                }
+                ChatStreamEvent::ToolCallDelta {
+                    id,
+                    name,
+                    arguments_delta,
+                    ..
+                } => self.events.emit(AgentEvent::ToolCallDelta {
+                    id,
+                    name,
+                    arguments_delta,
+                }),
+                ChatStreamEvent::Usage { usage } => self.events.emit(AgentEvent::Usage { usage }),
+                // Errors surface through `result()`.
+                ChatStreamEvent::Done { .. } | ChatStreamEvent::Error { .. } => {}
+            }
+        }
+        stream.result().await
+    }
+}
+
+/// Pure model catalog (built-in + extension).
+#[derive(Clone, Default)]
+pub struct ModelCatalog {
+    models: Vec<Model>,
+}
+
+impl ModelCatalog {
+    pub fn new(models: impl IntoIterator<Item = Model>) -> Self {
+        Self {
+            models: models.into_iter().collect(),
+        }
+    }
+
+    /// Small built-in catalog for bootstrapping.
+    ///
+    /// Full parity with upstream's generated catalog is a later drop; this provides the *mechanism*
+    /// for model discovery (list + lookup + extension). Prices are list prices in USD per 1M
+    /// tokens; local models are free.
+    pub fn builtin() -> Self {
+        use pi_contracts::{ApiKind, InputModality, NonEmptyString, TokenCost};
+
+        let m = |provider: &str,
+                 id: &str,
+                 api: ApiKind,
+                 name: &str,
+                 cost: TokenCost,
+                 ctx: u32,
+                 max: u32,
+                 input: Vec<InputModality>,
+                 reasoning: bool,
+                 base: Option<&str>| {
+            Model::new(
+                NonEmptyString::new(provider).unwrap(),
+                NonEmptyString::new(id).unwrap(),
+                api,
+                name,
+                cost,
+                ctx,
+                max,
+                input,
+                reasoning,
+                base.map(|s| s.to_string()),
+            )
+        };
+
+        Self::new([
+            m(
+                "openai",
+                "gpt-4o-mini",
+                ApiKind::OpenAiCompletions,
+                "GPT-4o mini",
+                TokenCost {
+                    input: 0.15,
+                    output: 0.6,
+                    cache_read: 0.075,
+                    cache_write: 0.0,
+                },
+                128_000,
                 16_000,
                 vec![InputModality::Text],
                 false,
@@ -320,7 +1415,12 @@ impl ModelCatalog {
                 "gpt-4o",
                 ApiKind::OpenAiCompletions,
                 "GPT-4o",
-                TokenCost::free(),
+                TokenCost {
+                    input: 2.5,
+                    output: 10.0,
+                    cache_read: 1.25,
+                    cache_write: 0.0,
+                },
                 128_000,
                 16_000,
                 vec![InputModality::Text, InputModality::Image],
@@ -332,7 +1432,12 @@ impl ModelCatalog {
                 "gpt-5.1-codex",
                 ApiKind::OpenAiCompletions,
                 "GPT-5.1 Codex",
-                TokenCost::free(),
+                TokenCost {
+                    input: 1.25,
+                    output: 10.0,
+                    cache_read: 0.125,
+                    cache_write: 0.0,
+                },
                 200_000,
                 32_000,
                 vec![InputModality::Text],
@@ -344,7 +1449,12 @@ impl ModelCatalog {
                 "claude-sonnet-4-5",
                 ApiKind::AnthropicMessages,
                 "Claude Sonnet 4.5",
-                TokenCost::free(),
+                TokenCost {
+                    input: 3.0,
+                    output: 15.0,
+                    cache_read: 0.3,
+                    cache_write: 3.75,
+                },
                 200_000,
                 32_000,
                 vec![InputModality::Text, InputModality::Image],
@@ -356,7 +1466,12 @@ impl ModelCatalog {
                 "gemini-2.5-flash",
                 ApiKind::GoogleGenerativeAi,
                 "Gemini 2.5 Flash",
-                TokenCost::free(),
+                TokenCost {
+                    input: 0.3,
+                    output: 2.5,
+                    cache_read: 0.03,
+                    cache_write: 0.0,
+                },
                 1_000_000,
                 32_000,
                 vec![InputModality::Text, InputModality::Image],
@@ -419,6 +1534,58 @@ impl ProviderHub {
     }
 }
 
+/// Fails if a message has content (an image, audio) the model does not take as input.
+fn check_modalities(model: &Model, messages: &[ChatMessage]) -> Result<(), PiError> {
+    let missing = messages
+        .iter()
+        .flat_map(ChatMessage::parts)
+        .map(ContentPart::modality)
+        .find(|m| *m != InputModality::Text && !model.input.contains(m));
+    let kind = match missing {
+        None => return Ok(()),
+        Some(InputModality::Audio) => "audio",
+        Some(_) => "image",
+    };
+    Err(PiError::Invalid(format!(
+        "model `{}` does not accept {kind} input",
+        model.id
+    )))
+}
+
+/// Request settings for an [`AiClient`] call; all unset (provider defaults) by default.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompletionOptions {
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Sent only when tools are passed, like [`ChatRequest::tool_choice`].
+    pub tool_choice: Option<ToolChoice>,
+    pub parallel_tool_calls: Option<bool>,
+    pub params: RequestParams,
+}
+
+impl CompletionOptions {
+    fn request(
+        self,
+        model: &Model,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolSpec>,
+    ) -> ChatRequest {
+        ChatRequest {
+            model: model.id.clone(),
+            messages,
+            tools,
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            reasoning_effort: self.reasoning_effort,
+            tool_choice: self.tool_choice,
+            parallel_tool_calls: self.parallel_tool_calls,
+            response_format: None,
+            params: self.params,
+        }
+    }
+}
+
 /// Unified multi-provider API (pi-ai style), minus provider-specific I/O.
 #[derive(Clone)]
 pub struct AiClient {
@@ -446,19 +1613,17 @@ impl AiClient {
         model: &Model,
         ctx: &AiContext,
         tools: Vec<ToolSpec>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
+        opts: CompletionOptions,
     ) -> Result<ChatResponse, PiError> {
+        self.send(model, opts.request(model, ctx.messages.clone(), tools))
+            .await
+    }
+
+    /// Sends `req` to `model`'s provider, filling in the cost estimate when it has none.
+    async fn send(&self, model: &Model, req: ChatRequest) -> Result<ChatResponse, PiError> {
+        check_modalities(model, &req.messages)?;
         let p = self.provider(&model.provider)?;
-        let mut resp = p
-            .chat(ChatRequest {
-                model: model.id.clone(),
-                messages: ctx.messages.clone(),
-                tools,
-                temperature,
-                max_tokens,
-            })
-            .await?;
+        let mut resp = p.chat(req).await?;
 
         if resp.cost.is_none() {
             if let Some(u) = resp.usage.as_ref() {
@@ -473,27 +1638,23 @@ impl AiClient {
         model: &Model,
         ctx: &AiContext,
         tools: Vec<ToolSpec>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
+        opts: CompletionOptions,
     ) -> Result<ChatStream, PiError> {
+        check_modalities(model, &ctx.messages)?;
         let p = self.provider(&model.provider)?;
         let cost = model.cost;
-        Ok(p.chat_stream(ChatRequest {
-            model: model.id.clone(),
-            messages: ctx.messages.clone(),
-            tools,
-            temperature,
-            max_tokens,
-        })
-        .await?
-        .map_result(move |mut resp| {
-            if resp.cost.is_none() {
-                if let Some(u) = resp.usage.as_ref() {
-                    resp.cost = Some(cost.estimate_usd(u));
-                }
-            }
-            resp
-        }))
+        Ok(
+            p.chat_stream(opts.request(model, ctx.messages.clone(), tools))
+                .await?
+                .map_result(move |mut resp| {
+                    if resp.cost.is_none() {
+                        if let Some(u) = resp.usage.as_ref() {
+                            resp.cost = Some(cost.estimate_usd(u));
+                        }
+                    }
+                    resp
+                }),
+        )
     }
 }
 
@@ -506,7 +1667,7 @@ mod tests {
         SinkExt,
     };
     use pi_contracts::{NonEmptyString, TokenCost, TokenUsage};
-    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[derive(Clone)]
     struct StubProvider {
@@ -522,6 +1683,7 @@ mod tests {
                 assistant: msg,
                 usage: None,
                 cost: None,
+                finish_reason: None,
             })
         }
     }
@@ -564,39 +1726,31 @@ mod tests {
         let provider = StubProvider {
             q: Arc::new(Mutex::new(vec![assistant_1, assistant_2])),
         };
-        let tools = ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]);
+        let tools = ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap();
 
         let cfg = AgentConfig {
-            model: NonEmptyString::new("gpt-test").unwrap(),
-            system_prompt: None,
             max_steps: 8,
-            temperature: None,
-            max_tokens: None,
+            ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
         };
 
         let agent = Agent::new(provider, tools, cfg);
         let mut tr: Transcript = vec![];
         agent
-            .run_to_end(
-                &mut tr,
-                "go",
-                ToolContext {
-                    cwd: PathBuf::from("."),
-                },
-            )
+            .run_to_end(&mut tr, "go", ToolContext::new("."))
             .await
             .unwrap();
 
         // Expected: user, assistant(toolcall), tool(result), assistant(final)
-        assert!(matches!(tr[0], ChatMessage::User { .. }));
-        assert!(matches!(tr[1], ChatMessage::Assistant { .. }));
-        assert!(matches!(tr[2], ChatMessage::Tool { .. }));
-        assert!(matches!(tr[3], ChatMessage::Assistant { .. }));
+        assert!(matches!(tr[0].message, ChatMessage::User { .. }));
+        assert!(matches!(tr[1].message, ChatMessage::Assistant { .. }));
+        assert!(matches!(tr[2].message, ChatMessage::Tool { .. }));
+        assert!(matches!(tr[3].message, ChatMessage::Assistant { .. }));
 
-        match &tr[2] {
+        match &tr[2].message {
             ChatMessage::Tool {
                 tool_call_id,
                 content,
+                ..
             } => {
                 assert_eq!(tool_call_id, &call_id);
                 assert_eq!(content, "hi");
@@ -605,6 +1759,1093 @@ mod tests {
         }
     }
 
+    /// Serves queued assistant messages through both transports; streams replay them as deltas.
+    #[derive(Clone)]
+    struct ScriptedProvider {
+        q: Arc<Mutex<Vec<ChatMessage>>>,
+        /// Requests seen through `chat`.
+        requests: Arc<Mutex<Vec<ChatRequest>>>,
+        /// Finish reasons for the first `chat` responses.
+        finish: Arc<Mutex<Vec<FinishReason>>>,
+    }
+
+    impl ScriptedProvider {
+        fn new(msgs: Vec<ChatMessage>) -> Self {
+            Self {
+                q: Arc::new(Mutex::new(msgs)),
+                requests: Arc::default(),
+                finish: Arc::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ChatProvider for ScriptedProvider {
+        async fn chat(&self, req: ChatRequest) -> Result<ChatResponse, PiError> {
+            self.requests.lock().unwrap().push(req);
+            let mut finish = self.finish.lock().unwrap();
+            Ok(ChatResponse {
+                assistant: self.q.lock().unwrap().remove(0),
+                usage: Some(TokenUsage::new(1, 1, 2)),
+                cost: None,
+                finish_reason: (!finish.is_empty()).then(|| finish.remove(0)),
+            })
+        }
+
+        fn id(&self) -> Option<ProviderId> {
+            Some(NonEmptyString::new("scripted").unwrap())
+        }
+    }
+
+    #[async_trait]
+    impl ChatProviderStream for ScriptedProvider {
+        async fn chat_stream(&self, _req: ChatRequest) -> Result<ChatStream, PiError> {
+            let msg = self.q.lock().unwrap().remove(0);
+            let (mut tx, rx) = mpsc::channel(32);
+            if let ChatMessage::Assistant {
+                content,
+                tool_calls,
+                ..
+            } = &msg
+            {
+                for ch in content.chars() {
+                    tx.send(ChatStreamEvent::TextDelta {
+                        delta: ch.to_string(),
+                    })
+                    .await
+                    .unwrap();
+                }
+                for tc in tool_calls {
+                    tx.send(ChatStreamEvent::ToolCallDelta {
+                        id: tc.id.clone(),
+                        name: tc.name.clone(),
+                        arguments_delta: tc.arguments.to_string(),
+                        parsed_arguments: Some(tc.arguments.clone()),
+                    })
+                    .await
+                    .unwrap();
+                }
+            }
+            tx.send(ChatStreamEvent::Usage {
+                usage: TokenUsage::new(1, 1, 2),
+            })
+            .await
+            .unwrap();
+            tx.send(ChatStreamEvent::Done {
+                finish_reason: None,
+            })
+            .await
+            .unwrap();
+            Ok(ChatStream::new(
+                rx,
+                Box::pin(async move {
+                    Ok(ChatResponse {
+                        assistant: msg,
+                        usage: Some(TokenUsage::new(1, 1, 2)),
+                        cost: None,
+                        finish_reason: None,
+                    })
+                }),
+            ))
+        }
+    }
+
+    fn echo_call(id: &str, text: &str) -> ToolCall {
+        ToolCall {
+            id: NonEmptyString::new(id).unwrap(),
+            name: NonEmptyString::new("echo").unwrap(),
+            arguments: serde_json::json!({ "text": text }),
+        }
+    }
+
+    fn test_ctx() -> ToolContext {
+        ToolContext::new(".")
+    }
+
+    fn tool_message(m: &ChatMessage) -> (&str, &str, bool) {
+        match m {
+            ChatMessage::Tool {
+                tool_call_id,
+                content,
+                is_error,
+                ..
+            } => (tool_call_id.as_str(), content.as_str(), *is_error),
+            other => panic!("expected tool message, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn tool_failures_are_reported_to_the_model() {
+        let bogus = ToolCall {
+            id: NonEmptyString::new("call_1").unwrap(),
+            name: NonEmptyString::new("nope").unwrap(),
+            arguments: serde_json::json!({}),
+        };
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant("", vec![bogus, echo_call("call_2", "hi")]),
+            ChatMessage::assistant("recovered", vec![]),
+        ]);
+        let tools = ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap();
+        let agent = Agent::new(
+            provider,
+            tools,
+            AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap()),
+        );
+
+        let mut tr: Transcript = vec![];
+        agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+
+        let (id, content, is_error) = tool_message(&tr[2].message);
+        assert_eq!(id, "call_1");
+        assert!(content.contains("unknown tool: nope"));
+        assert!(is_error);
+        assert_eq!(tool_message(&tr[3].message), ("call_2", "hi", false));
+        assert_eq!(tr[4].message, ChatMessage::assistant("recovered", vec![]));
+    }
+
+    #[tokio::test]
+    async fn cut_off_replies_are_continued_and_filtered_ones_fail() {
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant("The answer", vec![]),
+            ChatMessage::assistant(" is 42.", vec![]),
+        ]);
+        provider
+            .finish
+            .lock()
+            .unwrap()
+            .extend([FinishReason::Length, FinishReason::Stop]);
+        let requests = provider.requests.clone();
+        let agent = Agent::new(
+            provider,
+            ToolSet::default(),
+            AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap()),
+        );
+        let mut tr: Transcript = vec![];
+        let summary = agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+        assert_eq!(summary.steps, 2);
+        let second = requests.lock().unwrap()[1].messages.clone();
+        assert_eq!(second[1], ChatMessage::assistant("The answer", vec![]));
+        assert_eq!(second[2], ChatMessage::user(CONTINUE_PROMPT));
+
+        // The continuation completes the cut-off reply in a single entry.
+        assert_eq!(tr.len(), 2);
+        assert_eq!(
+            tr[1].message,
+            ChatMessage::assistant("The answer is 42.", vec![])
+        );
+        assert_eq!(tr[1].usage, Some(TokenUsage::new(2, 2, 4)));
+        assert_eq!(tr[1].stop_reason, Some(FinishReason::Stop));
+
+        let provider = ScriptedProvider::new(vec![ChatMessage::assistant("", vec![])]);
+        provider
+            .finish
+            .lock()
+            .unwrap()
+            .push(FinishReason::ContentFilter);
+        let agent = Agent::new(
+            provider,
+            ToolSet::default(),
+            AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap()),
+        );
+        let mut tr: Transcript = vec![];
+        let err = agent.run_to_end(&mut tr, "go", test_ctx()).await;
+        assert!(err.unwrap_err().to_string().contains("content filter"));
+    }
+
+    struct ScreenshotTool;
+
+    #[async_trait]
+    impl Tool for ScreenshotTool {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: NonEmptyString::new("echo").unwrap(),
+                description: "screenshot".into(),
+                parameters: serde_json::json!({"type":"object"}),
+            }
+        }
+
+        async fn execute(&self, _args: Json, _ctx: ToolContext) -> Result<ToolResult, PiError> {
+            Ok(ToolResult {
+                parts: vec![ContentPart::Image {
+                    mime_type: "image/png".into(),
+                    data: "iVBORw0KGgo=".into(),
+                }],
+                ..ToolResult::error("page failed to load")
+                    .with_details(serde_json::json!({"status": 500}))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn rich_tool_results_are_kept_in_the_transcript() {
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant("", vec![echo_call("call_1", "x")]),
+            ChatMessage::assistant("it is down", vec![]),
+        ]);
+        let tools = ToolSet::new([Arc::new(ScreenshotTool) as Arc<dyn Tool>]).unwrap();
+        let agent = Agent::new(
+            provider,
+            tools,
+            AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap()),
+        );
+        let details = Arc::new(Mutex::new(None));
+        let seen = details.clone();
+        agent.events().on_event(move |ev| {
+            if let AgentEvent::ToolExecutionEnd { details, .. } = ev {
+                *seen.lock().unwrap() = details.clone();
+            }
+        });
+
+        let mut tr: Transcript = vec![];
+        let summary = agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+
+        let ChatMessage::Tool {
+            is_error,
+            details: Some(d),
+            parts,
+            ..
+        } = &tr[2].message
+        else {
+            panic!(
+                "expected tool message with details, got {:?}",
+                tr[2].message
+            );
+        };
+        assert!(is_error);
+        assert_eq!(d["status"], 500);
+        assert!(
+            matches!(&parts[..], [ContentPart::Image { mime_type, .. }] if mime_type == "image/png")
+        );
+        assert_eq!(
+            summary.tool_calls[0].error.as_deref(),
+            Some("page failed to load")
+        );
+        assert_eq!(
+            details.lock().unwrap().clone(),
+            Some(serde_json::json!({"status": 500}))
+        );
+    }
+
+    #[tokio::test]
+    async fn abort_policy_stops_but_keeps_transcript_paired() {
+        let bogus = ToolCall {
+            id: NonEmptyString::new("call_1").unwrap(),
+            name: NonEmptyString::new("nope").unwrap(),
+            arguments: serde_json::json!({}),
+        };
+        let provider = ScriptedProvider::new(vec![ChatMessage::assistant(
+            "",
+            vec![bogus, echo_call("call_2", "hi")],
+        )]);
+        let tools = ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap();
+        let cfg = AgentConfig {
+            tool_errors: ToolErrorPolicy::Abort,
+            max_parallel_tools: 1,
+            ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+        };
+        let agent = Agent::new(provider, tools, cfg);
+
+        let mut tr: Transcript = vec![];
+        let err = agent
+            .run_to_end(&mut tr, "go", test_ctx())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, PiError::Tool(_)));
+
+        assert_eq!(tr.len(), 4);
+        assert!(tool_message(&tr[2].message).2);
+        let (id, _, is_error) = tool_message(&tr[3].message);
+        assert_eq!(id, "call_2");
+        assert!(is_error);
+    }
+
+    #[tokio::test]
+    async fn compacts_before_a_step_once_the_window_fills_up() {
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant("summary of the old work", vec![]),
+            ChatMessage::assistant("done", vec![]),
+        ]);
+        let requests = provider.requests.clone();
+        let cfg = AgentConfig {
+            system_prompt: Some("sys".into()),
+            compaction: CompactionConfig {
+                keep_recent_tokens: 1,
+                ..CompactionConfig::for_window(400)
+            },
+            ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+        };
+        let agent = Agent::new(provider, ToolSet::new([]).unwrap(), cfg);
+        let mut events = agent.subscribe();
+
+        let mut tr: Transcript = SessionEntry::chain([
+            ChatMessage::system("sys"),
+            ChatMessage::user("x".repeat(800)),
+            ChatMessage::assistant("y".repeat(800), vec![]),
+        ]);
+        agent.run_to_end(&mut tr, "next", test_ctx()).await.unwrap();
+
+        // History is kept; the compaction entry points at the new user message.
+        assert_eq!(tr.len(), 6);
+        assert_eq!(
+            tr[4].message,
+            ChatMessage::compaction("summary of the old work", 3, 417)
+        );
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].tools.is_empty());
+        assert_eq!(
+            requests[1].messages,
+            vec![
+                ChatMessage::system("sys"),
+                tr[4].message.clone(),
+                ChatMessage::user("next"),
+            ]
+        );
+        let compactions: Vec<_> = std::iter::from_fn(|| events.try_next().ok().flatten())
+            .filter(|e| matches!(e, AgentEvent::Compaction { .. }))
+            .collect();
+        assert_eq!(compactions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn token_budget_stops_the_run_and_skips_pending_tools() {
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant("", vec![echo_call("call_1", "a")]),
+            ChatMessage::assistant("", vec![echo_call("call_2", "b")]),
+            ChatMessage::assistant("never", vec![]),
+        ]);
+        let tools = ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap();
+        let agent = Agent::new(
+            provider,
+            tools,
+            AgentConfig {
+                budget: RunBudget {
+                    max_tokens: Some(4),
+                    ..RunBudget::unlimited()
+                },
+                ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+            },
+        );
+        let mut events = agent.subscribe();
+
+        let mut tr: Transcript = vec![];
+        let summary = agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+        assert_eq!(
+            summary.stop_reason,
+            StopReason::Budget {
+                kind: BudgetKind::Tokens
+            }
+        );
+
+        // Each scripted response reports 2 tokens: the second one hits the limit.
+        assert_eq!(summary.steps, 2);
+        assert_eq!(summary.usage.total_tokens, 4);
+        let calls: Vec<_> = summary
+            .tool_calls
+            .iter()
+            .map(|c| (c.id.as_str(), c.error.as_deref()))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                ("call_1", None),
+                ("call_2", Some("skipped: budget exceeded"))
+            ]
+        );
+        assert_eq!(tr.len(), 5);
+        assert_eq!(tool_message(&tr[2].message), ("call_1", "a", false));
+        assert_eq!(
+            tool_message(&tr[4].message),
+            ("call_2", "skipped: budget exceeded", true)
+        );
+        let tail: Vec<_> = std::iter::from_fn(|| events.try_next().ok().flatten())
+            .filter(|e| {
+                matches!(
+                    e,
+                    AgentEvent::BudgetExceeded { .. } | AgentEvent::RunEnd { .. }
+                )
+            })
+            .collect();
+        assert!(matches!(
+            &tail[..],
+            [
+                AgentEvent::BudgetExceeded { kind: BudgetKind::Tokens, usage },
+                AgentEvent::RunEnd { summary: end, error: None },
+            ] if usage.tokens == 4 && usage.max_tokens == Some(4) && **end == summary
+        ));
+    }
+
+    #[tokio::test]
+    async fn invalid_arguments_are_reported_without_running_the_tool() {
+        let bad = ToolCall {
+            arguments: serde_json::json!({ "text": 5 }),
+            ..echo_call("call_1", "")
+        };
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant("", vec![bad]),
+            ChatMessage::assistant("done", vec![]),
+        ]);
+        let tools = ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap();
+        let agent = Agent::new(
+            provider,
+            tools,
+            AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap()),
+        );
+
+        let mut tr: Transcript = vec![];
+        agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+        assert_eq!(
+            tool_message(&tr[2].message),
+            (
+                "call_1",
+                "tool: invalid arguments for `echo`: /text: expected string, got integer",
+                true
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn tools_registered_later_are_offered_unless_filtered_for_the_run() {
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant("", vec![echo_call("call_1", "a")]),
+            ChatMessage::assistant("done", vec![]),
+            ChatMessage::assistant(
+                "",
+                vec![ToolCall {
+                    name: NonEmptyString::new("secret__echo").unwrap(),
+                    ..echo_call("call_2", "b")
+                }],
+            ),
+            ChatMessage::assistant("done", vec![]),
+        ]);
+        let requests = provider.requests.clone();
+        // Asking about every call; filtered ones must not get that far.
+        let permissions: PermissionPolicy =
+            serde_json::from_value(serde_json::json!({"default": "ask"})).unwrap();
+        let agent = Agent::new(
+            provider,
+            ToolSet::default(),
+            AgentConfig {
+                permissions,
+                ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+            },
+        )
+        .with_approver(Arc::new(ScriptedApprover(Mutex::new(vec![
+            ApprovalDecision::Approve,
+        ]))));
+        agent.tools().register(Arc::new(EchoTool)).unwrap();
+        let ctx = ToolContext {
+            tools: ToolFilter {
+                allow: None,
+                deny: vec!["secret*".into()],
+            },
+            ..test_ctx()
+        };
+
+        let mut tr: Transcript = vec![];
+        agent.run_to_end(&mut tr, "go", ctx.clone()).await.unwrap();
+        assert_eq!(tool_message(&tr[2].message), ("call_1", "a", false));
+        assert_eq!(requests.lock().unwrap()[0].tools.len(), 1);
+
+        agent.tools().unregister("echo").unwrap();
+        agent
+            .tools()
+            .register_namespaced("secret", Arc::new(EchoTool))
+            .unwrap();
+        agent.run_to_end(&mut tr, "again", ctx).await.unwrap();
+        assert!(requests.lock().unwrap()[2].tools.is_empty());
+        assert_eq!(
+            tool_message(&tr[6].message),
+            ("call_2", "tool: unknown tool: secret__echo", true)
+        );
+    }
+
+    #[tokio::test]
+    async fn forced_tool_choice_lasts_until_the_first_tool_call() {
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant("", vec![echo_call("call_1", "a")]),
+            ChatMessage::assistant("done", vec![]),
+        ]);
+        let requests = provider.requests.clone();
+        let echo = NonEmptyString::new("echo").unwrap();
+        let agent = Agent::new(
+            provider,
+            ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap(),
+            AgentConfig {
+                tool_choice: Some(ToolChoice::Tool { name: echo.clone() }),
+                parallel_tool_calls: Some(false),
+                ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+            },
+        );
+        let mut tr: Transcript = vec![];
+        agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0].tool_choice,
+            Some(ToolChoice::Tool { name: echo })
+        );
+        assert_eq!(requests[1].tool_choice, None);
+        assert_eq!(requests[1].parallel_tool_calls, Some(false));
+    }
+
+    #[tokio::test]
+    async fn last_step_wraps_up_without_tools() {
+        let script = || {
+            vec![
+                ChatMessage::assistant("", vec![echo_call("call_1", "a")]),
+                ChatMessage::assistant("done a; b remains", vec![echo_call("call_2", "b")]),
+            ]
+        };
+        let cfg = AgentConfig {
+            max_steps: 2,
+            ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+        };
+        let tools = || ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap();
+        let provider = ScriptedProvider::new(script());
+        let requests = provider.requests.clone();
+        let agent = Agent::new(provider, tools(), cfg.clone());
+
+        let mut tr: Transcript = vec![];
+        let summary = agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+        assert_eq!(summary.stop_reason, StopReason::Completed);
+        assert!(summary.truncated);
+        let last = requests.lock().unwrap().pop().unwrap();
+        assert_eq!(last.tool_choice, Some(ToolChoice::None));
+        assert_eq!(last.tools.len(), 1);
+        assert_eq!(
+            last.messages.last(),
+            Some(&ChatMessage::user(WRAP_UP_PROMPT))
+        );
+        // The instruction is not recorded; a stray tool call is answered so the transcript pairs.
+        assert_eq!(tr.len(), 5);
+        assert_eq!(
+            tool_message(&tr[4].message),
+            ("call_2", "skipped: step limit reached", true)
+        );
+
+        let agent = Agent::new(
+            ScriptedProvider::new(script()),
+            tools(),
+            AgentConfig {
+                max_steps_policy: MaxStepsPolicy::Stop,
+                ..cfg
+            },
+        );
+        let mut tr: Transcript = vec![];
+        let summary = agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+        assert_eq!(summary.stop_reason, StopReason::MaxSteps);
+        assert!(!summary.truncated);
+        assert_eq!(tool_message(&tr[4].message), ("call_2", "b", false));
+    }
+
+    #[tokio::test]
+    async fn a_single_step_keeps_its_tools() {
+        let provider = ScriptedProvider::new(vec![ChatMessage::assistant(
+            "",
+            vec![echo_call("call_1", "a")],
+        )]);
+        let requests = provider.requests.clone();
+        let agent = Agent::new(
+            provider,
+            ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap(),
+            AgentConfig {
+                max_steps: 1,
+                ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+            },
+        );
+
+        let mut tr: Transcript = vec![];
+        let summary = agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+        assert_eq!(summary.stop_reason, StopReason::MaxSteps);
+        assert!(!summary.truncated);
+        let first = &requests.lock().unwrap()[0];
+        assert_eq!(first.tool_choice, None);
+        assert_eq!(first.messages.last(), Some(&ChatMessage::user("go")));
+        assert_eq!(tool_message(&tr[2].message), ("call_1", "a", false));
+    }
+
+    /// Simulates the user typing while a tool runs.
+    struct SteerTool(MessageQueue);
+
+    #[async_trait]
+    impl Tool for SteerTool {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: NonEmptyString::new("steer").unwrap(),
+                description: "steer".into(),
+                parameters: serde_json::json!({"type":"object"}),
+            }
+        }
+
+        async fn execute(&self, _args: Json, _ctx: ToolContext) -> Result<ToolResult, PiError> {
+            self.0.steer("use the other file");
+            Ok(ToolResult::text("steered"))
+        }
+    }
+
+    #[tokio::test]
+    async fn steering_skips_pending_tools_and_follow_ups_run_after_the_turn() {
+        let steer = ToolCall {
+            id: NonEmptyString::new("call_1").unwrap(),
+            name: NonEmptyString::new("steer").unwrap(),
+            arguments: serde_json::json!({}),
+        };
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant("", vec![steer, echo_call("call_2", "hi")]),
+            ChatMessage::assistant("ok", vec![]),
+            ChatMessage::assistant("done", vec![]),
+        ]);
+        let queue = MessageQueue::new();
+        let tools = ToolSet::new([
+            Arc::new(SteerTool(queue.clone())) as Arc<dyn Tool>,
+            Arc::new(EchoTool),
+        ])
+        .unwrap();
+        let cfg = AgentConfig {
+            max_parallel_tools: 1,
+            ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+        };
+        let agent = Agent::new(provider, tools, cfg).with_message_queue(queue.clone());
+        let mut events = agent.subscribe();
+        queue.follow_up("and then this");
+
+        let mut tr: Transcript = vec![];
+        agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+
+        assert_eq!(tr.len(), 8);
+        assert_eq!(tool_message(&tr[2].message), ("call_1", "steered", false));
+        assert_eq!(
+            tool_message(&tr[3].message),
+            ("call_2", "skipped: the user sent a new message", true)
+        );
+        assert_eq!(tr[4].message, ChatMessage::user("use the other file"));
+        assert_eq!(tr[5].message, ChatMessage::assistant("ok", vec![]));
+        assert_eq!(tr[6].message, ChatMessage::user("and then this"));
+        assert_eq!(tr[7].message, ChatMessage::assistant("done", vec![]));
+        assert!(queue.is_empty());
+
+        let kinds: Vec<_> = std::iter::from_fn(|| events.try_next().ok().flatten())
+            .filter_map(|e| match e {
+                AgentEvent::QueuedMessage { kind, .. } => Some(kind),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![QueuedMessageKind::Steering, QueuedMessageKind::FollowUp]
+        );
+    }
+
+    struct ScriptedApprover(Mutex<Vec<ApprovalDecision>>);
+
+    #[async_trait]
+    impl ToolApprover for ScriptedApprover {
+        async fn approve(&self, _call: &ToolCall) -> Result<ApprovalDecision, PiError> {
+            Ok(self.0.lock().unwrap().remove(0))
+        }
+    }
+
+    #[tokio::test]
+    async fn permission_policy_gates_tool_execution() {
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant(
+                "",
+                vec![
+                    echo_call("call_1", "secret"),
+                    echo_call("call_2", "one"),
+                    echo_call("call_3", "two"),
+                    echo_call("call_4", "three"),
+                ],
+            ),
+            ChatMessage::assistant("done", vec![]),
+        ]);
+        let tools = ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap();
+        let permissions: PermissionPolicy = serde_json::from_value(serde_json::json!({
+            "default": "ask",
+            "rules": [{"tool": "echo", "argument": "text", "pattern": "secret*", "action": "deny"}]
+        }))
+        .unwrap();
+        let cfg = AgentConfig {
+            permissions,
+            ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+        };
+        // `call_1` never reaches the approver; `call_4` is covered by "always".
+        let approver = ScriptedApprover(Mutex::new(vec![
+            ApprovalDecision::Deny,
+            ApprovalDecision::ApproveAlways,
+        ]));
+        let agent = Agent::new(provider, tools, cfg).with_approver(Arc::new(approver));
+
+        let mut tr: Transcript = vec![];
+        agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+
+        let (_, content, is_error) = tool_message(&tr[2].message);
+        assert!(is_error && content.contains("blocked by policy"));
+        let (_, content, is_error) = tool_message(&tr[3].message);
+        assert!(is_error && content.contains("rejected by user"));
+        assert_eq!(tool_message(&tr[4].message), ("call_3", "two", false));
+        assert_eq!(tool_message(&tr[5].message), ("call_4", "three", false));
+    }
+
+    /// Sleeps for `ms` (from args) and tracks how many instances run at once.
+    struct SlowTool {
+        name: &'static str,
+        parallel_safe: bool,
+        active: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Tool for SlowTool {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: NonEmptyString::new(self.name).unwrap(),
+                description: "sleep".into(),
+                parameters: serde_json::json!({"type":"object","properties":{"ms":{"type":"integer"}}}),
+            }
+        }
+
+        async fn execute(&self, args: Json, _ctx: ToolContext) -> Result<ToolResult, PiError> {
+            let now = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            let ms = args["ms"].as_u64().unwrap_or(0);
+            tokio::time::sleep(std::time::Duration::from_millis(ms)).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            Ok(ToolResult::text(ms.to_string()))
+        }
+
+        fn parallel_safe(&self) -> bool {
+            self.parallel_safe
+        }
+    }
+
+    #[tokio::test]
+    async fn parallel_tool_calls_respect_limit_order_and_unsafe_tools() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let tool = |name, parallel_safe| {
+            Arc::new(SlowTool {
+                name,
+                parallel_safe,
+                active: active.clone(),
+                peak: peak.clone(),
+            }) as Arc<dyn Tool>
+        };
+        let tools = ToolSet::new([tool("sleep", true), tool("exclusive", false)]).unwrap();
+        let call = |id: &str, name: &str, ms: u64| ToolCall {
+            id: NonEmptyString::new(id).unwrap(),
+            name: NonEmptyString::new(name).unwrap(),
+            arguments: serde_json::json!({ "ms": ms }),
+        };
+
+        // Three parallel-safe calls, limit 2; later calls finish first.
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant(
+                "",
+                vec![
+                    call("a", "sleep", 40),
+                    call("b", "sleep", 20),
+                    call("c", "sleep", 1),
+                ],
+            ),
+            ChatMessage::assistant("done", vec![]),
+        ]);
+        let cfg = AgentConfig {
+            max_parallel_tools: 2,
+            ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+        };
+        let agent = Agent::new(provider, tools.clone(), cfg.clone());
+        let mut tr: Transcript = vec![];
+        agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        let ids: Vec<&str> = tr[2..5]
+            .iter()
+            .map(|e| tool_message(&e.message).0)
+            .collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+
+        // A tool that is not parallel-safe splits the step into sequential batches.
+        peak.store(0, Ordering::SeqCst);
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant(
+                "",
+                vec![
+                    call("a", "sleep", 10),
+                    call("b", "exclusive", 10),
+                    call("c", "sleep", 10),
+                ],
+            ),
+            ChatMessage::assistant("done", vec![]),
+        ]);
+        let agent = Agent::new(provider, tools, cfg);
+        let mut tr: Transcript = vec![];
+        agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+    }
+
+    /// Ends every wait at once, as if the time had passed, and records what was asked for.
+    #[derive(Default)]
+    struct InstantSleeper(Mutex<Vec<Duration>>);
+
+    #[async_trait]
+    impl Sleeper for InstantSleeper {
+        async fn sleep(&self, duration: Duration) {
+            self.0.lock().unwrap().push(duration);
+        }
+    }
+
+    /// Never answers.
+    struct HangingProvider;
+
+    #[async_trait]
+    impl ChatProvider for HangingProvider {
+        async fn chat(&self, _req: ChatRequest) -> Result<ChatResponse, PiError> {
+            future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn time_budget_cuts_short_steps_and_tools_that_never_finish() {
+        let cfg = AgentConfig {
+            budget: RunBudget {
+                max_duration: Some(Duration::from_secs(60)),
+                ..RunBudget::unlimited()
+            },
+            ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+        };
+        let time_up = StopReason::Budget {
+            kind: BudgetKind::Time,
+        };
+        let sleeper = Arc::new(InstantSleeper::default());
+
+        let agent = Agent::new(HangingProvider, ToolSet::default(), cfg.clone())
+            .with_sleeper(sleeper.clone());
+        let mut events = agent.subscribe();
+        let mut tr: Transcript = vec![];
+        let summary = agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+        assert_eq!(summary.stop_reason, time_up);
+        assert_eq!(summary.steps, 0);
+        assert!(sleeper.0.lock().unwrap()[0] <= Duration::from_secs(60));
+        assert!(
+            std::iter::from_fn(|| events.try_next().ok().flatten()).any(|e| matches!(
+                e,
+                AgentEvent::BudgetExceeded {
+                    kind: BudgetKind::Time,
+                    ..
+                }
+            ))
+        );
+
+        // The step answers at once; the tool call it asks for would take a minute.
+        let tools = ToolSet::new([Arc::new(SlowTool {
+            name: "sleep",
+            parallel_safe: true,
+            active: Arc::new(AtomicUsize::new(0)),
+            peak: Arc::new(AtomicUsize::new(0)),
+        }) as Arc<dyn Tool>])
+        .unwrap();
+        let call = ToolCall {
+            id: NonEmptyString::new("call_1").unwrap(),
+            name: NonEmptyString::new("sleep").unwrap(),
+            arguments: serde_json::json!({ "ms": 60_000 }),
+        };
+        let provider = ScriptedProvider::new(vec![ChatMessage::assistant("", vec![call])]);
+        let agent = Agent::new(provider, tools, cfg).with_sleeper(sleeper);
+        let mut tr: Transcript = vec![];
+        let summary = agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+        assert_eq!(summary.stop_reason, time_up);
+        assert_eq!(summary.steps, 1);
+        assert_eq!(summary.tool_calls[0].error.as_deref(), Some("aborted"));
+        assert_eq!(tool_message(&tr[2].message), ("call_1", "aborted", true));
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_run_aborts_tools_and_keeps_transcript_paired() {
+        let tools = ToolSet::new([Arc::new(SlowTool {
+            name: "sleep",
+            parallel_safe: true,
+            active: Arc::new(AtomicUsize::new(0)),
+            peak: Arc::new(AtomicUsize::new(0)),
+        }) as Arc<dyn Tool>])
+        .unwrap();
+        let call = ToolCall {
+            id: NonEmptyString::new("call_1").unwrap(),
+            name: NonEmptyString::new("sleep").unwrap(),
+            arguments: serde_json::json!({ "ms": 60_000 }),
+        };
+        let provider = ScriptedProvider::new(vec![ChatMessage::assistant("", vec![call])]);
+        let agent = Agent::new(
+            provider,
+            tools,
+            AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap()),
+        );
+
+        let ctx = test_ctx();
+        let cancel = ctx.cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            cancel.cancel();
+        });
+
+        let mut tr: Transcript = vec![];
+        let summary = agent.run_stream(&mut tr, "go", ctx).await.unwrap();
+        assert_eq!(summary.stop_reason, StopReason::Aborted);
+        assert_eq!(summary.tool_calls[0].error.as_deref(), Some("aborted"));
+        assert_eq!(tr.len(), 3);
+        assert_eq!(tool_message(&tr[2].message), ("call_1", "aborted", true));
+    }
+
+    #[tokio::test]
+    async fn transcript_entries_record_provenance() {
+        let provider = ScriptedProvider::new(vec![
+            ChatMessage::assistant("", vec![echo_call("call_1", "a")]),
+            ChatMessage::assistant("done", vec![]),
+        ]);
+        provider
+            .finish
+            .lock()
+            .unwrap()
+            .extend([FinishReason::ToolCalls, FinishReason::Stop]);
+        let agent = Agent::new(
+            provider,
+            ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap(),
+            AgentConfig {
+                pricing: TokenCost {
+                    input: 1_000_000.0,
+                    output: 1_000_000.0,
+                    ..TokenCost::free()
+                },
+                ..AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap())
+            },
+        );
+        let mut tr: Transcript = vec![];
+        agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+
+        assert_eq!(tr.len(), 4);
+        assert_eq!(tr[0].parent_id, None);
+        for pair in tr.windows(2) {
+            assert_eq!(pair[1].parent_id, Some(pair[0].id));
+        }
+        let reply = &tr[3];
+        assert_eq!(reply.message, ChatMessage::assistant("done", vec![]));
+        assert_eq!(reply.provider.as_ref().unwrap().as_str(), "scripted");
+        assert_eq!(reply.model.as_ref().unwrap().as_str(), "gpt-test");
+        assert_eq!(reply.usage, Some(TokenUsage::new(1, 1, 2)));
+        assert!((reply.cost.unwrap().total - 2.0).abs() < 1e-9);
+        assert_eq!(reply.stop_reason, Some(FinishReason::Stop));
+        assert!(reply.duration_ms.is_some());
+        // Tool results carry their duration only; user input carries nothing.
+        assert!(tr[2].duration_ms.is_some() && tr[2].model.is_none());
+        assert!(tr[0].model.is_none() && tr[0].duration_ms.is_none());
+
+        // Without pricing, a reply's cost is unknown rather than zero.
+        let agent = Agent::new(
+            ScriptedProvider::new(vec![ChatMessage::assistant("done", vec![])]),
+            ToolSet::default(),
+            AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap()),
+        );
+        let mut tr: Transcript = vec![];
+        agent.run_to_end(&mut tr, "go", test_ctx()).await.unwrap();
+        assert!(tr[1].usage.is_some());
+        assert_eq!(tr[1].cost, None);
+    }
+
+    #[test]
+    fn callbacks_can_emit_and_subscribe_while_handling_an_event() {
+        let events = AgentEvents::new();
+        let mut rx = events.subscribe();
+        let inner = events.clone();
+        events.on_event(move |ev| {
+            if matches!(ev, AgentEvent::TextDelta { delta } if delta == "a") {
+                drop(inner.subscribe());
+                inner.emit(AgentEvent::TextDelta { delta: "b".into() });
+            }
+        });
+
+        events.emit(AgentEvent::TextDelta { delta: "a".into() });
+        let deltas: Vec<_> = std::iter::from_fn(|| rx.try_next().ok().flatten())
+            .map(|ev| match ev {
+                AgentEvent::TextDelta { delta } => delta,
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(deltas, ["a", "b"]);
+        // The dropped subscriber is gone; the channel and the callback remain.
+        assert_eq!(events.sinks.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn streaming_run_matches_run_to_end_and_emits_events() {
+        let script = || {
+            vec![
+                ChatMessage::assistant("on it", vec![echo_call("call_1", "hi")]),
+                ChatMessage::assistant("done", vec![]),
+            ]
+        };
+        let tools = || ToolSet::new([Arc::new(EchoTool) as Arc<dyn Tool>]).unwrap();
+        let cfg = AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap());
+
+        let plain = Agent::new(ScriptedProvider::new(script()), tools(), cfg.clone());
+        let mut tr_plain: Transcript = vec![];
+        plain
+            .run_to_end(&mut tr_plain, "go", test_ctx())
+            .await
+            .unwrap();
+
+        let streaming = Agent::new(ScriptedProvider::new(script()), tools(), cfg);
+        let events = streaming.subscribe();
+        let mut tr_stream: Transcript = vec![];
+        streaming
+            .run_stream(&mut tr_stream, "go", test_ctx())
+            .await
+            .unwrap();
+
+        let messages = |tr: &Transcript| tr.iter().map(|e| e.message.clone()).collect::<Vec<_>>();
+        assert_eq!(messages(&tr_plain), messages(&tr_stream));
+
+        drop(streaming);
+        let events: Vec<AgentEvent> = events.collect().await;
+        let text: String = events
+            .iter()
+            .filter_map(|e| match e {
+                AgentEvent::TextDelta { delta } => Some(delta.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text, "on itdone");
+
+        let kinds: Vec<&str> = events
+            .iter()
+            .filter_map(|e| match e {
+                AgentEvent::TurnStart { .. } => Some("turn_start"),
+                AgentEvent::ToolCallDelta { .. } => Some("tool_call_delta"),
+                AgentEvent::MessageEnd { .. } => Some("message_end"),
+                AgentEvent::ToolExecutionStart { .. } => Some("tool_start"),
+                AgentEvent::ToolExecutionEnd { content, .. } => {
+                    assert_eq!(content, "hi");
+                    Some("tool_end")
+                }
+                AgentEvent::TurnEnd { .. } => Some("turn_end"),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "turn_start",
+                "tool_call_delta",
+                "message_end",
+                "tool_start",
+                "tool_end",
+                "turn_end",
+                "turn_start",
+                "message_end",
+                "turn_end"
+            ]
+        );
+    }
+
     #[derive(Clone)]
     struct StubStreamProvider;
 
@@ -615,6 +2856,7 @@ mod tests {
                 assistant: ChatMessage::assistant("hi", vec![]),
                 usage: Some(TokenUsage::new(1_000_000, 1_000_000, 2_000_000)),
                 cost: None,
+                finish_reason: None,
             })
         }
     }
@@ -631,11 +2873,16 @@ mod tests {
                 let _ = tx
                     .send(ChatStreamEvent::TextDelta { delta: "i".into() })
                     .await;
-                let _ = tx.send(ChatStreamEvent::Done).await;
+                let _ = tx
+                    .send(ChatStreamEvent::Done {
+                        finish_reason: None,
+                    })
+                    .await;
                 let _ = res_tx.send(Ok(ChatResponse {
                     assistant: ChatMessage::assistant("hi", vec![]),
                     usage: Some(TokenUsage::new(1_000_000, 1_000_000, 2_000_000)),
                     cost: None,
+                    finish_reason: None,
                 }));
             });
 
@@ -650,6 +2897,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn completion_options_fill_the_request() {
+        let model = ModelCatalog::builtin().find("openai", "gpt-4o").unwrap();
+        let opts = CompletionOptions {
+            max_tokens: Some(64),
+            tool_choice: Some(ToolChoice::Required),
+            parallel_tool_calls: Some(false),
+            ..CompletionOptions::default()
+        };
+        let req = opts.request(&model, vec![ChatMessage::user("yo")], vec![]);
+        assert_eq!(req.model.as_str(), "gpt-4o");
+        assert_eq!(req.max_tokens, Some(64));
+        assert_eq!(req.tool_choice, Some(ToolChoice::Required));
+        assert_eq!(req.parallel_tool_calls, Some(false));
+        assert_eq!(req.response_format, None);
+    }
+
     #[tokio::test]
     async fn ai_client_injects_cost_on_complete_and_stream_result() {
         use pi_contracts::{ApiKind, InputModality, Model};
@@ -684,11 +2948,17 @@ mod tests {
             messages: vec![ChatMessage::user("yo")],
         };
 
-        let r = ai.complete(&model, &ctx, vec![], None, None).await.unwrap();
+        let r = ai
+            .complete(&model, &ctx, vec![], CompletionOptions::default())
+            .await
+            .unwrap();
         assert!(r.cost.is_some());
         assert!((r.cost.unwrap().total - 2.0).abs() < 1e-9);
 
-        let mut s = ai.stream(&model, &ctx, vec![], None, None).await.unwrap();
+        let mut s = ai
+            .stream(&model, &ctx, vec![], CompletionOptions::default())
+            .await
+            .unwrap();
         let mut buf = String::new();
         while let Some(ev) = s.next().await {
             if let ChatStreamEvent::TextDelta { delta } = ev {
@@ -699,5 +2969,22 @@ mod tests {
         let r2 = s.result().await.unwrap();
         assert!(r2.cost.is_some());
         assert!((r2.cost.unwrap().total - 2.0).abs() < 1e-9);
+
+        let ctx = AiContext {
+            messages: vec![ChatMessage::user_with_parts(
+                "what is this?",
+                vec![ContentPart::Image {
+                    mime_type: "image/png".into(),
+                    data: "AAAA".into(),
+                }],
+            )],
+        };
+        let err = ai
+            .complete(&model, &ctx, vec![], CompletionOptions::default())
+            .await;
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "invalid: model `m` does not accept image input"
+        );
     }
 }
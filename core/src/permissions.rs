@@ -0,0 +1,189 @@
+//! Tool permission policy + approval port.
+//!
+//! A [`PermissionPolicy`] maps tool calls to [`Permission`]s using per-tool and per-argument glob
+//! rules. `Ask` decisions are delegated to a [`ToolApprover`] implemented by the driving adapter
+//! (CLI prompt, TUI dialog, Slack button, Swift callback, ...).
+
+use async_trait::async_trait;
+use pi_contracts::{PiError, ToolCall};
+use serde::{Deserialize, Serialize};
+
+/// Outcome of evaluating a tool call against a policy.
+///
+/// Ordered by restrictiveness: `Allow < Ask < Deny`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Allow,
+    Ask,
+    Deny,
+}
+
+/// One permission rule.
+///
+/// `tool` is a glob over tool names. If `argument` is set, the rule only matches when that top-level
+/// argument is present and its value (strings as-is, anything else as JSON) matches `pattern`
+/// (default `*`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PermissionRule {
+    pub tool: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argument: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    pub action: Permission,
+}
+
+impl PermissionRule {
+    fn matches(&self, call: &ToolCall) -> bool {
+        if !glob_match(&self.tool, call.name.as_str()) {
+            return false;
+        }
+        let Some(arg) = &self.argument else {
+            return true;
+        };
+        let Some(value) = call.arguments.get(arg) else {
+            return false;
+        };
+        let text = match value.as_str() {
+            Some(s) => s.to_string(),
+            None => value.to_string(),
+        };
+        glob_match(self.pattern.as_deref().unwrap_or("*"), &text)
+    }
+}
+
+/// Set of permission rules, typically loaded from project config (`.pi/permissions.json`).
+///
+/// Every matching rule is considered and the most restrictive action wins, so rule order does not
+/// matter. Calls matched by no rule get `default`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PermissionPolicy {
+    #[serde(default = "PermissionPolicy::default_action")]
+    pub default: Permission,
+    #[serde(default)]
+    pub rules: Vec<PermissionRule>,
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        Self::allow_all()
+    }
+}
+
+impl PermissionPolicy {
+    fn default_action() -> Permission {
+        Permission::Allow
+    }
+
+    /// No rules; everything is allowed.
+    pub fn allow_all() -> Self {
+        Self {
+            default: Permission::Allow,
+            rules: vec![],
+        }
+    }
+
+    pub fn evaluate(&self, call: &ToolCall) -> Permission {
+        self.rules
+            .iter()
+            .filter(|r| r.matches(call))
+            .map(|r| r.action)
+            .max()
+            .unwrap_or(self.default)
+    }
+}
+
+/// A human's answer to an approval request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalDecision {
+    Approve,
+    /// Approve this call and every later `Ask` for the same tool during this agent's lifetime.
+    ApproveAlways,
+    Deny,
+}
+
+/// Port: asks someone whether a tool call may run.
+#[async_trait]
+pub trait ToolApprover: Send + Sync {
+    async fn approve(&self, call: &ToolCall) -> Result<ApprovalDecision, PiError>;
+}
+
+/// Minimal glob matching: `*` matches any run of characters, `?` exactly one.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pi_contracts::NonEmptyString;
+
+    fn call(name: &str, args: serde_json::Value) -> ToolCall {
+        ToolCall {
+            id: NonEmptyString::new("call_1").unwrap(),
+            name: NonEmptyString::new(name).unwrap(),
+            arguments: args,
+        }
+    }
+
+    #[test]
+    fn most_restrictive_matching_rule_wins() {
+        let policy: PermissionPolicy = serde_json::from_value(serde_json::json!({
+            "rules": [
+                {"tool": "bash", "argument": "command", "pattern": "rm *", "action": "deny"},
+                {"tool": "bash", "action": "ask"},
+                {"tool": "read", "action": "allow"},
+                {"tool": "*", "argument": "path", "pattern": "/etc/*", "action": "deny"}
+            ]
+        }))
+        .unwrap();
+
+        let bash = |cmd: &str| call("bash", serde_json::json!({ "command": cmd }));
+        assert_eq!(policy.evaluate(&bash("ls -la")), Permission::Ask);
+        assert_eq!(policy.evaluate(&bash("rm -rf /")), Permission::Deny);
+        assert_eq!(
+            policy.evaluate(&call("read", serde_json::json!({"path": "src/lib.rs"}))),
+            Permission::Allow
+        );
+        assert_eq!(
+            policy.evaluate(&call("read", serde_json::json!({"path": "/etc/passwd"}))),
+            Permission::Deny
+        );
+        // Unmatched tools fall back to the default.
+        assert_eq!(
+            policy.evaluate(&call("write", serde_json::json!({}))),
+            Permission::Allow
+        );
+    }
+
+    #[test]
+    fn glob_handles_wildcards() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("git *", "git push --force"));
+        assert!(glob_match("*--force*", "git push --force origin"));
+        assert!(glob_match("b?sh", "bash"));
+        assert!(!glob_match("git *", "gitk"));
+        assert!(!glob_match("*.rs", "main.rs.bak"));
+    }
+}
@@ -0,0 +1,87 @@
+//! Messages submitted while a run is in progress.
+
+use pi_contracts::QueuedMessageKind;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Default)]
+struct Queues {
+    steering: VecDeque<String>,
+    follow_up: VecDeque<String>,
+}
+
+/// Steering and follow-up messages for a running agent.
+///
+/// Cheap to clone; clones share the same queues, so a front end can keep one and push to it from
+/// another task or thread while [`crate::Agent`] drains it between steps.
+#[derive(Clone, Default)]
+pub struct MessageQueue {
+    inner: Arc<Mutex<Queues>>,
+}
+
+impl MessageQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues guidance for the running turn; it is sent before the next provider call.
+    pub fn steer(&self, message: impl Into<String>) {
+        self.inner
+            .lock()
+            .unwrap()
+            .steering
+            .push_back(message.into());
+    }
+
+    /// Queues a new user turn to run after the current one ends.
+    pub fn follow_up(&self, message: impl Into<String>) {
+        self.inner
+            .lock()
+            .unwrap()
+            .follow_up
+            .push_back(message.into());
+    }
+
+    pub fn push(&self, kind: QueuedMessageKind, message: impl Into<String>) {
+        match kind {
+            QueuedMessageKind::Steering => self.steer(message),
+            QueuedMessageKind::FollowUp => self.follow_up(message),
+        }
+    }
+
+    pub fn has_steering(&self) -> bool {
+        !self.inner.lock().unwrap().steering.is_empty()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let q = self.inner.lock().unwrap();
+        q.steering.is_empty() && q.follow_up.is_empty()
+    }
+
+    /// Drops everything queued (e.g. after an aborted run).
+    pub fn clear(&self) {
+        let mut q = self.inner.lock().unwrap();
+        q.steering.clear();
+        q.follow_up.clear();
+    }
+
+    pub(crate) fn take_steering(&self) -> Vec<String> {
+        self.inner.lock().unwrap().steering.drain(..).collect()
+    }
+
+    pub(crate) fn pop_follow_up(&self) -> Option<String> {
+        self.inner.lock().unwrap().follow_up.pop_front()
+    }
+}
+
+impl std::fmt::Debug for MessageQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let q = self.inner.lock().unwrap();
+        f.debug_struct("MessageQueue")
+            .field("steering", &q.steering.len())
+            .field("follow_up", &q.follow_up.len())
+            .finish()
+    }
+}
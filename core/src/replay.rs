@@ -0,0 +1,368 @@
+//! Record/replay of provider exchanges.
+//!
+//! [`RecordingProvider`] saves every successful request/response pair (with its stream events) to
+//! a [`FixtureStore`]; [`ReplayProvider`] serves them back without a network, so agent runs,
+//! compaction and front ends can be regression-tested against real model output.
+
+use crate::{ChatProvider, ChatProviderStream, ChatStream};
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use pi_contracts::{
+    ChatRequest, ChatResponse, ChatStreamEvent, PiError, ProviderFixture, ProviderId,
+};
+use serde_json::Value as Json;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+/// Outbound port: recorded exchanges, in recording order.
+#[async_trait]
+pub trait FixtureStore: Send + Sync {
+    async fn load(&self) -> Result<Vec<ProviderFixture>, PiError>;
+    /// Saves the `index`-th (0-based) exchange of a recording.
+    async fn save(&self, index: usize, fixture: &ProviderFixture) -> Result<(), PiError>;
+}
+
+/// Wraps a provider and records each successful exchange.
+///
+/// Errors are passed through unrecorded. Stream events are forwarded as they arrive; the
+/// exchange is saved, with the events delivered so far, when the stream's result is taken.
+pub struct RecordingProvider<P> {
+    inner: P,
+    store: Arc<dyn FixtureStore>,
+    next: Arc<AtomicUsize>,
+}
+
+impl<P> RecordingProvider<P> {
+    pub fn new(inner: P, store: Arc<dyn FixtureStore>) -> Self {
+        Self {
+            inner,
+            store,
+            next: Arc::default(),
+        }
+    }
+}
+
+async fn save(
+    store: &dyn FixtureStore,
+    next: &AtomicUsize,
+    fixture: &ProviderFixture,
+) -> Result<(), PiError> {
+    store
+        .save(next.fetch_add(1, Ordering::SeqCst), fixture)
+        .await
+}
+
+#[async_trait]
+impl<P: ChatProvider> ChatProvider for RecordingProvider<P> {
+    async fn chat(&self, req: ChatRequest) -> Result<ChatResponse, PiError> {
+        let resp = self.inner.chat(req.clone()).await?;
+        let fixture = ProviderFixture {
+            request: req,
+            response: resp.clone(),
+            stream: None,
+        };
+        save(self.store.as_ref(), &self.next, &fixture).await?;
+        Ok(resp)
+    }
+
+    fn id(&self) -> Option<ProviderId> {
+        self.inner.id()
+    }
+}
+
+#[async_trait]
+impl<P: ChatProviderStream> ChatProviderStream for RecordingProvider<P> {
+    async fn chat_stream(&self, req: ChatRequest) -> Result<ChatStream, PiError> {
+        let events = Arc::new(Mutex::new(vec![]));
+        let copy = events.clone();
+        let mut stream = self
+            .inner
+            .chat_stream(req.clone())
+            .await?
+            .inspect_events(move |ev| copy.lock().unwrap().push(ev.clone()));
+        let (store, next) = (self.store.clone(), self.next.clone());
+        if let Some(result) = stream.result.take() {
+            stream.result = Some(Box::pin(async move {
+                let resp = result.await?;
+                let fixture = ProviderFixture {
+                    request: req,
+                    response: resp.clone(),
+                    stream: Some(std::mem::take(&mut *events.lock().unwrap())),
+                };
+                save(store.as_ref(), &next, &fixture).await?;
+                Ok(resp)
+            }));
+        }
+        Ok(stream)
+    }
+}
+
+/// Serves recorded exchanges.
+///
+/// Each request must match an exchange not served yet, compared as JSON with surrounding
+/// whitespace in strings ignored; exchanges may be served in any order, so concurrent requests
+/// (e.g. parallel sub-agents) replay too. A request with no match fails, naming the first point
+/// where it differs from the next unserved recording.
+pub struct ReplayProvider {
+    fixtures: Vec<(Json, ProviderFixture)>,
+    served: Mutex<Vec<bool>>,
+}
+
+impl ReplayProvider {
+    pub fn new(fixtures: Vec<ProviderFixture>) -> Result<Self, PiError> {
+        let fixtures = fixtures
+            .into_iter()
+            .map(|f| Ok((normalize(&f.request)?, f)))
+            .collect::<Result<Vec<_>, PiError>>()?;
+        Ok(Self {
+            served: Mutex::new(vec![false; fixtures.len()]),
+            fixtures,
+        })
+    }
+
+    pub async fn load(store: &dyn FixtureStore) -> Result<Self, PiError> {
+        Self::new(store.load().await?)
+    }
+
+    /// Number of recorded exchanges not served yet.
+    pub fn remaining(&self) -> usize {
+        self.served.lock().unwrap().iter().filter(|s| !**s).count()
+    }
+
+    fn take(&self, req: &ChatRequest) -> Result<ProviderFixture, PiError> {
+        let req = normalize(req)?;
+        let mut served = self.served.lock().unwrap();
+        let unserved = |i: &usize| !served[*i];
+        let found = (0..self.fixtures.len())
+            .filter(unserved)
+            .find(|&i| self.fixtures[i].0 == req);
+        if let Some(i) = found {
+            served[i] = true;
+            return Ok(self.fixtures[i].1.clone());
+        }
+        let why = match (0..self.fixtures.len()).find(unserved) {
+            None => "all recorded exchanges have been served".to_string(),
+            Some(i) => format!(
+                "differs from recording #{i} at {}",
+                first_difference(&self.fixtures[i].0, &req, String::new()).unwrap_or_default()
+            ),
+        };
+        Err(PiError::Provider(format!(
+            "replay: unexpected request ({why})"
+        )))
+    }
+}
+
+#[async_trait]
+impl ChatProvider for ReplayProvider {
+    async fn chat(&self, req: ChatRequest) -> Result<ChatResponse, PiError> {
+        Ok(self.take(&req)?.response)
+    }
+}
+
+#[async_trait]
+impl ChatProviderStream for ReplayProvider {
+    async fn chat_stream(&self, req: ChatRequest) -> Result<ChatStream, PiError> {
+        let f = self.take(&req)?;
+        Ok(buffered(f.stream.unwrap_or_default(), Ok(f.response)))
+    }
+}
+
+/// A stream that delivers `events`, then resolves to `resp`.
+fn buffered(events: Vec<ChatStreamEvent>, resp: Result<ChatResponse, PiError>) -> ChatStream {
+    let (mut tx, rx) = mpsc::channel(events.len());
+    for ev in events {
+        tx.try_send(ev).expect("channel sized for all events");
+    }
+    ChatStream::new(rx, Box::pin(async move { resp }))
+}
+
+fn normalize(req: &ChatRequest) -> Result<Json, PiError> {
+    fn trim(v: &mut Json) {
+        match v {
+            Json::String(s) => *s = s.trim().to_string(),
+            Json::Array(items) => items.iter_mut().for_each(trim),
+            Json::Object(map) => map.values_mut().for_each(trim),
+            _ => {}
+        }
+    }
+    let mut v = serde_json::to_value(req)?;
+    trim(&mut v);
+    Ok(v)
+}
+
+/// JSON-pointer-like path of the first place `a` and `b` differ.
+fn first_difference(a: &Json, b: &Json, path: String) -> Option<String> {
+    match (a, b) {
+        (Json::Object(x), Json::Object(y)) => {
+            let keys = x.keys().chain(y.keys().filter(|k| !x.contains_key(*k)));
+            keys.into_iter().find_map(|k| match (x.get(k), y.get(k)) {
+                (Some(p), Some(q)) => first_difference(p, q, format!("{path}/{k}")),
+                _ => Some(format!("{path}/{k}")),
+            })
+        }
+        (Json::Array(x), Json::Array(y)) => x
+            .iter()
+            .zip(y)
+            .enumerate()
+            .find_map(|(i, (p, q))| first_difference(p, q, format!("{path}/{i}")))
+            .or_else(|| (x.len() != y.len()).then(|| format!("{path}/{}", x.len().min(y.len())))),
+        _ => (a != b).then(|| if path.is_empty() { "/".into() } else { path }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use pi_contracts::{ChatMessage, NonEmptyString, RequestParams, TokenUsage};
+
+    #[derive(Default)]
+    struct MemoryStore(Mutex<Vec<ProviderFixture>>);
+
+    #[async_trait]
+    impl FixtureStore for MemoryStore {
+        async fn load(&self) -> Result<Vec<ProviderFixture>, PiError> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+
+        async fn save(&self, index: usize, fixture: &ProviderFixture) -> Result<(), PiError> {
+            let mut v = self.0.lock().unwrap();
+            assert_eq!(index, v.len());
+            v.push(fixture.clone());
+            Ok(())
+        }
+    }
+
+    /// Echoes the last message; streams it one character at a time.
+    struct Echo;
+
+    impl Echo {
+        fn answer(req: &ChatRequest) -> ChatResponse {
+            let text = match req.messages.last() {
+                Some(ChatMessage::User { content, .. }) => format!("you said {content}"),
+                _ => "?".into(),
+            };
+            ChatResponse {
+                assistant: ChatMessage::assistant(text, vec![]),
+                usage: Some(TokenUsage::new(3, 2, 5)),
+                cost: None,
+                finish_reason: None,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ChatProvider for Echo {
+        async fn chat(&self, req: ChatRequest) -> Result<ChatResponse, PiError> {
+            Ok(Self::answer(&req))
+        }
+    }
+
+    #[async_trait]
+    impl ChatProviderStream for Echo {
+        async fn chat_stream(&self, req: ChatRequest) -> Result<ChatStream, PiError> {
+            let resp = Self::answer(&req);
+            let events = ["you", " said"]
+                .map(|d| ChatStreamEvent::TextDelta { delta: d.into() })
+                .into_iter()
+                .chain([ChatStreamEvent::Done {
+                    finish_reason: None,
+                }])
+                .collect();
+            Ok(buffered(events, Ok(resp)))
+        }
+    }
+
+    fn req(text: &str) -> ChatRequest {
+        ChatRequest {
+            model: NonEmptyString::new("m").unwrap(),
+            messages: vec![ChatMessage::system("be brief"), ChatMessage::user(text)],
+            tools: vec![],
+            temperature: None,
+            max_tokens: None,
+            reasoning_effort: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            response_format: None,
+            params: RequestParams::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn recorded_exchanges_replay_in_any_order() {
+        let store = Arc::new(MemoryStore::default());
+        let rec = RecordingProvider::new(Echo, store.clone());
+        let a = rec.chat(req("a")).await.unwrap();
+        let mut s = rec.chat_stream(req("b")).await.unwrap();
+        let b_events: Vec<_> = s.by_ref().collect().await;
+        let b = s.result().await.unwrap();
+        assert_eq!(b_events.len(), 3);
+
+        let replay = ReplayProvider::load(store.as_ref()).await.unwrap();
+        let mut s = replay.chat_stream(req("b  \n")).await.unwrap();
+        assert_eq!(s.by_ref().collect::<Vec<_>>().await, b_events);
+        assert_eq!(s.result().await.unwrap(), b);
+        assert_eq!(replay.chat(req("a")).await.unwrap(), a);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    /// Streams whatever the test sends on `tx`, then resolves with a fixed reply.
+    struct Live(Mutex<Option<mpsc::Receiver<ChatStreamEvent>>>);
+
+    #[async_trait]
+    impl ChatProviderStream for Live {
+        async fn chat_stream(&self, req: ChatRequest) -> Result<ChatStream, PiError> {
+            let rx = self.0.lock().unwrap().take().unwrap();
+            Ok(ChatStream::new(
+                rx,
+                Box::pin(async move { Ok(Echo::answer(&req)) }),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn recording_forwards_events_as_they_arrive() {
+        let (mut tx, rx) = mpsc::channel(4);
+        let store = Arc::new(MemoryStore::default());
+        let rec = RecordingProvider::new(Live(Mutex::new(Some(rx))), store.clone());
+        let mut s = rec.chat_stream(req("b")).await.unwrap();
+
+        let delta = ChatStreamEvent::TextDelta {
+            delta: "you".into(),
+        };
+        tx.try_send(delta.clone()).unwrap();
+        assert_eq!(s.next().await, Some(delta.clone()));
+        assert!(store.0.lock().unwrap().is_empty());
+
+        drop(tx);
+        assert_eq!(s.next().await, None);
+        let resp = s.result().await.unwrap();
+        let saved = store.load().await.unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].response, resp);
+        assert_eq!(saved[0].stream, Some(vec![delta]));
+    }
+
+    #[tokio::test]
+    async fn unmatched_requests_fail_with_the_first_difference() {
+        let replay = ReplayProvider::new(vec![ProviderFixture {
+            request: req("a"),
+            response: Echo::answer(&req("a")),
+            stream: None,
+        }])
+        .unwrap();
+        let err = replay.chat(req("c")).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "provider: replay: unexpected request (differs from recording #0 at /messages/1/content)"
+        );
+        replay.chat(req("a")).await.unwrap();
+        let err = replay.chat(req("a")).await.unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("all recorded exchanges have been served"));
+    }
+}
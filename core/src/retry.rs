@@ -0,0 +1,366 @@
+//! Retries for transient provider failures.
+//!
+//! [`RetryProvider`] wraps any provider and re-sends a request after rate limits, server errors
+//! and network failures, backing off exponentially (with jitter) or as long as the server asks
+//! via `Retry-After`.
+
+use crate::{AgentEvents, ChatProvider, ChatProviderStream, ChatStream};
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use pi_contracts::{
+    AgentEvent, ChatRequest, ChatResponse, ChatStreamEvent, PiError, ProviderId, StreamErrorReason,
+};
+use std::{collections::hash_map::RandomState, hash::BuildHasher, sync::Arc, time::Duration};
+
+/// Outbound port: waiting between attempts, or for a run's time budget (timers live in adapters).
+#[async_trait]
+pub trait Sleeper: Send + Sync {
+    async fn sleep(&self, duration: Duration);
+}
+
+/// How often and how long to retry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first (`1` = never retry).
+    pub max_attempts: u32,
+    /// Delay before the first retry; each later one is `multiplier` times longer.
+    pub initial_delay: Duration,
+    pub multiplier: f64,
+    /// Upper bound for backoff delays.
+    pub max_delay: Duration,
+    /// Upper bound for waits the server asks for with `Retry-After`; a longer hint waits this
+    /// long. Separate from `max_delay` because rate limits often ask for a minute or more.
+    pub max_retry_after: Duration,
+    /// Random spread applied to backoff delays, as a fraction (`0.2` = ±20%).
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(30),
+            max_retry_after: Duration::from_secs(300),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// How long to wait after `err` ended attempt `attempt` (1-based), or `None` to give up.
+    /// `unit` is a random number in `[0, 1)` used for jitter.
+    pub fn delay(&self, attempt: u32, err: &PiError, unit: f64) -> Option<Duration> {
+        if attempt >= self.max_attempts || !err.is_retryable() {
+            return None;
+        }
+        if let Some(hint) = err.retry_after() {
+            return Some(hint.min(self.max_retry_after));
+        }
+        let exp = i32::try_from(attempt - 1).unwrap_or(i32::MAX);
+        let base = self.initial_delay.as_secs_f64() * self.multiplier.powi(exp);
+        let secs = base * (1.0 + self.jitter * (2.0 * unit - 1.0));
+        Some(
+            Duration::try_from_secs_f64(secs.clamp(0.0, self.max_delay.as_secs_f64()))
+                .unwrap_or(self.max_delay),
+        )
+    }
+}
+
+/// A random number in `[0, 1)`; std's hasher keys are randomly seeded, which is plenty for jitter.
+fn jitter_unit() -> f64 {
+    (RandomState::new().hash_one(0u8) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Wraps a provider with [`RetryPolicy`].
+///
+/// Streams are only retried while nothing has been delivered: a failure to open the stream, or
+/// an error before its first event. Once an event is out, the error surfaces as usual.
+pub struct RetryProvider<P> {
+    inner: P,
+    policy: RetryPolicy,
+    sleeper: Arc<dyn Sleeper>,
+    events: AgentEvents,
+}
+
+impl<P> RetryProvider<P> {
+    pub fn new(inner: P, policy: RetryPolicy, sleeper: Arc<dyn Sleeper>) -> Self {
+        Self {
+            inner,
+            policy,
+            sleeper,
+            events: AgentEvents::new(),
+        }
+    }
+
+    /// Reports [`AgentEvent::Retry`] to `events`; pass the agent's (see
+    /// [`Agent::with_events`](crate::Agent::with_events)) so front ends can show it.
+    pub fn with_events(mut self, events: AgentEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Waits before the next attempt; `false` means give up.
+    async fn backoff(&self, attempt: u32, err: &PiError) -> bool {
+        let Some(delay) = self.policy.delay(attempt, err, jitter_unit()) else {
+            return false;
+        };
+        self.events.emit(AgentEvent::Retry {
+            attempt: attempt + 1,
+            max_attempts: self.policy.max_attempts,
+            delay_ms: u64::try_from(delay.as_millis()).unwrap_or(u64::MAX),
+            error: err.to_string(),
+        });
+        self.sleeper.sleep(delay).await;
+        true
+    }
+}
+
+#[async_trait]
+impl<P: ChatProvider> ChatProvider for RetryProvider<P> {
+    async fn chat(&self, req: ChatRequest) -> Result<ChatResponse, PiError> {
+        let mut attempt = 1;
+        loop {
+            let err = match self.inner.chat(req.clone()).await {
+                Err(e) => e,
+                ok => return ok,
+            };
+            if !self.backoff(attempt, &err).await {
+                return Err(err);
+            }
+            attempt += 1;
+        }
+    }
+
+    fn id(&self) -> Option<ProviderId> {
+        self.inner.id()
+    }
+}
+
+#[async_trait]
+impl<P: ChatProviderStream> ChatProviderStream for RetryProvider<P> {
+    async fn chat_stream(&self, req: ChatRequest) -> Result<ChatStream, PiError> {
+        let mut attempt = 1;
+        loop {
+            let err = match self.inner.chat_stream(req.clone()).await {
+                Err(e) => e,
+                Ok(mut stream) => {
+                    let failed_early = match stream.peek().await {
+                        None => true,
+                        Some(ChatStreamEvent::Error { reason, .. }) => {
+                            *reason != StreamErrorReason::Aborted
+                        }
+                        Some(_) => false,
+                    };
+                    if !failed_early {
+                        return Ok(stream);
+                    }
+                    match stream.result().await {
+                        Ok(resp) => return Ok(ready(resp)),
+                        Err(e) => e,
+                    }
+                }
+            };
+            if !self.backoff(attempt, &err).await {
+                return Err(err);
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// A stream with no events that resolves to `resp`.
+fn ready(resp: ChatResponse) -> ChatStream {
+    let (_, events) = mpsc::channel(0);
+    ChatStream::new(events, Box::pin(async move { Ok(resp) }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use pi_contracts::{ChatMessage, NonEmptyString, RequestParams};
+    use std::sync::Mutex;
+
+    fn status(code: u16, retry_after_ms: Option<u64>) -> PiError {
+        PiError::HttpStatus {
+            status: code,
+            message: format!("test {code}"),
+            code: None,
+            error_type: None,
+            retry_after_ms,
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingSleeper(Mutex<Vec<Duration>>);
+
+    #[async_trait]
+    impl Sleeper for RecordingSleeper {
+        async fn sleep(&self, duration: Duration) {
+            self.0.lock().unwrap().push(duration);
+        }
+    }
+
+    /// Fails with the scripted errors, in order, then answers "ok". Streams emit one text delta
+    /// before failing when `mid_stream` is set.
+    struct Flaky {
+        errors: Mutex<Vec<PiError>>,
+        mid_stream: bool,
+    }
+
+    impl Flaky {
+        fn new(mut errors: Vec<PiError>, mid_stream: bool) -> Self {
+            errors.reverse();
+            Self {
+                errors: Mutex::new(errors),
+                mid_stream,
+            }
+        }
+
+        fn next(&self) -> Result<ChatResponse, PiError> {
+            match self.errors.lock().unwrap().pop() {
+                Some(e) => Err(e),
+                None => Ok(ChatResponse {
+                    assistant: ChatMessage::assistant("ok", vec![]),
+                    usage: None,
+                    cost: None,
+                    finish_reason: None,
+                }),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ChatProvider for Flaky {
+        async fn chat(&self, _req: ChatRequest) -> Result<ChatResponse, PiError> {
+            self.next()
+        }
+    }
+
+    #[async_trait]
+    impl ChatProviderStream for Flaky {
+        async fn chat_stream(&self, _req: ChatRequest) -> Result<ChatStream, PiError> {
+            let r = self.next();
+            let (mut tx, rx) = mpsc::channel(4);
+            let first = match (&r, self.mid_stream) {
+                (Ok(_), _) | (Err(_), true) => ChatStreamEvent::TextDelta { delta: "o".into() },
+                (Err(e), false) => ChatStreamEvent::Error {
+                    reason: StreamErrorReason::Provider,
+                    message: e.to_string(),
+                },
+            };
+            tx.try_send(first).unwrap();
+            Ok(ChatStream::new(rx, Box::pin(async move { r })))
+        }
+    }
+
+    fn req() -> ChatRequest {
+        ChatRequest {
+            model: NonEmptyString::new("m").unwrap(),
+            messages: vec![ChatMessage::user("hi")],
+            tools: vec![],
+            temperature: None,
+            max_tokens: None,
+            reasoning_effort: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            response_format: None,
+            params: RequestParams::default(),
+        }
+    }
+
+    fn provider(flaky: Flaky) -> (RetryProvider<Flaky>, Arc<RecordingSleeper>, AgentEvents) {
+        let sleeper = Arc::new(RecordingSleeper::default());
+        let events = AgentEvents::new();
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        let p = RetryProvider::new(flaky, policy, sleeper.clone()).with_events(events.clone());
+        (p, sleeper, events)
+    }
+
+    #[test]
+    fn delays_back_off_and_honor_retry_after() {
+        let p = RetryPolicy::default();
+        let busy = status(503, None);
+        assert_eq!(p.delay(1, &busy, 0.5), Some(Duration::from_secs(1)));
+        assert_eq!(p.delay(3, &busy, 0.5), Some(Duration::from_secs(4)));
+        assert_eq!(p.delay(2, &busy, 0.0), Some(Duration::from_secs_f64(1.6)));
+        assert_eq!(p.delay(4, &busy, 0.5), None);
+        assert_eq!(p.delay(1, &status(400, None), 0.5), None);
+        assert_eq!(
+            p.delay(1, &status(429, Some(7_000)), 0.5),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            p.delay(1, &status(429, Some(60_000)), 0.5),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            p.delay(1, &status(429, Some(3_600_000)), 0.5),
+            Some(Duration::from_secs(300))
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_retries_transient_errors_and_reports_them() {
+        let (p, sleeper, events) = provider(Flaky::new(
+            vec![status(429, Some(2_500)), PiError::Http("reset".into())],
+            false,
+        ));
+        let mut rx = events.subscribe();
+        let resp = p.chat(req()).await.unwrap();
+        assert_eq!(resp.assistant, ChatMessage::assistant("ok", vec![]));
+        assert_eq!(
+            *sleeper.0.lock().unwrap(),
+            vec![Duration::from_millis(2_500), Duration::from_secs(2)]
+        );
+        assert!(matches!(
+            rx.try_next().unwrap(),
+            Some(AgentEvent::Retry {
+                attempt: 2,
+                max_attempts: 4,
+                delay_ms: 2_500,
+                ..
+            })
+        ));
+
+        let (p, sleeper, _) = provider(Flaky::new(vec![status(400, None)], false));
+        assert!(matches!(
+            p.chat(req()).await,
+            Err(PiError::HttpStatus { status: 400, .. })
+        ));
+        assert!(sleeper.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn streams_are_retried_only_before_their_first_event() {
+        let (p, sleeper, _) = provider(Flaky::new(vec![status(500, None)], false));
+        let mut s = p.chat_stream(req()).await.unwrap();
+        assert!(matches!(
+            s.next().await,
+            Some(ChatStreamEvent::TextDelta { .. })
+        ));
+        assert!(s.result().await.is_ok());
+        assert_eq!(sleeper.0.lock().unwrap().len(), 1);
+
+        let (p, sleeper, _) = provider(Flaky::new(vec![status(500, None)], true));
+        let mut s = p.chat_stream(req()).await.unwrap();
+        assert!(matches!(
+            s.next().await,
+            Some(ChatStreamEvent::TextDelta { .. })
+        ));
+        assert!(s.result().await.is_err());
+        assert!(sleeper.0.lock().unwrap().is_empty());
+    }
+}
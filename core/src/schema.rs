@@ -0,0 +1,416 @@
+//! Tool-argument validation against [`ToolSpec::parameters`](pi_contracts::ToolSpec).
+//!
+//! Covers the JSON Schema subset tool definitions use: `type` (one or a list), `enum`, `const`,
+//! `properties`, `required`, `additionalProperties`, `items`, `anyOf`/`oneOf` (both treated as
+//! "matches at least one"), length/size/numeric bounds, and local `$ref`s (`#/definitions/...`,
+//! `#/$defs/...`). Other keywords are ignored.
+//!
+//! Common model mistakes in tool arguments are coerced where the intent is unambiguous: numbers
+//! and booleans sent as strings (`"42"`, `"true"`), objects and arrays sent as JSON strings, and
+//! `null` for optional properties (dropped). [`validate_value`] checks without coercing.
+
+use serde_json::{Map, Value as Json};
+use std::fmt;
+
+/// How many schemas deep a `$ref` may still be followed, so a cycle that never reaches a nested
+/// value fails instead of recursing forever.
+const MAX_DEPTH: usize = 128;
+
+/// One schema violation, located by a JSON pointer into the arguments (`/edits/0/find`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArgumentError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        write!(f, "{path}: {}", self.message)
+    }
+}
+
+/// Checks `args` against `schema`, returning them with coercions applied, or every violation.
+pub fn validate_arguments(schema: &Json, mut args: Json) -> Result<Json, Vec<ArgumentError>> {
+    let mut errors = vec![];
+    Validator {
+        root: schema,
+        coerce: true,
+    }
+    .check(schema, &mut args, "", 0, &mut errors);
+    if errors.is_empty() {
+        Ok(args)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Checks `value` against `schema` as given: nothing is coerced or dropped.
+pub fn validate_value(schema: &Json, value: &Json) -> Result<(), Vec<ArgumentError>> {
+    let mut errors = vec![];
+    Validator {
+        root: schema,
+        coerce: false,
+    }
+    .check(schema, &mut value.clone(), "", 0, &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+struct Validator<'a> {
+    /// The schema `$ref`s resolve against.
+    root: &'a Json,
+    coerce: bool,
+}
+
+impl Validator<'_> {
+    fn check(
+        &self,
+        schema: &Json,
+        v: &mut Json,
+        path: &str,
+        depth: usize,
+        errors: &mut Vec<ArgumentError>,
+    ) {
+        if let Some(Json::String(reference)) = schema.get("$ref") {
+            let target = reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.root.pointer(pointer))
+                .filter(|_| depth < MAX_DEPTH);
+            let Some(target) = target else {
+                return errors.push(ArgumentError {
+                    path: path.to_string(),
+                    message: format!("schema has an unresolvable or cyclic `$ref` ({reference})"),
+                });
+            };
+            self.check(target, v, path, depth + 1, errors);
+        }
+
+        let mut fail = |message: String| {
+            errors.push(ArgumentError {
+                path: path.to_string(),
+                message,
+            })
+        };
+        let schema = match schema {
+            Json::Object(s) => s,
+            Json::Bool(false) => return fail("no value is allowed here".into()),
+            _ => return,
+        };
+
+        for key in ["anyOf", "oneOf"] {
+            let Some(Json::Array(variants)) = schema.get(key) else {
+                continue;
+            };
+            let matched = variants.iter().find_map(|variant| {
+                let mut candidate = v.clone();
+                let mut errs = vec![];
+                self.check(variant, &mut candidate, path, depth + 1, &mut errs);
+                errs.is_empty().then_some(candidate)
+            });
+            match matched {
+                Some(candidate) => *v = candidate,
+                None => return fail("does not match any of the allowed schemas".into()),
+            }
+        }
+
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                Json::String(t) => vec![t.as_str()],
+                Json::Array(ts) => ts.iter().filter_map(Json::as_str).collect(),
+                _ => vec![],
+            };
+            if !types.is_empty() && !types.iter().any(|t| has_type(v, t)) {
+                let coerced = if self.coerce {
+                    types.iter().find_map(|t| coerce(v, t))
+                } else {
+                    None
+                };
+                match coerced {
+                    Some(coerced) => *v = coerced,
+                    None => {
+                        return fail(format!(
+                            "expected {}, got {}",
+                            types.join(" or "),
+                            type_name(v)
+                        ))
+                    }
+                }
+            }
+        }
+
+        if let Some(Json::Array(allowed)) = schema.get("enum") {
+            if !allowed.contains(v) {
+                return fail(format!("must be one of {}", Json::Array(allowed.clone())));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if v != expected {
+                return fail(format!("must be {expected}"));
+            }
+        }
+
+        let bound = |key: &str| schema.get(key).and_then(Json::as_f64);
+        match v {
+            Json::String(s) => {
+                let len = s.chars().count() as f64;
+                if bound("minLength").is_some_and(|min| len < min) {
+                    fail(format!(
+                        "must be at least {} characters",
+                        schema["minLength"]
+                    ));
+                }
+                if bound("maxLength").is_some_and(|max| len > max) {
+                    fail(format!(
+                        "must be at most {} characters",
+                        schema["maxLength"]
+                    ));
+                }
+            }
+            Json::Number(n) => {
+                let n = n.as_f64().unwrap_or_default();
+                if bound("minimum").is_some_and(|min| n < min) {
+                    fail(format!("must be >= {}", schema["minimum"]));
+                }
+                if bound("maximum").is_some_and(|max| n > max) {
+                    fail(format!("must be <= {}", schema["maximum"]));
+                }
+                if bound("exclusiveMinimum").is_some_and(|min| n <= min) {
+                    fail(format!("must be > {}", schema["exclusiveMinimum"]));
+                }
+                if bound("exclusiveMaximum").is_some_and(|max| n >= max) {
+                    fail(format!("must be < {}", schema["exclusiveMaximum"]));
+                }
+            }
+            Json::Array(items) => {
+                let len = items.len() as f64;
+                if bound("minItems").is_some_and(|min| len < min) {
+                    fail(format!("must have at least {} items", schema["minItems"]));
+                }
+                if bound("maxItems").is_some_and(|max| len > max) {
+                    fail(format!("must have at most {} items", schema["maxItems"]));
+                }
+                if let Some(item_schema) = schema.get("items") {
+                    for (i, item) in items.iter_mut().enumerate() {
+                        self.check(item_schema, item, &format!("{path}/{i}"), depth + 1, errors);
+                    }
+                }
+            }
+            Json::Object(obj) => self.check_object(schema, obj, path, depth, errors),
+            _ => {}
+        }
+    }
+
+    fn check_object(
+        &self,
+        schema: &Map<String, Json>,
+        obj: &mut Map<String, Json>,
+        path: &str,
+        depth: usize,
+        errors: &mut Vec<ArgumentError>,
+    ) {
+        let empty = Map::new();
+        let props = schema
+            .get("properties")
+            .and_then(Json::as_object)
+            .unwrap_or(&empty);
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Json::as_array)
+            .map(|r| r.iter().filter_map(Json::as_str).collect())
+            .unwrap_or_default();
+
+        // `null` usually means "not given" from a model; drop it unless it is meaningful.
+        if self.coerce {
+            obj.retain(|key, value| {
+                !value.is_null()
+                    || required.contains(&key.as_str())
+                    || props
+                        .get(key)
+                        .is_some_and(|p| self.allows_null(p, depth + 1))
+            });
+        }
+
+        for key in &required {
+            if !obj.contains_key(*key) {
+                errors.push(ArgumentError {
+                    path: format!("{path}/{key}"),
+                    message: "missing required property".into(),
+                });
+            }
+        }
+        for (key, value) in obj.iter_mut() {
+            let child = format!("{path}/{key}");
+            match (props.get(key), schema.get("additionalProperties")) {
+                (Some(prop), _) => self.check(prop, value, &child, depth + 1, errors),
+                (None, Some(Json::Bool(false))) => errors.push(ArgumentError {
+                    path: child,
+                    message: "unexpected property".into(),
+                }),
+                (None, Some(extra @ Json::Object(_))) => {
+                    self.check(extra, value, &child, depth + 1, errors)
+                }
+                (None, _) => {}
+            }
+        }
+    }
+
+    fn allows_null(&self, schema: &Json, depth: usize) -> bool {
+        let mut errors = vec![];
+        self.check(schema, &mut Json::Null, "", depth, &mut errors);
+        errors.is_empty()
+    }
+}
+
+fn has_type(v: &Json, t: &str) -> bool {
+    match t {
+        "integer" => v.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => v.is_number(),
+        other => type_name(v) == other,
+    }
+}
+
+fn type_name(v: &Json) -> &'static str {
+    match v {
+        Json::Null => "null",
+        Json::Bool(_) => "boolean",
+        Json::Number(n) if n.is_f64() => "number",
+        Json::Number(_) => "integer",
+        Json::String(_) => "string",
+        Json::Array(_) => "array",
+        Json::Object(_) => "object",
+    }
+}
+
+/// `v` converted to type `t`, for strings that unambiguously encode such a value.
+fn coerce(v: &Json, t: &str) -> Option<Json> {
+    let s = v.as_str()?.trim();
+    let parsed = match t {
+        "integer" | "number" => serde_json::from_str::<serde_json::Number>(s)
+            .ok()
+            .map(Json::Number),
+        "boolean" => match s {
+            "true" => Some(Json::Bool(true)),
+            "false" => Some(Json::Bool(false)),
+            _ => None,
+        },
+        "object" | "array" => serde_json::from_str(s).ok(),
+        _ => None,
+    }?;
+    has_type(&parsed, t).then_some(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn edit_schema() -> Json {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {"type": "string", "minLength": 1},
+                "limit": {"type": "integer", "minimum": 1},
+                "dry_run": {"type": "boolean"},
+                "edits": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {"find": {"type": "string"}, "replace": {"type": "string"}},
+                        "required": ["find", "replace"],
+                        "additionalProperties": false
+                    }
+                },
+                "mode": {"enum": ["fast", "safe"]}
+            },
+            "required": ["path"],
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn violations_are_reported_with_their_paths() {
+        let errs = validate_arguments(
+            &edit_schema(),
+            json!({
+                "limit": 0,
+                "edits": [{"find": 1, "replace": "y", "extra": true}],
+                "mode": "yolo",
+                "verbose": true
+            }),
+        )
+        .unwrap_err();
+        let got: Vec<_> = errs.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            got,
+            [
+                "/path: missing required property",
+                "/edits/0/extra: unexpected property",
+                "/edits/0/find: expected string, got integer",
+                "/limit: must be >= 1",
+                "/mode: must be one of [\"fast\",\"safe\"]",
+                "/verbose: unexpected property",
+            ]
+        );
+    }
+
+    #[test]
+    fn common_mistakes_are_coerced() {
+        let args = validate_arguments(
+            &edit_schema(),
+            json!({
+                "path": "a.txt",
+                "limit": " 20",
+                "dry_run": "false",
+                "edits": "[{\"find\":\"x\",\"replace\":\"y\"}]",
+                "mode": null
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            json!({
+                "path": "a.txt",
+                "limit": 20,
+                "dry_run": false,
+                "edits": [{"find": "x", "replace": "y"}]
+            })
+        );
+
+        let errs = validate_arguments(&edit_schema(), json!({"path": "a", "limit": "2.5"}));
+        assert_eq!(
+            errs.unwrap_err()[0].to_string(),
+            "/limit: expected integer, got string"
+        );
+    }
+
+    #[test]
+    fn values_are_checked_strictly_through_refs() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"edit": {"$ref": "#/definitions/Edit"}},
+            "definitions": {
+                "Edit": {"type": "object", "properties": {"limit": {"type": "integer"}}}
+            }
+        });
+        assert!(validate_value(&schema, &json!({"edit": {"limit": 2}})).is_ok());
+        let errs = validate_value(&schema, &json!({"edit": {"limit": "2"}})).unwrap_err();
+        assert_eq!(
+            errs[0].to_string(),
+            "/edit/limit: expected integer, got string"
+        );
+
+        let cyclic = json!({"$ref": "#/definitions/A", "definitions": {"A": {"$ref": "#"}}});
+        let errs = validate_value(&cyclic, &json!(1)).unwrap_err();
+        assert!(errs[0].message.contains("cyclic"), "{}", errs[0]);
+        let dangling = json!({"$ref": "#/definitions/Missing"});
+        assert!(validate_value(&dangling, &json!(1)).is_err());
+    }
+}
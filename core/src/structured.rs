@@ -0,0 +1,279 @@
+//! Typed JSON completions: the reply is constrained by a type's JSON Schema, validated, and
+//! deserialized, with the validation errors fed back to the model when it misses.
+
+use crate::{validate_value, AiClient, AiContext, CompletionOptions};
+use pi_contracts::{
+    ChatMessage, ChatRequest, CostBreakdown, Model, PiError, ResponseFormat, TokenUsage,
+};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::Value as Json;
+
+/// Requests [`AiClient::complete_json`] makes before giving up on a reply that doesn't fit.
+pub const JSON_ATTEMPTS: usize = 3;
+
+/// A [`AiClient::complete_json`] value, with the usage and cost of every request it took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonCompletion<T> {
+    pub value: T,
+    pub usage: TokenUsage,
+    pub cost: CostBreakdown,
+}
+
+impl AiClient {
+    /// Completes `ctx` into a `T`, sending `T`'s JSON Schema as the response format.
+    ///
+    /// A reply that isn't valid JSON, breaks the schema or doesn't deserialize is answered with
+    /// the errors and asked again, up to [`JSON_ATTEMPTS`] requests in all. The schema is sent
+    /// non-strict: schemars output rarely fits OpenAI's strict subset, and the local check
+    /// ([`validate_value`], which resolves the schema's `$ref`s and coerces nothing) covers the
+    /// difference.
+    pub async fn complete_json<T: JsonSchema + DeserializeOwned>(
+        &self,
+        model: &Model,
+        ctx: &AiContext,
+        opts: CompletionOptions,
+    ) -> Result<JsonCompletion<T>, PiError> {
+        let name = schema_name::<T>();
+        let schema = root_schema::<T>()?;
+        let mut messages = ctx.messages.clone();
+        let mut last_error = String::new();
+        let (mut usage, mut cost) = (TokenUsage::default(), CostBreakdown::default());
+        for _ in 0..JSON_ATTEMPTS {
+            let req = ChatRequest {
+                response_format: Some(ResponseFormat::JsonSchema {
+                    name: name.clone(),
+                    schema: schema.clone(),
+                    strict: false,
+                }),
+                ..opts.clone().request(model, messages.clone(), vec![])
+            };
+            let resp = self.send(model, req).await?;
+            usage += resp.usage.as_ref().unwrap_or(&TokenUsage::default());
+            cost += resp.cost.as_ref().unwrap_or(&CostBreakdown::default());
+            let reply = match &resp.assistant {
+                ChatMessage::Assistant { content, .. } => content.as_str(),
+                _ => "",
+            };
+            match parse_reply::<T>(&schema, reply) {
+                Ok(value) => return Ok(JsonCompletion { value, usage, cost }),
+                Err(e) => {
+                    messages.push(resp.assistant);
+                    messages.push(ChatMessage::user(format!(
+                        "That reply does not match the `{name}` schema: {e}\n\
+                         Answer again with only the corrected JSON."
+                    )));
+                    last_error = e;
+                }
+            }
+        }
+        Err(PiError::Provider(format!(
+            "no reply matched the `{name}` schema in {JSON_ATTEMPTS} attempts: {last_error}"
+        )))
+    }
+}
+
+/// `T`'s schema name, restricted to the characters providers accept (`[A-Za-z0-9_-]`).
+fn schema_name<T: JsonSchema>() -> String {
+    T::schema_name()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn root_schema<T: JsonSchema>() -> Result<Json, PiError> {
+    let mut schema = serde_json::to_value(schemars::schema_for!(T))?;
+    if let Json::Object(map) = &mut schema {
+        map.remove("$schema");
+    }
+    Ok(schema)
+}
+
+/// Parses, validates and deserializes `reply`; the error is phrased for the model.
+fn parse_reply<T: DeserializeOwned>(schema: &Json, reply: &str) -> Result<T, String> {
+    let value: Json =
+        serde_json::from_str(reply.trim()).map_err(|e| format!("not valid JSON ({e})"))?;
+    validate_value(schema, &value).map_err(|errors| {
+        errors
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("; ")
+    })?;
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AiProvider, ChatProvider, ChatProviderStream, ChatStream, ModelCatalog, ProviderHub,
+    };
+    use async_trait::async_trait;
+    use pi_contracts::{ApiKind, ChatResponse, InputModality, NonEmptyString, TokenCost};
+    use serde::Deserialize;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, PartialEq, Deserialize, JsonSchema)]
+    struct Verdict {
+        approved: bool,
+        reasons: Vec<String>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, JsonSchema)]
+    struct Review {
+        verdict: Verdict,
+        reviewer: Option<String>,
+    }
+
+    /// Replies with queued texts and records every request.
+    #[derive(Clone, Default)]
+    struct Scripted {
+        replies: Arc<Mutex<Vec<&'static str>>>,
+        requests: Arc<Mutex<Vec<ChatRequest>>>,
+    }
+
+    #[async_trait]
+    impl ChatProvider for Scripted {
+        async fn chat(&self, req: ChatRequest) -> Result<ChatResponse, PiError> {
+            self.requests.lock().unwrap().push(req);
+            let text = self.replies.lock().unwrap().remove(0);
+            Ok(ChatResponse {
+                assistant: ChatMessage::assistant(text, vec![]),
+                usage: Some(TokenUsage::new(2, 1, 3)),
+                cost: None,
+                finish_reason: None,
+            })
+        }
+    }
+
+    #[async_trait]
+    impl ChatProviderStream for Scripted {
+        async fn chat_stream(&self, _req: ChatRequest) -> Result<ChatStream, PiError> {
+            Err(PiError::Provider("not streamed".into()))
+        }
+    }
+
+    fn client(replies: Vec<&'static str>) -> (AiClient, Model, Scripted) {
+        let model = Model::new(
+            NonEmptyString::new("scripted").unwrap(),
+            NonEmptyString::new("m").unwrap(),
+            ApiKind::OpenAiCompletions,
+            "scripted",
+            TokenCost {
+                input: 1_000_000.0,
+                output: 1_000_000.0,
+                ..TokenCost::default()
+            },
+            1000,
+            100,
+            vec![InputModality::Text],
+            false,
+            None,
+        );
+        let provider = Scripted {
+            replies: Arc::new(Mutex::new(replies)),
+            ..Scripted::default()
+        };
+        let mut providers = ProviderHub::new();
+        providers.insert(
+            model.provider.clone(),
+            Arc::new(provider.clone()) as Arc<dyn AiProvider>,
+        );
+        let ai = AiClient::new(ModelCatalog::new([model.clone()]), providers);
+        (ai, model, provider)
+    }
+
+    #[tokio::test]
+    async fn invalid_replies_are_sent_back_with_the_errors() {
+        let (ai, model, provider) = client(vec![
+            "not json",
+            r#"{"approved": "yes", "reasons": []}"#,
+            r#"{"approved": true, "reasons": ["tests pass"]}"#,
+        ]);
+        let ctx = AiContext {
+            messages: vec![ChatMessage::user("review this")],
+        };
+
+        let out: JsonCompletion<Verdict> = ai
+            .complete_json(&model, &ctx, CompletionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            out.value,
+            Verdict {
+                approved: true,
+                reasons: vec!["tests pass".into()],
+            }
+        );
+        // All three requests are accounted for.
+        assert_eq!(out.usage, TokenUsage::new(6, 3, 9));
+        assert!((out.cost.total - 9.0).abs() < 1e-9);
+
+        let requests = provider.requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 3);
+        let Some(ResponseFormat::JsonSchema { name, schema, .. }) = &requests[0].response_format
+        else {
+            panic!("expected a json schema response format");
+        };
+        assert_eq!(name, "Verdict");
+        assert_eq!(
+            schema["required"],
+            serde_json::json!(["approved", "reasons"])
+        );
+        assert!(schema.get("$schema").is_none());
+
+        let retry = requests[2].messages.last().unwrap();
+        let ChatMessage::User { content, .. } = retry else {
+            panic!("expected the validation error as a user message");
+        };
+        assert!(content.contains("/approved"), "{content}");
+    }
+
+    #[tokio::test]
+    async fn nested_types_are_checked_through_refs_without_coercion() {
+        let (ai, model, provider) = client(vec![
+            r#"{"verdict": {"approved": "true", "reasons": []}, "reviewer": null}"#,
+            r#"{"verdict": {"approved": true, "reasons": []}, "reviewer": null}"#,
+        ]);
+        let ctx = AiContext {
+            messages: vec![ChatMessage::user("review this")],
+        };
+
+        let out: JsonCompletion<Review> = ai
+            .complete_json(&model, &ctx, CompletionOptions::default())
+            .await
+            .unwrap();
+        assert!(out.value.verdict.approved);
+        assert_eq!(out.value.reviewer, None);
+
+        let requests = provider.requests.lock().unwrap().clone();
+        let ChatMessage::User { content, .. } = requests[1].messages.last().unwrap() else {
+            panic!("expected the validation error as a user message");
+        };
+        assert!(
+            content.contains("/verdict/approved: expected boolean, got string"),
+            "{content}"
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_attempt() {
+        let (ai, model, _) = client(vec!["[]"; JSON_ATTEMPTS]);
+        let ctx = AiContext {
+            messages: vec![ChatMessage::user("review this")],
+        };
+
+        let err = ai
+            .complete_json::<Verdict>(&model, &ctx, CompletionOptions::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("in 3 attempts"), "{err}");
+    }
+}
@@ -0,0 +1,258 @@
+//! Delegation to a nested agent.
+//!
+//! [`SubAgentTool`] runs a fresh [`Agent`] on a task and returns its final answer, so searches or
+//! reviews don't fill the parent's transcript. The nested run's usage is added to the parent run
+//! and its events are re-emitted as [`AgentEvent::SubAgent`].
+
+use crate::{
+    Agent, AgentConfig, AgentEvents, ChatProvider, Tool, ToolContext, ToolFilter, ToolResult,
+    ToolSet,
+};
+use async_trait::async_trait;
+use pi_contracts::{AgentEvent, ChatMessage, PiError, ToolName, ToolSpec};
+use serde_json::Value as Json;
+use std::sync::Arc;
+
+/// A tool that hands `{"task": "..."}` to a nested agent with its own model, system prompt,
+/// tools and step/budget limits (all taken from its [`AgentConfig`]).
+///
+/// The nested agent has no approver, so tool calls its policy marks as `Ask` are denied.
+pub struct SubAgentTool {
+    name: ToolName,
+    description: String,
+    provider: Arc<dyn ChatProvider>,
+    tools: ToolSet,
+    cfg: AgentConfig,
+    events: AgentEvents,
+}
+
+impl SubAgentTool {
+    pub fn new(
+        name: ToolName,
+        description: impl Into<String>,
+        provider: Arc<dyn ChatProvider>,
+        tools: ToolSet,
+        cfg: AgentConfig,
+    ) -> Self {
+        Self {
+            name,
+            description: description.into(),
+            provider,
+            tools,
+            cfg,
+            events: AgentEvents::new(),
+        }
+    }
+
+    /// Re-emits nested events to `events`; pass the parent agent's so subscribers see them.
+    pub fn with_events(mut self, events: AgentEvents) -> Self {
+        self.events = events;
+        self
+    }
+}
+
+#[async_trait]
+impl Tool for SubAgentTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "task": {
+                        "type": "string",
+                        "description": "What to do, with all the context needed; the sub-agent does not see this conversation."
+                    }
+                },
+                "required": ["task"]
+            }),
+        }
+    }
+
+    async fn execute(&self, args: Json, ctx: ToolContext) -> Result<ToolResult, PiError> {
+        let task = args
+            .get("task")
+            .and_then(|v| v.as_str())
+            .filter(|t| !t.trim().is_empty())
+            .ok_or_else(|| PiError::Tool("`task` must be a non-empty string".into()))?;
+
+        let events = AgentEvents::new();
+        let (parent, agent) = (self.events.clone(), self.name.to_string());
+        events.on_event(move |ev| {
+            parent.emit(AgentEvent::SubAgent {
+                agent: agent.clone(),
+                event: Box::new(ev.clone()),
+            })
+        });
+        let sub = Agent::new(self.provider.clone(), self.tools.clone(), self.cfg.clone())
+            .with_events(events);
+
+        // The nested agent offers all of its own tools, whatever the parent run filters.
+        let ctx = ToolContext {
+            tools: ToolFilter::all(),
+            ..ctx
+        };
+        let mut transcript = vec![];
+        let (r, summary) = sub.run_tracked(&mut transcript, task.into(), ctx).await;
+        let answer = transcript
+            .iter()
+            .rev()
+            .find_map(|e| match &e.message {
+                ChatMessage::Assistant { content, .. } if !content.is_empty() => {
+                    Some(content.as_str())
+                }
+                _ => None,
+            })
+            .unwrap_or_default();
+        // A nested run that stops early still reports what it found (and what it cost); only an
+        // abort is passed up as an error.
+        let result = match r.and_then(|s| s.stop_error().map_or(Ok(()), Err)) {
+            Ok(()) if summary.truncated => ToolResult::text(format!(
+                "sub-agent reached its step limit. Partial answer:\n{answer}"
+            )),
+            Ok(()) => ToolResult::text(answer),
+            Err(PiError::Aborted) => return Err(PiError::Aborted),
+            Err(e) => ToolResult::error(format!(
+                "sub-agent stopped early ({e}). Partial answer:\n{answer}"
+            )),
+        };
+        Ok(ToolResult {
+            usage: Some(summary.usage),
+            cost: Some(summary.cost),
+            ..result
+        })
+    }
+
+    fn parallel_safe(&self) -> bool {
+        self.tools.tools().iter().all(|t| t.parallel_safe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pi_contracts::{
+        ChatRequest, ChatResponse, FinishReason, NonEmptyString, TokenUsage, ToolCall,
+    };
+    use std::sync::Mutex;
+
+    /// Answers with scripted messages (and finish reasons, while there are any) in order; every
+    /// response costs 10 tokens.
+    struct Scripted {
+        replies: Mutex<Vec<ChatMessage>>,
+        finish: Mutex<Vec<FinishReason>>,
+    }
+
+    impl Scripted {
+        fn new(replies: Vec<ChatMessage>) -> Self {
+            Self {
+                replies: Mutex::new(replies),
+                finish: Mutex::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ChatProvider for Scripted {
+        async fn chat(&self, _req: ChatRequest) -> Result<ChatResponse, PiError> {
+            let mut finish = self.finish.lock().unwrap();
+            Ok(ChatResponse {
+                assistant: self.replies.lock().unwrap().remove(0),
+                usage: Some(TokenUsage::new(7, 3, 10)),
+                cost: None,
+                finish_reason: (!finish.is_empty()).then(|| finish.remove(0)),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn delegates_and_rolls_up_usage_and_events() {
+        let model = NonEmptyString::new("gpt-test").unwrap();
+        let provider: Arc<dyn ChatProvider> = Arc::new(Scripted::new(vec![
+            ChatMessage::assistant(
+                "",
+                vec![ToolCall {
+                    id: NonEmptyString::new("call_1").unwrap(),
+                    name: NonEmptyString::new("research").unwrap(),
+                    arguments: serde_json::json!({"task": "find the bug"}),
+                }],
+            ),
+            ChatMessage::assistant("it is in parse()", vec![]),
+            ChatMessage::assistant("fixed", vec![]),
+        ]));
+
+        let events = AgentEvents::new();
+        let research = SubAgentTool::new(
+            NonEmptyString::new("research").unwrap(),
+            "Investigates a question",
+            provider.clone(),
+            ToolSet::default(),
+            AgentConfig {
+                system_prompt: Some("You research.".into()),
+                max_steps: 4,
+                ..AgentConfig::minimal(model.clone())
+            },
+        )
+        .with_events(events.clone());
+        let agent = Agent::new(
+            provider,
+            ToolSet::new([Arc::new(research) as Arc<dyn Tool>]).unwrap(),
+            AgentConfig::minimal(model),
+        )
+        .with_events(events);
+        let mut rx = agent.subscribe();
+
+        let mut tr = vec![];
+        agent
+            .run_to_end(&mut tr, "go", ToolContext::new("."))
+            .await
+            .unwrap();
+        assert_eq!(
+            tr[2].message,
+            ChatMessage::tool(NonEmptyString::new("call_1").unwrap(), "it is in parse()")
+        );
+        assert_eq!(tr[3].message, ChatMessage::assistant("fixed", vec![]));
+
+        let evs: Vec<_> = std::iter::from_fn(|| rx.try_next().ok().flatten()).collect();
+        assert!(evs.iter().any(|e| matches!(
+            e,
+            AgentEvent::SubAgent { agent, event }
+                if agent == "research" && matches!(**event, AgentEvent::RunEnd { .. })
+        )));
+        // Two parent responses plus the nested one.
+        assert!(matches!(
+            evs.last(),
+            Some(AgentEvent::RunEnd { summary, error: None }) if summary.budget.tokens == 30
+        ));
+    }
+
+    #[tokio::test]
+    async fn answers_include_continuations_of_cut_off_replies() {
+        let provider = Scripted::new(vec![
+            ChatMessage::assistant("The answer", vec![]),
+            ChatMessage::assistant(" is 42.", vec![]),
+        ]);
+        provider
+            .finish
+            .lock()
+            .unwrap()
+            .extend([FinishReason::Length, FinishReason::Stop]);
+        let tool = SubAgentTool::new(
+            NonEmptyString::new("ask").unwrap(),
+            "Answers questions",
+            Arc::new(provider),
+            ToolSet::default(),
+            AgentConfig::minimal(NonEmptyString::new("gpt-test").unwrap()),
+        );
+
+        let out = tool
+            .execute(
+                serde_json::json!({"task": "what is it?"}),
+                ToolContext::new("."),
+            )
+            .await
+            .unwrap();
+        assert_eq!(out.content, "The answer is 42.");
+    }
+}
@@ -0,0 +1,400 @@
+//! Local token counting.
+//!
+//! [`BpeTokenizer`] reproduces OpenAI's tiktoken encodings from a `.tiktoken` vocab (loaded by an
+//! adapter; core does no I/O). Other model families get a [`HeuristicCounter`]. [`Tokenizers`]
+//! picks one per model based on `Model.api`.
+
+use base64::Engine as _;
+use fancy_regex::Regex;
+use pi_contracts::{ApiKind, ChatMessage, ChatRequest, ContentPart, Model, PiError, ToolSpec};
+use serde_json::Value as Json;
+use std::{collections::HashMap, sync::Arc};
+
+/// Fixed cost of every chat message (role + delimiters), per OpenAI's published accounting.
+const TOKENS_PER_MESSAGE: usize = 3;
+/// Every reply is primed with `<|start|>assistant<|message|>`.
+const TOKENS_PER_REPLY: usize = 3;
+/// Approximate framing around each tool call and tool definition.
+const TOKENS_PER_TOOL: usize = 8;
+/// Images are billed by size; this is OpenAI's cost for a 1024x1024 image at high detail.
+const TOKENS_PER_IMAGE: usize = 765;
+/// Audio runs about 10 tokens a second; this assumes 16 kHz 16-bit mono WAV (32 KB/s).
+const AUDIO_BYTES_PER_TOKEN: usize = 3200;
+
+/// Counts tokens. Only [`TokenCounter::count_text`] is required; the structured helpers add the
+/// chat-format overheads on top.
+pub trait TokenCounter: Send + Sync {
+    fn count_text(&self, text: &str) -> usize;
+
+    /// Tokens used by tool-call arguments as serialized on the wire.
+    fn count_arguments(&self, args: &Json) -> usize {
+        self.count_text(&args.to_string())
+    }
+
+    /// Tokens used by multi-part content.
+    fn count_parts(&self, parts: &[ContentPart]) -> usize {
+        parts
+            .iter()
+            .map(|p| match p {
+                ContentPart::Text { text } => self.count_text(text),
+                ContentPart::Image { .. } => TOKENS_PER_IMAGE,
+                // Base64 is 4/3 of the raw size.
+                ContentPart::Audio { data, .. } => {
+                    (data.len() * 3 / 4 / AUDIO_BYTES_PER_TOKEN).max(1)
+                }
+            })
+            .sum()
+    }
+
+    fn count_tool_specs(&self, tools: &[ToolSpec]) -> usize {
+        tools
+            .iter()
+            .map(|t| {
+                TOKENS_PER_TOOL
+                    + self.count_text(t.name.as_str())
+                    + self.count_text(&t.description)
+                    + self.count_arguments(&t.parameters)
+            })
+            .sum()
+    }
+
+    fn count_message(&self, message: &ChatMessage) -> usize {
+        let body = match message {
+            ChatMessage::System { content } => self.count_text(content),
+            ChatMessage::User { content, parts } => {
+                self.count_text(content) + self.count_parts(parts)
+            }
+            ChatMessage::Assistant {
+                content,
+                tool_calls,
+                ..
+            } => {
+                self.count_text(content)
+                    + tool_calls
+                        .iter()
+                        .map(|tc| {
+                            TOKENS_PER_TOOL
+                                + self.count_text(tc.name.as_str())
+                                + self.count_arguments(&tc.arguments)
+                        })
+                        .sum::<usize>()
+            }
+            ChatMessage::Tool { content, parts, .. } => {
+                self.count_text(content) + self.count_parts(parts)
+            }
+            ChatMessage::Compaction { summary, .. } => self.count_text(summary),
+        };
+        TOKENS_PER_MESSAGE + body
+    }
+
+    /// Prompt tokens for a conversation, including reply priming.
+    fn count_messages(&self, messages: &[ChatMessage]) -> usize {
+        TOKENS_PER_REPLY
+            + messages
+                .iter()
+                .map(|m| self.count_message(m))
+                .sum::<usize>()
+    }
+
+    /// Prompt tokens for a whole request (messages + tool definitions).
+    fn count_request(&self, req: &ChatRequest) -> usize {
+        self.count_messages(&req.messages) + self.count_tool_specs(&req.tools)
+    }
+}
+
+/// Character-based estimate for models without a local vocab.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeuristicCounter {
+    pub chars_per_token: f32,
+}
+
+impl Default for HeuristicCounter {
+    fn default() -> Self {
+        Self {
+            chars_per_token: 4.0,
+        }
+    }
+}
+
+impl HeuristicCounter {
+    /// Typical ratio for English text and code in each API family's tokenizer.
+    pub fn for_api(api: ApiKind) -> Self {
+        let chars_per_token = match api {
+            ApiKind::OpenAiCompletions | ApiKind::OpenAiResponses => 4.0,
+            ApiKind::AnthropicMessages => 3.5,
+            ApiKind::GoogleGenerativeAi => 4.0,
+        };
+        Self { chars_per_token }
+    }
+}
+
+impl TokenCounter for HeuristicCounter {
+    fn count_text(&self, text: &str) -> usize {
+        (text.chars().count() as f32 / self.chars_per_token).ceil() as usize
+    }
+}
+
+/// OpenAI BPE encodings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BpeEncoding {
+    /// GPT-4, GPT-3.5.
+    Cl100kBase,
+    /// GPT-4o, GPT-4.1, GPT-5, o-series.
+    O200kBase,
+}
+
+impl BpeEncoding {
+    pub const ALL: [BpeEncoding; 2] = [BpeEncoding::Cl100kBase, BpeEncoding::O200kBase];
+
+    /// Encoding name; vocab files are named `<name>.tiktoken`.
+    pub fn name(self) -> &'static str {
+        match self {
+            BpeEncoding::Cl100kBase => "cl100k_base",
+            BpeEncoding::O200kBase => "o200k_base",
+        }
+    }
+
+    /// Encoding used by an OpenAI model id; `None` for other APIs and unknown (e.g. local) models.
+    pub fn for_model(api: ApiKind, model_id: &str) -> Option<Self> {
+        if !matches!(api, ApiKind::OpenAiCompletions | ApiKind::OpenAiResponses) {
+            return None;
+        }
+        const O200K: [&str; 5] = ["gpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "chatgpt-4o"];
+        const CL100K: [&str; 2] = ["gpt-4", "gpt-3.5"];
+        let is_o_series = |id: &str| {
+            id.strip_prefix('o')
+                .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+        };
+        if O200K.iter().any(|p| model_id.starts_with(p)) || is_o_series(model_id) {
+            Some(BpeEncoding::O200kBase)
+        } else if CL100K.iter().any(|p| model_id.starts_with(p)) {
+            Some(BpeEncoding::Cl100kBase)
+        } else {
+            None
+        }
+    }
+
+    fn pattern(self) -> &'static str {
+        match self {
+            BpeEncoding::Cl100kBase => {
+                r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+"
+            }
+            BpeEncoding::O200kBase => concat!(
+                r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+                r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+                r"|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+(?!\S)|\s+",
+            ),
+        }
+    }
+}
+
+/// Byte-pair encoder compatible with tiktoken. Special tokens are encoded as ordinary text.
+pub struct BpeTokenizer {
+    encoding: BpeEncoding,
+    ranks: HashMap<Vec<u8>, u32>,
+    pattern: Regex,
+}
+
+impl BpeTokenizer {
+    /// Parses a `.tiktoken` vocab: one `<base64 token> <rank>` pair per line.
+    pub fn from_tiktoken(encoding: BpeEncoding, vocab: &str) -> Result<Self, PiError> {
+        let invalid = |line: usize, why: &str| {
+            PiError::Invalid(format!(
+                "{} vocab line {}: {why}",
+                encoding.name(),
+                line + 1
+            ))
+        };
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let mut ranks = HashMap::new();
+        for (n, line) in vocab.lines().enumerate().filter(|(_, l)| !l.is_empty()) {
+            let (token, rank) = line
+                .split_once(' ')
+                .ok_or_else(|| invalid(n, "expected `<token> <rank>`"))?;
+            let token = b64
+                .decode(token)
+                .map_err(|_| invalid(n, "token is not base64"))?;
+            let rank = rank
+                .parse()
+                .map_err(|_| invalid(n, "rank is not a number"))?;
+            ranks.insert(token, rank);
+        }
+        // Every byte must be encodable on its own, or `encode_piece` would hit a missing part.
+        if let Some(b) = (0..=255u8).find(|b| !ranks.contains_key(&[*b][..])) {
+            return Err(PiError::Invalid(format!(
+                "{} vocab has no token for byte {b:#04x}",
+                encoding.name()
+            )));
+        }
+        let pattern = Regex::new(encoding.pattern())
+            .map_err(|e| PiError::Invalid(format!("tokenizer pattern: {e}")))?;
+        Ok(Self {
+            encoding,
+            ranks,
+            pattern,
+        })
+    }
+
+    pub fn encoding(&self) -> BpeEncoding {
+        self.encoding
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        let mut out = vec![];
+        for piece in self.pattern.find_iter(text) {
+            // The patterns are simple enough that matching cannot hit fancy-regex's backtrack limit.
+            let Ok(piece) = piece else { break };
+            self.encode_piece(piece.as_str().as_bytes(), &mut out);
+        }
+        out
+    }
+
+    /// Merges adjacent parts lowest-rank-first until no pair is in the vocab.
+    fn encode_piece(&self, piece: &[u8], out: &mut Vec<u32>) {
+        if let Some(&rank) = self.ranks.get(piece) {
+            out.push(rank);
+            return;
+        }
+        let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let best = (0..bounds.len().saturating_sub(2))
+                .filter_map(|i| {
+                    self.ranks
+                        .get(&piece[bounds[i]..bounds[i + 2]])
+                        .map(|&r| (r, i))
+                })
+                .min();
+            let Some((_, i)) = best else { break };
+            bounds.remove(i + 1);
+        }
+        out.extend(bounds.windows(2).map(|w| self.ranks[&piece[w[0]..w[1]]]));
+    }
+}
+
+impl TokenCounter for BpeTokenizer {
+    fn count_text(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+}
+
+impl std::fmt::Debug for BpeTokenizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BpeTokenizer")
+            .field("encoding", &self.encoding)
+            .field("vocab_size", &self.ranks.len())
+            .finish()
+    }
+}
+
+/// Loaded BPE vocabs; hands out the best available counter per model.
+#[derive(Clone, Debug, Default)]
+pub struct Tokenizers {
+    bpe: HashMap<BpeEncoding, Arc<BpeTokenizer>>,
+}
+
+impl Tokenizers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, tokenizer: BpeTokenizer) {
+        self.bpe.insert(tokenizer.encoding(), Arc::new(tokenizer));
+    }
+
+    /// Exact BPE counting if the model's encoding is loaded, otherwise the API family's heuristic.
+    pub fn counter(&self, api: ApiKind, model_id: &str) -> Arc<dyn TokenCounter> {
+        match BpeEncoding::for_model(api, model_id).and_then(|e| self.bpe.get(&e)) {
+            Some(bpe) => bpe.clone(),
+            None => Arc::new(HeuristicCounter::for_api(api)),
+        }
+    }
+
+    pub fn for_model(&self, model: &Model) -> Arc<dyn TokenCounter> {
+        self.counter(model.api, model.id.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pi_contracts::{NonEmptyString, ToolCall};
+
+    /// Every single byte, then a few merges: "he", "ll", "hell", "hello", " world".
+    fn tiny_vocab() -> String {
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let mut tokens: Vec<Vec<u8>> = (0..=255u8).map(|b| vec![b]).collect();
+        for t in [
+            "he", "ll", "hell", "hello", " w", " wo", "rl", " worl", " world",
+        ] {
+            tokens.push(t.as_bytes().to_vec());
+        }
+        tokens
+            .iter()
+            .enumerate()
+            .map(|(rank, t)| format!("{} {rank}\n", b64.encode(t)))
+            .collect()
+    }
+
+    #[test]
+    fn bpe_merges_by_rank_and_splits_on_the_pattern() {
+        let bpe = BpeTokenizer::from_tiktoken(BpeEncoding::Cl100kBase, &tiny_vocab()).unwrap();
+        // Both pieces ("hello", " world") are whole-vocab hits.
+        assert_eq!(bpe.encode("hello world"), vec![259, 264]);
+        // "help" -> "hel" is not in the vocab, so the best merge is "he" + "l" + "p".
+        assert_eq!(bpe.encode("help"), vec![256, b'l' as u32, b'p' as u32]);
+        assert_eq!(bpe.count_text("hello hello"), 3);
+        let o200k = BpeTokenizer::from_tiktoken(BpeEncoding::O200kBase, &tiny_vocab()).unwrap();
+        assert_eq!(o200k.encode("hello world"), vec![259, 264]);
+
+        let err = BpeTokenizer::from_tiktoken(BpeEncoding::Cl100kBase, "aGk= 0\n").unwrap_err();
+        assert!(err.to_string().contains("no token for byte"));
+    }
+
+    #[test]
+    fn counts_structured_messages_and_picks_counter_by_api() {
+        let h = HeuristicCounter::default();
+        let call = ToolCall {
+            id: NonEmptyString::new("c1").unwrap(),
+            name: NonEmptyString::new("read").unwrap(),
+            arguments: serde_json::json!({"path": "a.rs"}),
+        };
+        assert_eq!(h.count_arguments(&call.arguments), 4);
+        assert_eq!(
+            h.count_messages(&[ChatMessage::user("abcdefgh")]),
+            TOKENS_PER_REPLY + TOKENS_PER_MESSAGE + 2
+        );
+        assert_eq!(
+            h.count_message(&ChatMessage::assistant("", vec![call])),
+            TOKENS_PER_MESSAGE + TOKENS_PER_TOOL + 1 + 4
+        );
+
+        assert_eq!(
+            BpeEncoding::for_model(ApiKind::OpenAiCompletions, "gpt-4o-mini"),
+            Some(BpeEncoding::O200kBase)
+        );
+        assert_eq!(
+            BpeEncoding::for_model(ApiKind::OpenAiResponses, "o3-mini"),
+            Some(BpeEncoding::O200kBase)
+        );
+        assert_eq!(
+            BpeEncoding::for_model(ApiKind::OpenAiCompletions, "gpt-4-turbo"),
+            Some(BpeEncoding::Cl100kBase)
+        );
+        assert_eq!(
+            BpeEncoding::for_model(ApiKind::OpenAiCompletions, "llama3"),
+            None
+        );
+        assert_eq!(
+            BpeEncoding::for_model(ApiKind::AnthropicMessages, "gpt-4o"),
+            None
+        );
+
+        let mut tokenizers = Tokenizers::new();
+        let heuristic = tokenizers.counter(ApiKind::OpenAiCompletions, "gpt-4");
+        assert_eq!(heuristic.count_text("hello world"), 3);
+        tokenizers
+            .insert(BpeTokenizer::from_tiktoken(BpeEncoding::Cl100kBase, &tiny_vocab()).unwrap());
+        let exact = tokenizers.counter(ApiKind::OpenAiCompletions, "gpt-4");
+        assert_eq!(exact.count_text("hello world"), 2);
+    }
+}
@@ -0,0 +1,274 @@
+//! Tool registry.
+//!
+//! [`ToolSet`] indexes tools by the name the model sees. Names are unique; tools from an external
+//! origin (an MCP server, an extension) can be registered under a namespace (`github__search`)
+//! and removed together when that origin goes away.
+
+use crate::{permissions::glob_match, Tool};
+use pi_contracts::{NonEmptyString, PiError, ToolName, ToolSpec};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+/// Separator between a namespace and a tool name. Provider tool names only allow
+/// `[A-Za-z0-9_-]`, so it can't be `.` or `/`.
+pub const NAMESPACE_SEPARATOR: &str = "__";
+
+/// Name-indexed tool registry.
+///
+/// Cheap to clone; clones share the same registry, so tools registered through
+/// [`crate::Agent::tools`] (or any clone) are offered from the agent's next request on. Specs are
+/// read once, at registration.
+#[derive(Clone, Default)]
+pub struct ToolSet {
+    inner: Arc<RwLock<Registry>>,
+}
+
+#[derive(Default)]
+struct Registry {
+    entries: Vec<Entry>,
+    index: HashMap<String, usize>,
+}
+
+struct Entry {
+    /// With the registered (possibly namespaced) name.
+    spec: ToolSpec,
+    namespace: Option<String>,
+    tool: Arc<dyn Tool>,
+}
+
+impl Registry {
+    fn reindex(&mut self) {
+        self.index = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.spec.name.as_str().to_string(), i))
+            .collect();
+    }
+}
+
+impl ToolSet {
+    /// Fails if two tools share a name.
+    pub fn new(tools: impl IntoIterator<Item = Arc<dyn Tool>>) -> Result<Self, PiError> {
+        let set = Self::default();
+        for tool in tools {
+            set.register(tool)?;
+        }
+        Ok(set)
+    }
+
+    /// Adds a tool under its own name; fails if the name is taken.
+    pub fn register(&self, tool: Arc<dyn Tool>) -> Result<ToolName, PiError> {
+        let spec = tool.spec();
+        self.insert(spec, None, tool)
+    }
+
+    /// Adds a tool as `<namespace>__<name>`; fails if that name is taken.
+    pub fn register_namespaced(
+        &self,
+        namespace: &str,
+        tool: Arc<dyn Tool>,
+    ) -> Result<ToolName, PiError> {
+        if namespace.is_empty()
+            || !namespace
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(PiError::Invalid(format!(
+                "invalid tool namespace `{namespace}` (use letters, digits, `_` and `-`)"
+            )));
+        }
+        let mut spec = tool.spec();
+        spec.name = NonEmptyString::new(format!("{namespace}{NAMESPACE_SEPARATOR}{}", spec.name))?;
+        self.insert(spec, Some(namespace.to_string()), tool)
+    }
+
+    fn insert(
+        &self,
+        spec: ToolSpec,
+        namespace: Option<String>,
+        tool: Arc<dyn Tool>,
+    ) -> Result<ToolName, PiError> {
+        let mut reg = self.inner.write().unwrap();
+        let name = spec.name.clone();
+        if reg.index.contains_key(name.as_str()) {
+            return Err(PiError::Invalid(format!("duplicate tool name `{name}`")));
+        }
+        let i = reg.entries.len();
+        reg.index.insert(name.as_str().to_string(), i);
+        reg.entries.push(Entry {
+            spec,
+            namespace,
+            tool,
+        });
+        Ok(name)
+    }
+
+    /// Removes a tool by its registered name.
+    pub fn unregister(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        let mut reg = self.inner.write().unwrap();
+        let i = reg.index.get(name).copied()?;
+        let entry = reg.entries.remove(i);
+        reg.reindex();
+        Some(entry.tool)
+    }
+
+    /// Removes every tool registered under `namespace`; returns how many there were.
+    pub fn unregister_namespace(&self, namespace: &str) -> usize {
+        let mut reg = self.inner.write().unwrap();
+        let before = reg.entries.len();
+        reg.entries
+            .retain(|e| e.namespace.as_deref() != Some(namespace));
+        reg.reindex();
+        before - reg.entries.len()
+    }
+
+    /// Specs of every tool, in registration order.
+    pub fn specs(&self) -> Vec<ToolSpec> {
+        self.specs_matching(&ToolFilter::all())
+    }
+
+    /// Specs of the tools `filter` lets through, in registration order.
+    pub fn specs_matching(&self, filter: &ToolFilter) -> Vec<ToolSpec> {
+        let reg = self.inner.read().unwrap();
+        reg.entries
+            .iter()
+            .filter(|e| filter.allows(e.spec.name.as_str()))
+            .map(|e| e.spec.clone())
+            .collect()
+    }
+
+    pub fn get(&self, name: &ToolName) -> Option<Arc<dyn Tool>> {
+        self.resolve(name).map(|(tool, _)| tool)
+    }
+
+    /// The tool and its registered spec.
+    pub(crate) fn resolve(&self, name: &ToolName) -> Option<(Arc<dyn Tool>, ToolSpec)> {
+        let reg = self.inner.read().unwrap();
+        let e = &reg.entries[*reg.index.get(name.as_str())?];
+        Some((e.tool.clone(), e.spec.clone()))
+    }
+
+    pub fn tools(&self) -> Vec<Arc<dyn Tool>> {
+        let reg = self.inner.read().unwrap();
+        reg.entries.iter().map(|e| e.tool.clone()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Which registered tools are offered to the model, by glob patterns (`*`, `?`) over registered
+/// names. A tool is offered if it matches `allow` (when set) and no `deny` pattern.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl ToolFilter {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        self.allow
+            .as_ref()
+            .is_none_or(|allow| allow.iter().any(|p| glob_match(p, name)))
+            && !self.deny.iter().any(|p| glob_match(p, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ToolContext, ToolResult};
+    use async_trait::async_trait;
+    use serde_json::Value as Json;
+
+    struct Named(&'static str);
+
+    #[async_trait]
+    impl Tool for Named {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: NonEmptyString::new(self.0).unwrap(),
+                description: self.0.into(),
+                parameters: serde_json::json!({"type":"object"}),
+            }
+        }
+
+        async fn execute(&self, _args: Json, _ctx: ToolContext) -> Result<ToolResult, PiError> {
+            Ok(ToolResult::text(self.0))
+        }
+    }
+
+    fn names(specs: Vec<ToolSpec>) -> Vec<String> {
+        specs.into_iter().map(|s| s.name.to_string()).collect()
+    }
+
+    #[test]
+    fn names_are_unique_and_namespaced_by_origin() {
+        assert!(ToolSet::new([
+            Arc::new(Named("read")) as Arc<dyn Tool>,
+            Arc::new(Named("read"))
+        ])
+        .is_err());
+
+        let set = ToolSet::new([Arc::new(Named("read")) as Arc<dyn Tool>]).unwrap();
+        let shared = set.clone();
+        shared
+            .register_namespaced("github", Arc::new(Named("search")))
+            .unwrap();
+        shared
+            .register_namespaced("github", Arc::new(Named("read")))
+            .unwrap();
+        assert!(set.register(Arc::new(Named("read"))).is_err());
+        assert!(set
+            .register_namespaced("git hub", Arc::new(Named("x")))
+            .is_err());
+        assert_eq!(
+            names(set.specs()),
+            ["read", "github__search", "github__read"]
+        );
+        assert!(set
+            .get(&NonEmptyString::new("github__search").unwrap())
+            .is_some());
+
+        assert_eq!(set.unregister_namespace("github"), 2);
+        assert!(set.unregister("read").is_some());
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn filters_pick_the_offered_tools() {
+        let set =
+            ToolSet::new(["read", "write", "bash"].map(|n| Arc::new(Named(n)) as Arc<dyn Tool>))
+                .unwrap();
+        set.register_namespaced("github", Arc::new(Named("search")))
+            .unwrap();
+        let filter = ToolFilter {
+            allow: Some(vec!["read".into(), "github__*".into()]),
+            deny: vec!["*search".into()],
+        };
+        assert_eq!(names(set.specs_matching(&filter)), ["read"]);
+        let filter = ToolFilter {
+            allow: None,
+            deny: vec!["bash".into()],
+        };
+        assert_eq!(
+            names(set.specs_matching(&filter)),
+            ["read", "write", "github__search"]
+        );
+    }
+}